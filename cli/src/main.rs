@@ -1,115 +1,136 @@
-use firefly::{BotConfig, HikariFireflyBot};
-use game::tetris::{tbp::*, GameState, Move, SevenBag};
-use tokio::io::AsyncBufReadExt;
+use std::{io, time::Duration};
+
+use firefly::{BotConfig, HikariFireflyBot, SearchLimits};
+use game::tetris::{fumen::Fumen, GameState};
+use session::Session;
+use tbp_io::{run_bot, Connection, Endpoint, Listener};
+
+mod options;
+mod session;
+
+/// How long a frontend has to complete the WebSocket handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 fn main() {
-    tokio::runtime::Builder::new_current_thread()
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (config, args) = match options::parse(&args) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}\n{}", err, options::USAGE);
+            std::process::exit(1);
+        }
+    };
+    // sessions wait for the search limits, which must not hold up other connections
+    let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
-        .unwrap()
-        .block_on(async {
-            let mut bot = None;
-            let mut last_reply_moves = vec![];
+        .unwrap();
 
-            write_message(BotMessage::Info {
-                name: "Hikari".to_owned(),
-                version: env!("CARGO_PKG_VERSION").to_owned(),
-                author: "SoRA-X7".to_owned(),
-                features: vec!["randomizer".to_owned()],
-            });
+    match args.as_slice() {
+        [cmd, fumen] if cmd == "fumen" => suggest_fumen(fumen, config),
+        [cmd] if cmd == "weights" => println!("{}", config.weights.to_json()),
+        [cmd, endpoint] if cmd == "serve" => {
+            let endpoint = match endpoint.parse::<Endpoint>() {
+                Ok(endpoint) => endpoint,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(err) = runtime.block_on(serve(&endpoint, config)) {
+                eprintln!("cannot serve on {}: {}", endpoint, err);
+                std::process::exit(1);
+            }
+        }
+        [] => {
+            if let Err(err) = runtime.block_on(run(Connection::stdio(), config)) {
+                eprintln!("{}", err);
+            }
+        }
+        _ => {
+            eprintln!("{}", options::USAGE);
+            std::process::exit(1);
+        }
+    }
+}
 
-            let mut reader = tokio::io::BufReader::new(tokio::io::stdin()).lines();
-            while let Some(line) = reader.next_line().await.unwrap() {
-                if let Ok(result) = serde_json::from_str::<FrontendMessage>(&line) {
-                    match result {
-                        FrontendMessage::Rules { randomizer } => match randomizer.as_str() {
-                            "seven_bag" => {
-                                bot = Some(HikariFireflyBot::new(BotConfig { num_workers: 1 }));
-                                write_message(BotMessage::Ready);
-                            }
-                            _ => write_message(BotMessage::Error {
-                                reason: BotErrorReason::UnsupportedRules,
-                            }),
-                        },
-                        FrontendMessage::Start(start) => {
-                            let Some(bot) = &bot else {
-                                break;
-                            };
-                            bot.reset(Some(GameState {
-                                board: start.board.into(),
-                                queue: start.queue.into(),
-                                hold: start.hold,
-                                ren: start.combo as i32 - 1,
-                                b2b: start.back_to_back,
-                                bag: match start.randomizer {
-                                    Randomizer::SevenBag { bag_state } => SevenBag(bag_state),
-                                    _ => unimplemented!(),
-                                },
-                            }));
-                            bot.start();
-                        }
-                        FrontendMessage::Stop => {
-                            let Some(bot) = &bot else {
-                                break;
-                            };
-                            bot.stop();
-                        }
-                        FrontendMessage::NewPiece { piece } => {
-                            let Some(bot) = &bot else {
-                                break;
-                            };
-                            bot.add_piece(piece);
-                        }
-                        FrontendMessage::Play { mv: _ } => {
-                            let Some(bot) = &bot else {
-                                break;
-                            };
-                            for mv in &last_reply_moves {
-                                bot.pick_move(*mv);
-                            }
-                        }
-                        FrontendMessage::Suggest => {
-                            let Some(bot) = &bot else {
-                                break;
-                            };
-                            if let Some(plan) = bot.suggest() {
-                                let mv = plan[0];
-                                let piece = match mv {
-                                    Move::Hold => {
-                                        if let Some(&next_mv) = plan.get(1) {
-                                            let Move::Place(next_piece) = next_mv else {
-                                                panic!();
-                                            };
-                                            last_reply_moves = vec![mv, next_mv];
-                                            next_piece
-                                        } else {
-                                            panic!();
-                                        }
-                                    }
-                                    Move::Place(piece) => {
-                                        last_reply_moves = vec![mv];
-                                        piece
-                                    }
-                                };
-                                let message = BotMessage::Suggestion {
-                                    moves: vec![piece],
-                                    move_info: MoveInfo {
-                                        nodes: 0,
-                                        nps: 0.0,
-                                        extra: "".to_owned(),
-                                    },
-                                };
-                                write_message(message);
-                            }
-                        }
-                        FrontendMessage::Quit => break,
-                        FrontendMessage::Unknown => break,
+/// Serves frontends until the process is killed, each with its own bot.
+async fn serve(endpoint: &Endpoint, config: BotConfig) -> io::Result<()> {
+    let listener = Listener::bind(endpoint).await?;
+    eprintln!("listening on {}", listener.local_endpoint()?);
+    loop {
+        let incoming = match listener.accept().await {
+            Ok(incoming) => incoming,
+            Err(err) => {
+                eprintln!("cannot accept: {}", err);
+                continue;
+            }
+        };
+        // a frontend that never completes the handshake must not hold up the others
+        tokio::spawn(async move {
+            let connection =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, incoming.handshake()).await {
+                    Ok(Ok(connection)) => connection,
+                    Ok(Err(err)) => {
+                        eprintln!("handshake failed: {}", err);
+                        return;
                     }
-                }
+                    Err(_) => {
+                        eprintln!("handshake timed out");
+                        return;
+                    }
+                };
+            eprintln!("frontend connected");
+            if let Err(err) = run(connection, config).await {
+                eprintln!("{}", err);
             }
-        })
+            eprintln!("frontend disconnected");
+        });
+    }
 }
 
-fn write_message(message: BotMessage) {
-    println!("{}", serde_json::to_string(&message).unwrap());
+/// Plays TBP over the connection until the frontend quits or disconnects.
+async fn run(connection: Connection, config: BotConfig) -> Result<(), tbp_io::Error> {
+    let mut session = Session::new(config);
+    run_bot(connection, &mut session).await
+}
+
+/// Thinks about the first page of a fumen and prints the plan as a fumen.
+/// The queue is read from a `#Q=[hold](current)next` comment. Without search limits, the bot thinks
+/// for a second.
+fn suggest_fumen(fumen: &str, mut config: BotConfig) {
+    let fumen = match Fumen::decode(fumen) {
+        Ok(fumen) => fumen,
+        Err(err) => {
+            eprintln!("invalid fumen: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let Some(state) = fumen.pages.first().map(|page| page.state()) else {
+        eprintln!("fumen has no pages");
+        std::process::exit(1);
+    };
+    if state.queue.is_empty() {
+        eprintln!("fumen has no queue, add a #Q=[hold](current)next comment");
+        std::process::exit(1);
+    }
+
+    if config.limits == SearchLimits::default() {
+        config.limits.min_time = Duration::from_secs(1);
+    }
+    let bot = HikariFireflyBot::new(config);
+    bot.reset(Some(GameState {
+        board: state.board.clone().into(),
+        queue: state.queue.clone(),
+        hold: state.hold,
+        bag: state.bag,
+        ren: state.ren,
+        b2b: state.b2b,
+    }));
+    bot.start();
+    bot.wait_for_limits();
+    let plan = bot.suggest().unwrap_or_default();
+    bot.stop();
+
+    println!("{}", Fumen::from_moves(&state, &plan));
 }
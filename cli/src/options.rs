@@ -0,0 +1,153 @@
+//! Command line options.
+
+use std::time::Duration;
+
+use firefly::{Backup, BotConfig, FinalMove, Selection, Weights};
+
+pub const USAGE: &str = "\
+usage: cli [options]                   play TBP over stdio
+       cli [options] serve <endpoint>  serve TBP on tcp://host:port or ws://host:port
+       cli [options] fumen <fumen>     print the plan for a fumen
+       cli [options] weights           print the weights of the standard evaluator as JSON
+
+options:
+    --workers <n>        number of search threads, 1 by default
+    --evaluator <name>   simple or standard, simple by default
+    --weights <file>     JSON weights of the standard evaluator, missing ones are defaulted
+    --min-time <ms>      think at least this long before suggesting
+    --max-time <ms>      suggest after this long at the latest
+    --nodes <n>          suggest once this many nodes were expanded
+    --stable-nodes <n>   suggest once the best move held for this many expansions
+    --selection <policy> uct or puct, puct by default
+    --exploration <c>    exploration constant, 1 by default
+    --prior <t>          softmax temperature of the puct prior, or none for a uniform one
+    --final-move <by>    play the move with the most visits or the best value
+    --backup <by>        value nodes by their max or mean action, max by default
+    --memory <MiB>       memory the search graph may hold, or none, 1024 by default";
+
+/// Splits the arguments into the bot config and the remaining positional arguments.
+pub fn parse(args: &[String]) -> Result<(BotConfig, Vec<String>), String> {
+    let mut config = BotConfig::default();
+    let mut positional = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.clone());
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        let invalid = || format!("invalid value for {}: {}", arg, value);
+        let number = || value.parse::<u64>().map_err(|_| invalid());
+        let real = || value.parse::<f32>().map_err(|_| invalid());
+        let limits = &mut config.limits;
+        let policy = &mut config.policy;
+        match arg.as_str() {
+            "--workers" => config.num_workers = number()? as usize,
+            "--evaluator" => config.evaluator = value.parse()?,
+            "--weights" => {
+                config.weights =
+                    Weights::load(value).map_err(|err| format!("{}: {}", value, err))?
+            }
+            "--min-time" => limits.min_time = Duration::from_millis(number()?),
+            "--max-time" => limits.max_time = Some(Duration::from_millis(number()?)),
+            "--nodes" => limits.nodes = Some(number()?),
+            "--stable-nodes" => limits.stable_nodes = Some(number()?),
+            "--selection" => {
+                policy.selection = match value.as_str() {
+                    "uct" => Selection::Uct,
+                    "puct" => Selection::Puct,
+                    _ => return Err(invalid()),
+                }
+            }
+            "--exploration" => policy.exploration = real()?,
+            "--prior" => {
+                policy.prior_temperature = match value.as_str() {
+                    "none" => None,
+                    _ => Some(real()?),
+                }
+            }
+            "--final-move" => {
+                policy.final_move = match value.as_str() {
+                    "visits" => FinalMove::Visits,
+                    "value" => FinalMove::Value,
+                    _ => return Err(invalid()),
+                }
+            }
+            "--backup" => {
+                policy.backup = match value.as_str() {
+                    "max" => Backup::Max,
+                    "mean" => Backup::Mean,
+                    _ => return Err(invalid()),
+                }
+            }
+            "--memory" => {
+                config.memory_budget = match value.as_str() {
+                    "none" => None,
+                    _ => Some(number()? as usize * (1 << 20)),
+                }
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok((config, positional))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firefly::EvaluatorKind;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let (config, positional) = parse(&args(
+            "--max-time 500 serve tcp://127.0.0.1:9000 --nodes 1000",
+        ))
+        .unwrap();
+        assert_eq!(positional, ["serve", "tcp://127.0.0.1:9000"]);
+        assert_eq!(config.num_workers, 1);
+        assert_eq!(config.limits.max_time, Some(Duration::from_millis(500)));
+        assert_eq!(config.limits.nodes, Some(1000));
+        assert_eq!(config.limits.min_time, Duration::ZERO);
+
+        let (config, _) = parse(&args(
+            "--selection uct --exploration 0.5 --prior none --final-move value --backup mean",
+        ))
+        .unwrap();
+        assert_eq!(config.policy.selection, Selection::Uct);
+        assert_eq!(config.policy.exploration, 0.5);
+        assert_eq!(config.policy.prior_temperature, None);
+        assert_eq!(config.policy.final_move, FinalMove::Value);
+        assert_eq!(config.policy.backup, Backup::Mean);
+
+        let (config, _) = parse(&args("--memory 64")).unwrap();
+        assert_eq!(config.memory_budget, Some(64 << 20));
+        let (config, _) = parse(&args("--memory none")).unwrap();
+        assert_eq!(config.memory_budget, None);
+
+        let (config, _) = parse(&args("--evaluator standard")).unwrap();
+        assert_eq!(config.evaluator, EvaluatorKind::Standard);
+        assert!(parse(&args("--evaluator neural")).is_err());
+
+        let path = std::env::temp_dir().join(format!("cli-weights-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{ "clear4": 100 }"#).unwrap();
+        let (config, _) = parse(&args(&format!("--weights {}", path.display()))).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            config.weights,
+            Weights::from_json(r#"{ "clear4": 100 }"#).unwrap()
+        );
+        assert!(parse(&args(&format!("--weights {}", path.display()))).is_err());
+
+        assert!(parse(&args("--nodes")).is_err());
+        assert!(parse(&args("--selection ucb")).is_err());
+        assert!(parse(&args("--nodes many")).is_err());
+        assert!(parse(&args("--depth 3")).is_err());
+    }
+}
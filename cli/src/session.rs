@@ -0,0 +1,295 @@
+//! TBP session handling.
+//!
+//! The session mirrors the game as the frontend sees it, so that played moves can be mapped to
+//! bot actions and suggestions can be answered before the search has found anything. The order of
+//! messages is checked by [`tbp_io::BotProtocol`].
+
+use firefly::{BotConfig, HikariFireflyBot, PlayError};
+use game::tetris::{
+    bag::BagTracker, tbp::*, BitBoard, GameState, Move, PieceKind, PieceState, SevenBag,
+};
+use tbp_io::{Bot, BotInfo, Suggestion};
+
+pub struct Session {
+    config: BotConfig,
+    /// Created once the rules are accepted.
+    bot: Option<HikariFireflyBot>,
+    /// The current game, from `start` until `stop`.
+    game: Option<GameState<BitBoard>>,
+    /// Only used when the frontend does not tell the bag state.
+    bag_tracker: Option<BagTracker>,
+}
+
+impl Session {
+    pub fn new(config: BotConfig) -> Self {
+        Self {
+            config,
+            bot: None,
+            game: None,
+            bag_tracker: None,
+        }
+    }
+}
+
+impl Bot for Session {
+    fn info(&self) -> BotInfo {
+        BotInfo {
+            name: "Hikari".to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            author: "SoRA-X7".to_owned(),
+            features: vec!["randomizer".to_owned()],
+        }
+    }
+
+    fn rules(&mut self, randomizer: Option<&str>) -> Result<(), BotErrorReason> {
+        match randomizer {
+            None | Some("seven_bag") => {
+                let config = self.config;
+                self.bot
+                    .get_or_insert_with(|| HikariFireflyBot::new(config));
+                Ok(())
+            }
+            Some(_) => {
+                self.bot = None;
+                Err(BotErrorReason::UnsupportedRules)
+            }
+        }
+    }
+
+    fn start(&mut self, start: Start) -> Result<(), BotErrorReason> {
+        let Some(bot) = &self.bot else {
+            return Err(BotErrorReason::UnexpectedMessage);
+        };
+
+        let bag = match start.randomizer {
+            Randomizer::SevenBag { bag_state } => {
+                self.bag_tracker = None;
+                SevenBag(bag_state)
+            }
+            Randomizer::Unknown => {
+                let tracker = BagTracker::with_history(start.queue.clone()).unwrap_or_else(|err| {
+                    eprintln!("bag desync: {}", err);
+                    BagTracker::new()
+                });
+                let bag = tracker.likely_bag();
+                self.bag_tracker = Some(tracker);
+                bag
+            }
+        };
+        let game = GameState {
+            board: start.board.into(),
+            queue: start.queue.into(),
+            hold: start.hold,
+            ren: start.combo as i32 - 1,
+            b2b: start.back_to_back,
+            bag,
+        };
+        bot.reset(Some(game.clone()));
+        bot.start();
+        self.game = Some(game);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if self.game.take().is_some() {
+            if let Some(bot) = &self.bot {
+                bot.stop();
+            }
+        }
+    }
+
+    fn suggest(&mut self) -> Result<Suggestion, BotErrorReason> {
+        let (Some(bot), Some(game)) = (&self.bot, &self.game) else {
+            return Err(BotErrorReason::UnexpectedMessage);
+        };
+
+        // the wait sleeps, which must not hold up the other connections of the runtime worker
+        let (plan, stats) =
+            tokio::task::block_in_place(|| bot.suggest_within_limits()).unwrap_or_default();
+        // A leading hold is implied by suggesting the piece that comes out of it
+        let planned = plan.iter().find_map(|mv| match mv {
+            Move::Place(piece) => Some(*piece),
+            Move::Hold => None,
+        });
+        let moves = planned
+            .or_else(|| fallback_move(game))
+            .into_iter()
+            .collect();
+
+        Ok(Suggestion {
+            moves,
+            move_info: MoveInfo {
+                nodes: stats.nodes,
+                nps: stats.nps,
+                // TBP has no fields for the rest
+                extra: stats.to_string(),
+            },
+        })
+    }
+
+    fn play(&mut self, piece: PieceState) -> Result<(), BotErrorReason> {
+        let (Some(bot), Some(game)) = (&self.bot, &mut self.game) else {
+            return Err(BotErrorReason::UnexpectedMessage);
+        };
+        let Some(moves) = moves_for(game, piece) else {
+            return Err(BotErrorReason::InvalidMove);
+        };
+
+        let played = bot.play(piece);
+        if played == Err(PlayError::IllegalPlacement) {
+            return Err(BotErrorReason::InvalidMove);
+        }
+        for &mv in &moves {
+            game.advance(mv);
+        }
+        if let Err(err) = played {
+            // the bot lost track of the game, start over from the frontend's state
+            eprintln!("desync: {}", err);
+            bot.reset(Some(game.clone()));
+        }
+        Ok(())
+    }
+
+    fn new_piece(&mut self, piece: PieceKind) -> Result<(), BotErrorReason> {
+        let (Some(bot), Some(game)) = (&self.bot, &mut self.game) else {
+            return Err(BotErrorReason::UnexpectedMessage);
+        };
+
+        match &mut self.bag_tracker {
+            Some(tracker) => {
+                if let Err(err) = tracker.observe(piece) {
+                    eprintln!("bag desync: {}", err);
+                }
+                game.bag = tracker.likely_bag();
+            }
+            None => {
+                if !game.bag.has(piece) {
+                    eprintln!("bag desync: {} is not in the bag", piece);
+                    game.bag = SevenBag::default();
+                }
+                game.bag.take(piece);
+            }
+        }
+        game.queue.push_back(piece);
+        bot.add_piece(piece);
+        // the search draws from its own bag, which misses what the tracker learned
+        if bot
+            .game_state()
+            .is_some_and(|searched| searched.bag != game.bag)
+        {
+            bot.set_bag(game.bag);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the moves that play `piece` in the mirrored game, adding a hold when the piece comes
+/// from the queue.
+fn moves_for(game: &GameState<BitBoard>, piece: PieceState) -> Option<Vec<Move>> {
+    let kind = piece.pos.kind;
+    if game.queue.front() == Some(&kind) {
+        return Some(vec![Move::Place(piece)]);
+    }
+    match game.hold {
+        Some(hold) if hold == kind && !game.queue.is_empty() => Some(vec![Move::Place(piece)]),
+        None if game.queue.get(1) == Some(&kind) => Some(vec![Move::Hold, Move::Place(piece)]),
+        _ => None,
+    }
+}
+
+/// Returns the lowest placement, for when the search has nothing to suggest yet.
+fn fallback_move(game: &GameState<BitBoard>) -> Option<PieceState> {
+    let moves = game.legal_moves(true).ok()?;
+    moves
+        .moves()
+        .into_iter()
+        .filter_map(|mv| match mv {
+            Move::Place(piece) => Some(piece),
+            Move::Hold => None,
+        })
+        .min_by_key(|piece| {
+            let top = piece.pos.cells().iter().map(|&(_, y)| y).max();
+            (top, piece.pos.x, piece.pos.y, piece.pos.rot)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::tetris::ColoredBoard;
+
+    fn start(queue: &[PieceKind]) -> Start {
+        Start {
+            board: ColoredBoard::default(),
+            queue: queue.to_vec(),
+            hold: None,
+            combo: 0,
+            back_to_back: false,
+            randomizer: Randomizer::Unknown,
+        }
+    }
+
+    #[test]
+    fn test_play_with_hold() {
+        let mut session = Session::new(BotConfig {
+            num_workers: 0,
+            ..Default::default()
+        });
+        session.rules(None).unwrap();
+        assert_eq!(
+            session.suggest().err(),
+            Some(BotErrorReason::UnexpectedMessage)
+        );
+        session.start(start(&[PieceKind::T, PieceKind::I])).unwrap();
+
+        // nothing was searched, but a move is suggested anyway
+        let Suggestion { moves, move_info } = session.suggest().unwrap();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].pos.kind, PieceKind::T);
+        assert_eq!(move_info.nodes, 0);
+        assert!(move_info.extra.contains("depth 0"));
+
+        // the I comes out of an implicit hold
+        let game = session.game.clone().unwrap();
+        let piece = fallback_move(&GameState {
+            queue: [PieceKind::I].into(),
+            ..game.clone()
+        })
+        .unwrap();
+        assert_eq!(
+            moves_for(&game, piece),
+            Some(vec![Move::Hold, Move::Place(piece)])
+        );
+        assert_eq!(session.play(piece), Ok(()));
+        let game = session.game.as_ref().unwrap();
+        assert_eq!(game.hold, Some(PieceKind::T));
+        assert!(game.queue.is_empty());
+
+        // the I is neither the current piece nor held anymore
+        assert_eq!(session.play(piece), Err(BotErrorReason::InvalidMove));
+
+        assert_eq!(session.new_piece(PieceKind::O), Ok(()));
+        assert_eq!(session.game.as_ref().unwrap().queue, [PieceKind::O]);
+        session.stop();
+    }
+
+    #[test]
+    fn test_bag_desync() {
+        let mut session = Session::new(BotConfig {
+            num_workers: 0,
+            ..Default::default()
+        });
+        session.rules(None).unwrap();
+        session.start(start(&[PieceKind::T, PieceKind::I])).unwrap();
+
+        // a second T means a bag ended in between, which the bot alone would only guess from the T
+        assert_eq!(session.new_piece(PieceKind::T), Ok(()));
+        let game = session.game.as_ref().unwrap();
+        assert_eq!(game.bag, session.bag_tracker.as_ref().unwrap().likely_bag());
+        assert!(!game.bag.has(PieceKind::I));
+        let searched = session.bot.as_ref().unwrap().game_state().unwrap();
+        assert_eq!(searched.bag, game.bag);
+        assert_eq!(searched.queue, game.queue);
+        session.stop();
+    }
+}
@@ -1,5 +1,5 @@
 use game::tetris::*;
-use serde::{ser::SerializeSeq, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -31,7 +31,7 @@ pub struct Replay {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReplayState {
-    #[serde(serialize_with = "serialize_board")]
+    #[serde(with = "game::tetris::codec::bool_grid")]
     board: BitBoard,
     current: PieceKind,
     unhold: PieceKind,
@@ -41,15 +41,3 @@ pub struct ReplayState {
     b2b: bool,
     bag: SmallVec<[PieceKind; 7]>,
 }
-
-fn serialize_board<S>(board: &BitBoard, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    let mut cols = serializer.serialize_seq(Some(10))?;
-    for x in 0..10 {
-        let cells: Vec<bool> = (0..64).map(|y| board.occupied((x, y as i8))).collect();
-        cols.serialize_element(&cells)?;
-    }
-    cols.end()
-}
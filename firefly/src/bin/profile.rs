@@ -1,4 +1,5 @@
 use firefly::{BotConfig, HikariFireflyBot};
+use game::tetris::GameState;
 
 fn main() {
     let server_addr = format!("127.0.0.1:{}", puffin_http::DEFAULT_PORT);
@@ -7,14 +8,33 @@ fn main() {
     puffin::set_scopes_on(true);
     puffin::GlobalProfiler::lock().new_frame();
 
-    let config = BotConfig { num_workers: 4 };
+    let evaluator = std::env::args().nth(1).unwrap_or("simple".to_owned());
+    let config = BotConfig {
+        num_workers: 4,
+        evaluator: evaluator.parse().expect("usage: profile [simple|standard]"),
+        ..Default::default()
+    };
     let bot = HikariFireflyBot::new(config);
+
+    let mut state = GameState::new();
+    for _ in 0..12 {
+        state.fulfill_queue();
+    }
+    bot.reset(Some(state.clone()));
     bot.start();
 
     for _ in 0..5 {
         std::thread::sleep(std::time::Duration::from_secs(1));
-        let plan = bot.suggest().unwrap();
-        bot.pick_move(plan[0]);
+        let Some(&mv) = bot.suggest().unwrap_or_default().first() else {
+            eprintln!("no move found");
+            break;
+        };
+        if let Err(err) = bot.pick_move(mv) {
+            eprintln!("cannot play {:?}: {}", mv, err);
+            break;
+        }
+        state.advance(mv);
+        bot.add_piece(state.fulfill_queue());
         puffin::GlobalProfiler::lock().new_frame();
     }
 
@@ -2,7 +2,11 @@ use firefly::{BotConfig, HikariFireflyBot};
 use game::tetris::GameState;
 
 fn main() {
-    let config = BotConfig { num_workers: 1 };
+    let evaluator = std::env::args().nth(1).unwrap_or("simple".to_owned());
+    let config = BotConfig {
+        evaluator: evaluator.parse().expect("usage: sample [simple|standard]"),
+        ..Default::default()
+    };
     let bot = HikariFireflyBot::new(config);
 
     let mut state = GameState::new();
@@ -22,11 +26,11 @@ fn main() {
         eprintln!("Move: {:?}", mv);
 
         state.advance(mv);
-        bot.pick_move(mv);
+        bot.pick_move(mv).unwrap();
 
         let piece_appended = state.fulfill_queue();
         bot.add_piece(piece_appended);
-        eprintln!("State: {:?}", state);
+        eprintln!("State:\n{}", state);
     }
 
     bot.stop();
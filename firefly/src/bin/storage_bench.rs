@@ -0,0 +1,103 @@
+//! Compares how the storage of the search scales with threads, between the `Rack` it used before
+//! and the `Arena` it uses now.
+//!
+//! Each operation does what an expansion does to the storage: it allocates a node, appends its
+//! actions, and updates an earlier node.
+
+use std::time::Instant;
+
+use firefly::storage::{Arena, Index, Rack};
+use parking_lot::Mutex;
+use rand::prelude::*;
+
+const OPERATIONS: usize = 200_000;
+const ACTIONS: usize = 32;
+/// The shelves of a rack in a generation, as the search used them.
+const SHELVES: u32 = 1 << 12;
+
+trait Storage: Sync {
+    fn new() -> Self;
+    fn expand(&self, value: u64) -> Index;
+    fn update(&self, index: Index);
+}
+
+struct RackStorage {
+    nodes: Rack<u64>,
+    actions: Rack<u64>,
+}
+
+impl Storage for RackStorage {
+    fn new() -> Self {
+        Self {
+            nodes: Rack::new(SHELVES),
+            actions: Rack::new(SHELVES),
+        }
+    }
+
+    fn expand(&self, value: u64) -> Index {
+        self.actions.rent_shelf().append_vec(vec![value; ACTIONS]);
+        self.nodes.alloc(value)
+    }
+
+    fn update(&self, index: Index) {
+        *self.nodes.get(index) += 1;
+    }
+}
+
+struct ArenaStorage {
+    nodes: Arena<Mutex<u64>>,
+    actions: Arena<u64>,
+}
+
+impl Storage for ArenaStorage {
+    fn new() -> Self {
+        Self {
+            nodes: Arena::new(),
+            actions: Arena::new(),
+        }
+    }
+
+    fn expand(&self, value: u64) -> Index {
+        self.actions.alloc_range(vec![value; ACTIONS]);
+        self.nodes.alloc(Mutex::new(value))
+    }
+
+    fn update(&self, index: Index) {
+        *self.nodes.get(index).lock() += 1;
+    }
+}
+
+/// Returns the operations per second with `threads` threads sharing one storage.
+fn run<S: Storage>(threads: usize) -> f64 {
+    let storage = S::new();
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut rng = thread_rng();
+                let mut indices = Vec::with_capacity(OPERATIONS / threads);
+                for i in 0..OPERATIONS / threads {
+                    indices.push(storage.expand(i as u64));
+                    storage.update(*indices.choose(&mut rng).unwrap());
+                }
+            });
+        }
+    });
+    OPERATIONS as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let max_threads = match std::env::args().nth(1) {
+        Some(threads) => threads.parse().expect("usage: storage_bench [max threads]"),
+        None => std::thread::available_parallelism().map_or(1, usize::from),
+    };
+
+    println!("threads      rack/s     arena/s");
+    let mut threads = 1;
+    while threads <= max_threads {
+        let rack = run::<RackStorage>(threads);
+        let arena = run::<ArenaStorage>(threads);
+        println!("{:>7} {:>11.0} {:>11.0}", threads, rack, arena);
+        threads *= 2;
+    }
+}
@@ -7,11 +7,15 @@ mod standard;
 #[allow(unused)]
 pub use simple::SimpleEvaluator;
 #[allow(unused)]
-pub use standard::StandardEvaluator;
+pub use standard::{StandardEvaluator, Weights, WeightsError, MAX_WEIGHT};
 
 pub trait Evaluator: Debug + Sync + Send {
-    type TransientReward: Clone + Copy + Debug;
-    type Accumulator: Accumulator<Reward = Self::TransientReward> + Clone + Copy + Debug;
+    type TransientReward: Clone + Copy + Debug + Default + Send + Sync;
+    type Accumulator: Accumulator<Reward = Self::TransientReward>
+        + Clone
+        + Copy
+        + Debug
+        + PartialEq;
 
     fn evaluate_state(&self, state: &GameState<BitBoard>) -> Self::Accumulator;
     fn evaluate_move(
@@ -27,4 +31,8 @@ pub trait Accumulator: Debug + Sync + Send + Default {
     type Reward;
     fn accumulate(&self, other: Self::Reward) -> Self;
     fn select_score(&self) -> i32;
+    /// Weighted mean of the values, whose weights sum to 1.
+    fn mean(values: &[(Self, f32)]) -> Self
+    where
+        Self: Sized;
 }
@@ -1,3 +1,5 @@
+use game::tetris::features;
+
 use super::*;
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -14,20 +16,8 @@ impl Evaluator for SimpleEvaluator {
     fn evaluate_state(&self, state: &GameState<BitBoard>) -> Self::Accumulator {
         let mut score = 0;
 
-        // height
-        let max_height = (0..10).map(|x| state.board.height_of(x)).max().unwrap();
-        score += max_height as i32 * -10;
-
-        // holes
-        let mut holes = 0;
-        for x in 0..10 {
-            for y in 0..(state.board.height_of(x) as i32 - 1) {
-                if !state.board.occupied((x, y as i8)) {
-                    holes += 1;
-                }
-            }
-        }
-        score += holes * -7;
+        score += features::max_height(&state.board) as i32 * -10;
+        score += features::holes(&state.board) as i32 * -7;
 
         score
     }
@@ -52,4 +42,12 @@ impl Accumulator for i32 {
     fn select_score(&self) -> i32 {
         *self
     }
+
+    fn mean(values: &[(Self, f32)]) -> Self {
+        values
+            .iter()
+            .map(|&(value, weight)| value as f64 * weight as f64)
+            .sum::<f64>()
+            .round() as i32
+    }
 }
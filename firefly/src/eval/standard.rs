@@ -1,8 +1,23 @@
-use game::tetris::*;
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use game::tetris::{
+    features,
+    tslot::{self, TSlotKind},
+    *,
+};
+use serde::{Deserialize, Serialize};
 
 use super::{Accumulator, Evaluator};
 
-#[derive(Debug, Clone)]
+/// Largest magnitude of a weight.
+///
+/// Squared features times such weights overflow an i32, so features are weighted in an i64 and
+/// scores saturate at the range of an i32.
+pub const MAX_WEIGHT: i32 = 1_000_000;
+
+/// Weights of [`StandardEvaluator`]. In files, missing weights take their default value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Weights {
     no_tspin: bool,
     bump_sum: i32,
@@ -39,6 +54,9 @@ pub struct Weights {
     t_spin3: i32,
     wasted_t: i32,
     hold_t: i32,
+    t_hole: i32,
+    tst_hole: i32,
+    fin_hole: i32,
 }
 
 impl Default for Weights {
@@ -54,9 +72,9 @@ impl Default for Weights {
             t_mini1: -880,
             t_mini2: -500,
             perfect: 3800,
-            // t_hole: 200,
-            // tst_hole: 180,
-            // fin_hole: 150,
+            t_hole: 200,
+            tst_hole: 180,
+            fin_hole: 150,
             b2b_continue: 340,
             b2b_destroy: -380,
             ren: 100,
@@ -86,18 +104,99 @@ impl Default for Weights {
     }
 }
 
+#[derive(Debug)]
+pub enum WeightsError {
+    Io(io::Error),
+    /// Malformed JSON, an unknown weight or a weight of the wrong type.
+    Parse(serde_json::Error),
+    OutOfRange {
+        weight: String,
+        value: i64,
+    },
+}
+
+impl fmt::Display for WeightsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WeightsError::Io(err) => write!(f, "cannot access weights: {}", err),
+            WeightsError::Parse(err) => write!(f, "invalid weights: {}", err),
+            WeightsError::OutOfRange { weight, value } => write!(
+                f,
+                "weight {} is {}, outside of -{max}..={max}",
+                weight,
+                value,
+                max = MAX_WEIGHT
+            ),
+        }
+    }
+}
+
+impl Error for WeightsError {}
+
+impl Weights {
+    pub fn from_json(json: &str) -> Result<Self, WeightsError> {
+        let weights: Weights = serde_json::from_str(json).map_err(WeightsError::Parse)?;
+        weights.validate()?;
+        Ok(weights)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("weights serialize")
+    }
+
+    /// Reads the weights from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, WeightsError> {
+        Self::from_json(&fs::read_to_string(path).map_err(WeightsError::Io)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), WeightsError> {
+        fs::write(path, self.to_json()).map_err(WeightsError::Io)
+    }
+
+    fn validate(&self) -> Result<(), WeightsError> {
+        let serde_json::Value::Object(weights) = serde_json::to_value(self).unwrap() else {
+            unreachable!("weights serialize to an object");
+        };
+        for (name, value) in weights {
+            let values = match value {
+                serde_json::Value::Array(values) => values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, value)| (format!("{}[{}]", name, i), value))
+                    .collect(),
+                value => vec![(name, value)],
+            };
+            for (weight, value) in values {
+                // flags are not numbers
+                if let Some(value) = value.as_i64() {
+                    if value.abs() > MAX_WEIGHT as i64 {
+                        return Err(WeightsError::OutOfRange { weight, value });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct StandardEvaluator {
     weights: Weights,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl StandardEvaluator {
+    pub fn new(weights: Weights) -> Self {
+        Self { weights }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Value {
     value: [i32; 3],
     spike: i32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Reward {
     eval: i32,
     attack: u32,
@@ -117,13 +216,31 @@ impl Accumulator for Value {
 
     fn accumulate(&self, next: Reward) -> Self {
         let mut value = self.value;
-        value[2] += next.eval;
+        value[2] = value[2].saturating_add(next.eval);
         let spike = if next.attack > 0 { self.spike + 1 } else { 0 };
         Value { value, spike }
     }
 
     fn select_score(&self) -> i32 {
-        self.value.iter().sum()
+        saturate(self.value.iter().map(|&value| value as i64).sum())
+    }
+
+    fn mean(values: &[(Self, f32)]) -> Self {
+        let mean = |field: fn(&Value) -> i32| {
+            values
+                .iter()
+                .map(|(value, weight)| field(value) as f64 * *weight as f64)
+                .sum::<f64>()
+                .round() as i32
+        };
+        Value {
+            value: [
+                mean(|v| v.value[0]),
+                mean(|v| v.value[1]),
+                mean(|v| v.value[2]),
+            ],
+            spike: mean(|v| v.spike),
+        }
     }
 }
 
@@ -140,84 +257,66 @@ impl Evaluator for StandardEvaluator {
 
     fn evaluate_state(&self, state: &GameState<BitBoard>) -> Self::Accumulator {
         puffin::profile_function!();
-        let mut field_safety = 0;
-        let mut field_power = 0;
+        // see MAX_WEIGHT
+        let mut field_safety = 0i64;
+        let mut field_power = 0i64;
 
-        /*
+        // the stack is judged as if the available T pieces were spun into its slots, found by shape
+        // as generating T moves for every evaluated state is too slow
+        let mut board = state.board.clone();
         if !self.weights.no_tspin {
-            let t_piece_count = (clone.bag.has(PieceKind::T) as i32)
-                + (clone.hold == Some(PieceKind::T)) as i32
-                + (clone.bag.len() <= 3) as i32;
-
-            for _ in 0..t_piece_count {
-                if let Some(t_hole) = self.scan_tspin(&clone, &max_heights, max_height) {
-                    field_power += t_hole.lines * weights.t_hole;
-                    self.after_spin(&mut clone, t_hole, piece_shapes);
-                    clone.get_columns(&mut columns, &mut max_heights);
-                    max_height -= t_hole.lines;
-                    continue;
-                }
-
-                if let Some(tst) = self.scan_tst(&clone, &max_heights, max_height) {
-                    field_power += tst.lines * weights.tst_hole;
-                    self.after_spin(&mut clone, tst, piece_shapes);
-                    clone.get_columns(&mut columns, &mut max_heights);
-                    max_height -= tst.lines;
-                    continue;
-                }
-
-                break;
+            for _ in 0..t_piece_count(state) {
+                let Some(slot) = tslot::scan_t_slot(&board) else {
+                    break;
+                };
+                let weight = match slot.kind {
+                    TSlotKind::Tsd | TSlotKind::ImperialCross => self.weights.t_hole,
+                    TSlotKind::Tst | TSlotKind::Stsd => self.weights.tst_hole,
+                    TSlotKind::Fin => self.weights.fin_hole,
+                };
+                field_power += slot.lines() as i64 * i64::from(weight);
+                slot.apply(&mut board);
             }
         }
-        */
 
-        let (well_column, well_depth) = calc_well_x_and_depth(&state.board);
+        let (well_column, well_depth) = features::well(&board);
 
-        let (bump_sum, bump_sq_sum) = calc_bumpiness(&state.board, well_column);
-        field_safety += bump_sum as i32 * self.weights.bump_sum;
-        field_safety += bump_sq_sum as i32 * self.weights.bump_sum_sq;
+        let (bump_sum, bump_sq_sum) = features::bumpiness(&board, well_column);
+        field_safety += bump_sum as i64 * i64::from(self.weights.bump_sum);
+        field_safety += bump_sq_sum as i64 * i64::from(self.weights.bump_sum_sq);
 
-        field_power += well_depth as i32 * self.weights.well_depth;
+        field_power += well_depth as i64 * i64::from(self.weights.well_depth);
         if well_depth >= 2 {
-            field_power += self.weights.well_x[well_column as usize];
+            field_power += i64::from(self.weights.well_x[well_column as usize]);
         }
 
-        let max_diff = (0..9)
-            .filter(|&x| x != well_column)
-            .map(|x| u32::abs_diff(state.board.height_of(x), state.board.height_of(x + 1)))
-            .max()
-            .unwrap();
-
-        let max_height = (0..10).map(|x| state.board.height_of(x)).max().unwrap();
+        let max_diff = features::max_height_diff(&board, well_column);
+        let max_height = features::max_height(&board) as i64;
 
-        field_safety += max_diff as i32 * self.weights.max_height_diff;
-        field_safety += max_height as i32 * self.weights.max_height;
-        field_safety += i32::max(max_height as i32 - 10, 0) * self.weights.top_50;
-        field_safety += i32::max(max_height as i32 - 15, 0) * self.weights.top_75;
+        field_safety += max_diff as i64 * i64::from(self.weights.max_height_diff);
+        field_safety += max_height * i64::from(self.weights.max_height);
+        field_safety += i64::max(max_height - 10, 0) * i64::from(self.weights.top_50);
+        field_safety += i64::max(max_height - 15, 0) * i64::from(self.weights.top_75);
 
-        let cav_ovh = cavities_and_overhangs(&state.board);
-        field_safety += cav_ovh.0 * self.weights.cavities;
-        field_safety += cav_ovh.0 * cav_ovh.0 * self.weights.cavities_sq;
-        field_safety += cav_ovh.1 * self.weights.overhangs;
-        field_safety += cav_ovh.1 * cav_ovh.1 * self.weights.overhangs_sq;
+        let (cavities, overhangs) = features::cavities_and_overhangs(&board);
+        let (cavities, overhangs) = (cavities as i64, overhangs as i64);
+        field_safety += cavities * i64::from(self.weights.cavities);
+        field_safety += cavities * cavities * i64::from(self.weights.cavities_sq);
+        field_safety += overhangs * i64::from(self.weights.overhangs);
+        field_safety += overhangs * overhangs * i64::from(self.weights.overhangs_sq);
 
-        let (covered, covered_sq) = covered_cells(&state.board);
-        field_safety += covered as i32 * self.weights.covered_cells;
-        field_safety += covered_sq as i32 * self.weights.covered_cells_sq;
+        let (covered, covered_sq) = features::covered_cells(&board);
+        field_safety += covered as i64 * i64::from(self.weights.covered_cells);
+        field_safety += covered_sq as i64 * i64::from(self.weights.covered_cells_sq);
 
-        let transitions = (0..64)
-            .map(|y| {
-                let row = state.board.get_row(y);
-                let lines = (row | 0b1_00000_00000) ^ (1 | row << 1);
-                lines.count_ones() as i32
-            })
-            .sum::<i32>();
+        let transitions = features::row_transitions(&board) as i64;
 
-        field_safety += transitions * self.weights.row_transitions;
-        field_power += (state.ren as f32).log2() as i32 * self.weights.downstack;
+        field_safety += transitions * i64::from(self.weights.row_transitions);
+        // no combo and the first clear of one are both worth nothing
+        field_power += state.ren.max(1).ilog2() as i64 * i64::from(self.weights.downstack);
 
         Value {
-            value: [field_safety, field_power, 0],
+            value: [saturate(field_safety), saturate(field_power), 0],
             spike: 0,
         }
     }
@@ -230,7 +329,7 @@ impl Evaluator for StandardEvaluator {
     ) -> Self::TransientReward {
         let prev_b2b = state.b2b;
 
-        let mut move_score = 0;
+        let mut move_score = 0i64;
 
         let mut time = 0;
         if let Move::Place(piece) = mv {
@@ -238,30 +337,31 @@ impl Evaluator for StandardEvaluator {
             if !placement.is_pc && placement.lines_cleared > 0 {
                 time += 40;
             }
-            move_score += time * self.weights.move_time;
+            move_score += time * i64::from(self.weights.move_time);
 
             let max_danger_height = [0, 1, 2, 7, 8, 9]
                 .iter()
                 .map(|&x| state.board.height_of(x))
                 .max()
                 .unwrap();
-            move_score += i32::max(max_danger_height as i32 - 15, 0) * time * self.weights.danger;
+            move_score +=
+                i64::max(max_danger_height as i64 - 15, 0) * time * i64::from(self.weights.danger);
 
             if placement.lines_cleared > 0 && placement.is_b2b_clear {
-                move_score += self.weights.b2b_continue;
+                move_score += i64::from(self.weights.b2b_continue);
             }
             if prev_b2b && !placement.is_b2b_clear {
-                move_score += self.weights.b2b_destroy;
+                move_score += i64::from(self.weights.b2b_destroy);
             }
 
-            move_score += piece.pos.y as i32 * self.weights.placement_height;
+            move_score += piece.pos.y as i64 * i64::from(self.weights.placement_height);
 
             if placement.is_pc {
-                move_score += self.weights.perfect;
+                move_score += i64::from(self.weights.perfect);
             } else {
-                let ren_attack = ren_attack(state.ren) as i32;
-                move_score += self.weights.ren * ren_attack * ren_attack;
-                move_score += match placement.spin {
+                let ren_attack = ren_attack(state.ren) as i64;
+                move_score += i64::from(self.weights.ren) * ren_attack * ren_attack;
+                move_score += i64::from(match placement.spin {
                     SpinKind::None => match placement.lines_cleared {
                         1 => self.weights.clear1,
                         2 => self.weights.clear2,
@@ -280,182 +380,239 @@ impl Evaluator for StandardEvaluator {
                         2 => self.weights.t_mini2,
                         _ => 0,
                     },
-                };
+                });
             }
 
             if piece.pos.kind == PieceKind::T {
                 if !(piece.spin == SpinKind::Full && placement.lines_cleared > 0) {
-                    move_score += self.weights.wasted_t;
+                    move_score += i64::from(self.weights.wasted_t);
                 }
             }
         }
 
         if state.hold == Some(PieceKind::T) {
-            move_score += self.weights.hold_t;
+            move_score += i64::from(self.weights.hold_t);
         }
 
         Reward {
-            eval: move_score,
+            eval: saturate(move_score),
             attack: placement.attack(),
         }
     }
 }
 
-#[inline(always)]
-/// Returns the number of covered cells and the sum of squares of the number of covered cells.
-/// A cell is considered covered if there is a block above it.
-fn covered_cells(board: &BitBoard) -> (u32, u32) {
-    let mut covered = 0;
-    let mut sq = 0;
-
-    for x in 0..10 {
-        for y in (0..(board.height_of(x).max(3) - 2 - 1)).rev() {
-            if board.occupied((x as i8, y as i8)) {
-                continue;
-            }
-            let cells = board.height_of(x) - y - 1;
-            covered += cells;
-            sq += cells * cells;
-        }
-    }
-
-    (covered, sq)
+/// Clamps a score summed in an i64 to the range of an i32.
+fn saturate(score: i64) -> i32 {
+    score.clamp(i32::MIN as i64, i32::MAX as i64) as i32
 }
 
-#[inline(always)]
-/// Returns the well position and depth.
-/// "Well" means the column with the lowest height.
-/// Depth is the number of lines below the well that is ready to be cleared.
-fn calc_well_x_and_depth(board: &BitBoard) -> (i8, u32) {
-    let well = (1..10).min_by_key(|&x| board.height_of(x)).unwrap_or(0);
-
-    let mut depth = 0;
-    for x in 0..10 {
-        let mut y = board.height_of(x) as i32 - 1;
-        while y >= 0 && board.occupied((x as i8, y as i8)) {
-            y -= 1;
-        }
-        if y >= 0 {
-            depth += y;
-        }
-    }
-
-    (well, depth as u32)
+/// Returns the number of T pieces to expect soon: the held and queued ones, the one left in the
+/// bag, and the one of the next bag once few pieces are left.
+fn t_piece_count(state: &GameState<BitBoard>) -> usize {
+    let queued = state.queue.iter().filter(|&&kind| kind == PieceKind::T);
+    // an empty set is a full bag
+    let bag_len = match state.bag.0.len() {
+        0 => 7,
+        len => len,
+    };
+    queued.count()
+        + (state.hold == Some(PieceKind::T)) as usize
+        + state.bag.has(PieceKind::T) as usize
+        + (bag_len <= 3) as usize
 }
 
-/// Returns the bumpiness and the sum of squares of the bumpiness.
-/// Bumpiness is the sum of the absolute differences in height between adjacent columns excluding the well.
-#[inline(always)]
-fn calc_bumpiness(board: &BitBoard, well: i8) -> (u32, u32) {
-    let mut bumpiness = 0;
-    let mut bumpiness_sq = 0;
-
-    let mut prev = 0;
-
-    for x in 0..10i8 {
-        if x == well {
-            continue;
-        }
-        if prev >= 0 {
-            let dh = u32::abs_diff(prev, board.height_of(x));
-            bumpiness += dh;
-            bumpiness_sq += dh * dh;
-        }
-
-        prev = board.height_of(x);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weights_json() {
+        let defaults = Weights::default();
+        assert_eq!(Weights::from_json(&defaults.to_json()).unwrap(), defaults);
+
+        // missing weights are defaulted
+        let weights = Weights::from_json(r#"{ "clear4": 100, "no_tspin": true }"#).unwrap();
+        assert_eq!(
+            weights,
+            Weights {
+                clear4: 100,
+                no_tspin: true,
+                ..defaults
+            }
+        );
+        assert_eq!(Weights::from_json("{}").unwrap(), defaults);
+
+        assert!(matches!(
+            Weights::from_json(r#"{ "clear5": 100 }"#),
+            Err(WeightsError::Parse(_))
+        ));
+        assert!(matches!(
+            Weights::from_json(r#"{ "clear4": "many" }"#),
+            Err(WeightsError::Parse(_))
+        ));
+        let err = Weights::from_json(r#"{ "well_x": [0, 0, 0, 0, 0, 0, 0, 0, 2000000, 0] }"#)
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            WeightsError::OutOfRange { weight, value: 2000000 } if weight == "well_x[8]"
+        ));
     }
 
-    (bumpiness, bumpiness_sq)
-}
-/*
-struct TSpinHole {
-    x: i8,
-    y: i8,
-    rot: Rotation,
-    lines: u8,
-}
-
-#[inline(always)]
-fn scan_tspin(board: &BitBoard) -> Option<TSpinHole> {
-    let best = None;
-    for x in 0..10 {
-        for y in (0..board.height_of(x) as i8).rev() {
-            let pos = (x, y);
-            board.
-        }
+    #[test]
+    fn test_t_slots() {
+        let evaluator = StandardEvaluator::default();
+        let no_t = SevenBag(
+            [
+                PieceKind::I,
+                PieceKind::O,
+                PieceKind::L,
+                PieceKind::J,
+                PieceKind::S,
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let state = |board: BitBoard, hold: Option<PieceKind>| GameState {
+            board,
+            hold,
+            bag: no_t,
+            ..GameState::new()
+        };
+        let board = game::bit_board! {
+            "xxx_______",
+            "xx___xxxxx",
+            "xxx_xxxxxx"
+        };
+        let after = game::bit_board! { "xxx_______" };
+
+        // the slot is judged as spun when a T is held
+        let with_t = evaluator.evaluate_state(&state(board.clone(), Some(PieceKind::T)));
+        let spun = evaluator.evaluate_state(&state(after, None));
+        assert_eq!(with_t.value[0], spun.value[0]);
+        assert_eq!(
+            with_t.value[1],
+            spun.value[1] + 2 * evaluator.weights.t_hole
+        );
+
+        // and left alone without one
+        let without_t = evaluator.evaluate_state(&state(board.clone(), Some(PieceKind::I)));
+        assert!(without_t.select_score() < with_t.select_score());
+        let disabled = StandardEvaluator::new(Weights {
+            no_tspin: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            disabled.evaluate_state(&state(board, Some(PieceKind::T))),
+            without_t
+        );
     }
-    best
-}
 
-#[inline(always)]
-fn scan_tst(
-    &self,
-    board: &SimpleBoard,
-    max_heights: &[u8],
-    max_height: usize,
-) -> Option<SimpleHole> {
-    for y in (0..max_height).rev() {
-        for x in 0..10 {
-            if max_heights[x] as usize > y {
-                continue;
-            }
-            if !board.tst_possible(x as i32, y as i32) {
-                continue;
-            }
-            if board.tst(x as i32, y as i32).lines == 3 {
-                return Some(SimpleHole { x, y, lines: 3 });
-            }
+    #[test]
+    fn test_t_piece_count() {
+        let mut state = GameState::<BitBoard>::new();
+        // a full bag has one
+        assert_eq!(t_piece_count(&state), 1);
+        state.hold = Some(PieceKind::T);
+        state.queue.push_back(PieceKind::T);
+        state.bag.take(PieceKind::T);
+        assert_eq!(t_piece_count(&state), 2);
+        // the next bag is close
+        for kind in [PieceKind::I, PieceKind::O, PieceKind::L] {
+            state.bag.take(kind);
         }
+        assert_eq!(t_piece_count(&state), 3);
     }
-    None
-}
 
-#[inline(always)]
-fn after_spin(&self, board: &mut SimpleBoard, hole: SimpleHole, piece_shapes: &[u32x4x4]) {
-    let piece = piece_shapes[PieceKind::T as usize];
-    for x in 0..4 {
-        for y in 0..4 {
-            let tx = hole.x + x as usize;
-            let ty = hole.y + y as usize;
-            if piece.cells[y][x] && !board.occupied_unbounded(tx as usize, ty as i32) {
-                board.clear(tx as usize, ty as i32);
+    #[test]
+    fn test_extreme_weights() {
+        let extreme = |weight: i32| {
+            let serde_json::Value::Object(mut weights) =
+                serde_json::to_value(Weights::default()).unwrap()
+            else {
+                unreachable!();
+            };
+            for value in weights.values_mut() {
+                match value {
+                    serde_json::Value::Number(_) => *value = weight.into(),
+                    serde_json::Value::Array(values) => values.fill(weight.into()),
+                    _ => {}
+                }
             }
+            StandardEvaluator::new(serde_json::from_value(weights.into()).unwrap())
+        };
+        // every other cell of 50 rows is a hole
+        let mut board = BitBoard::default();
+        for (x, col) in board.cols.iter_mut().enumerate() {
+            *col = (0x5555_5555_5555_5555 << (x % 2)) & ((1 << 50) - 1);
+        }
+        let state = GameState {
+            board,
+            hold: Some(PieceKind::T),
+            b2b: true,
+            ren: 5,
+            ..GameState::new()
+        };
+        let mv = Move::Place(PieceState::new(PieceKind::T, (4, 51), Rotation::North));
+        let placement = PlacementResult {
+            lines_cleared: 4,
+            ..Default::default()
+        };
+
+        let empty = GameState::<BitBoard>::new();
+        for (weight, saturated) in [(MAX_WEIGHT, i32::MAX), (-MAX_WEIGHT, i32::MIN)] {
+            let evaluator = extreme(weight);
+            let value = evaluator.evaluate_state(&state);
+            assert_eq!(value.value[0], saturated);
+            let reward = evaluator.evaluate_move(mv, placement, &state);
+            let value = value.accumulate(reward).accumulate(reward);
+            assert_eq!(value.select_score(), saturated);
+            // an empty board is far from saturating
+            assert_ne!(evaluator.evaluate_state(&empty).select_score(), saturated);
         }
     }
-}
-*/
 
-/// Evaluates the holes in the playfield.
-///
-/// The first returned value is the number of cells that make up fully enclosed spaces (cavities).
-/// The second is the number of cells that make up partially enclosed spaces (overhangs).
-fn cavities_and_overhangs(board: &BitBoard) -> (i32, i32) {
-    let mut cavities = 0;
-    let mut overhangs = 0;
-
-    for x in 0..10 {
-        for y in 0..board.height_of(x) as i32 {
-            if board.occupied((x, y as i8)) || y >= board.height_of(x) as i32 {
-                continue;
-            }
-
-            if x > 1 {
-                if board.height_of(x - 1) as i32 <= y - 1 && board.height_of(x - 2) as i32 <= y {
-                    overhangs += 1;
-                    continue;
-                }
-            }
-
-            if x < 8 {
-                if board.height_of(x + 1) as i32 <= y - 1 && board.height_of(x + 2) as i32 <= y {
-                    overhangs += 1;
-                    continue;
-                }
+    #[test]
+    fn test_downstack() {
+        let base = StandardEvaluator::new(Weights {
+            downstack: 0,
+            ..Default::default()
+        });
+        for downstack in [1, 2, -3, MAX_WEIGHT] {
+            let evaluator = StandardEvaluator::new(Weights {
+                downstack,
+                ..Default::default()
+            });
+            for ren in [-1, 0, 1] {
+                let state = GameState::<BitBoard> {
+                    ren,
+                    ..GameState::new()
+                };
+                assert_eq!(
+                    evaluator.evaluate_state(&state),
+                    base.evaluate_state(&state)
+                );
             }
-
-            cavities += 1;
+            let state = GameState::<BitBoard> {
+                ren: 4,
+                ..GameState::new()
+            };
+            assert_eq!(
+                evaluator.evaluate_state(&state).value[1],
+                base.evaluate_state(&state).value[1] + 2 * downstack
+            );
         }
     }
 
-    (cavities, overhangs)
+    #[test]
+    fn test_weights_file() {
+        let path = std::env::temp_dir().join(format!("weights-{}.json", std::process::id()));
+        let weights = Weights {
+            cavities: -1,
+            ..Default::default()
+        };
+        weights.save(&path).unwrap();
+        assert_eq!(Weights::load(&path).unwrap(), weights);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(Weights::load(&path), Err(WeightsError::Io(_))));
+    }
 }
@@ -1,21 +1,34 @@
 use core::fmt;
-use std::sync::{atomic::AtomicBool, Arc};
+use std::{
+    str::FromStr,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
 
-use eval::SimpleEvaluator;
+use eval::{SimpleEvaluator, StandardEvaluator};
+pub use eval::{Weights, WeightsError, MAX_WEIGHT};
 use game::tetris::*;
+pub use limits::{SearchLimits, SearchProgress};
 use parking_lot::RwLock;
-use search::Graph;
+pub use policy::{Backup, FinalMove, SearchPolicy, Selection};
+use search::{Graph, Search};
 
 mod eval;
+mod limits;
 mod mem;
+mod policy;
 mod search;
-mod storage;
+pub mod storage;
 
-type E = SimpleEvaluator;
+/// How often [`HikariFireflyBot::wait_for_limits`] checks the search.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Default of [`BotConfig::memory_budget`], 1 GiB.
+pub const DEFAULT_MEMORY_BUDGET: usize = 1 << 30;
 
 #[derive(Debug)]
 pub struct HikariFireflyBot {
-    graph: Arc<RwLock<Option<Graph<E>>>>,
+    graph: Arc<RwLock<Option<Box<dyn Search>>>>,
     abort: Arc<AtomicBool>,
     config: BotConfig,
 }
@@ -30,9 +43,28 @@ impl HikariFireflyBot {
     }
 
     pub fn reset(&self, state: Option<GameState<BitBoard>>) {
-        eprintln!("reset: {:?}", state);
+        match &state {
+            Some(state) => eprintln!("reset:\n{}", state),
+            None => eprintln!("reset: None"),
+        }
         let mut graph = self.graph.write();
-        *graph = state.map(|s| Graph::new(&s, Box::new(E::default())));
+        *graph = state.map(|s| self.new_graph(&s));
+    }
+
+    fn new_graph(&self, state: &GameState<BitBoard>) -> Box<dyn Search> {
+        let policy = self.config.policy;
+        let budget = self.config.memory_budget;
+        match self.config.evaluator {
+            EvaluatorKind::Simple => {
+                Box::new(Graph::new(state, Box::new(SimpleEvaluator), policy, budget))
+            }
+            EvaluatorKind::Standard => Box::new(Graph::new(
+                state,
+                Box::new(StandardEvaluator::new(self.config.weights)),
+                policy,
+                budget,
+            )),
+        }
     }
 
     pub fn start(&self) {
@@ -50,20 +82,97 @@ impl HikariFireflyBot {
         self.abort.store(true, std::sync::atomic::Ordering::Relaxed);
     }
 
+    /// Blocks until the search limits of the config are met.
+    /// Returns at once when the search is not running, as it would never meet them.
+    pub fn wait_for_limits(&self) {
+        let limits = self.config.limits;
+        let mut best = None;
+        let mut best_since = 0;
+        loop {
+            if self.config.num_workers == 0 || self.abort.load(std::sync::atomic::Ordering::Relaxed)
+            {
+                return;
+            }
+            let guard = self.graph.read();
+            let Some(graph) = guard.as_ref() else {
+                return;
+            };
+            let (nodes, _) = graph.search_progress();
+            let best_move = graph.best_move();
+            // the root may have moved since the last poll
+            if best_move != best || nodes < best_since {
+                best = best_move;
+                best_since = nodes;
+            }
+            let progress = SearchProgress {
+                elapsed: graph.search_time(),
+                nodes,
+                stable_nodes: if best.is_some() {
+                    nodes - best_since
+                } else {
+                    0
+                },
+                full: graph.is_full(),
+                exhausted: graph.is_exhausted(),
+            };
+            if limits.is_met(progress) {
+                return;
+            }
+            drop(guard);
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Waits for the search limits, then returns the best plan like [`Self::suggest_with_stats`].
+    pub fn suggest_within_limits(&self) -> Option<(Vec<Move>, Stats)> {
+        self.wait_for_limits();
+        self.suggest_with_stats()
+    }
+
     pub fn suggest(&self) -> Option<Vec<Move>> {
+        self.suggest_with_stats().map(|(moves, _)| moves)
+    }
+
+    /// Returns the best plan along with statistics of the search that found it.
+    pub fn suggest_with_stats(&self) -> Option<(Vec<Move>, Stats)> {
         let graph = self.graph.read();
-        if let Some(graph) = &*graph {
-            let best = graph.best_plan();
-            Some(best.moves)
-        } else {
-            None
+        let graph = graph.as_ref()?;
+        let best = graph.best_plan();
+        let (nodes, depth) = graph.search_progress();
+        let seconds = graph.search_time().as_secs_f64();
+        let stats = Stats {
+            nodes,
+            nps: if seconds > 0.0 {
+                nodes as f64 / seconds
+            } else {
+                0.0
+            },
+            depth,
+            eval: best.score,
+            pv_length: best.moves.len(),
+            graph_nodes: graph.count_nodes(),
+            memory: graph.memory_usage(),
+        };
+        Some((best.moves, stats))
+    }
+
+    /// Moves the root to the child reached by `mv`.
+    /// Fails when there is no graph or the move has not been searched.
+    pub fn pick_move(&self, mv: Move) -> Result<(), PlayError> {
+        let mut graph = self.graph.write();
+        match &mut *graph {
+            Some(graph) => graph.advance(mv),
+            None => Err(PlayError::NoGame),
         }
     }
 
-    pub fn pick_move(&self, mv: Move) {
+    /// Plays the placement the frontend made, which need not be a suggested one.
+    /// The search below the placement is kept when it was searched, and starts over otherwise.
+    pub fn play(&self, piece: PieceState) -> Result<PlayOutcome, PlayError> {
         let mut graph = self.graph.write();
-        if let Some(graph) = &mut *graph {
-            graph.advance(mv).unwrap();
+        match &mut *graph {
+            Some(graph) => graph.play(piece),
+            None => Err(PlayError::NoGame),
         }
     }
 
@@ -76,19 +185,31 @@ impl HikariFireflyBot {
         }
     }
 
-    pub fn stats(&self) -> usize {
-        let graph = self.graph.read();
-        if let Some(graph) = &*graph {
-            graph.count_nodes()
+    /// Replaces the bag after the queue, keeping the search within the queue.
+    pub fn set_bag(&self, bag: SevenBag) {
+        let mut graph = self.graph.write();
+        if let Some(graph) = &mut *graph {
+            graph.set_bag(bag);
         } else {
-            0
+            eprintln!("No graph available");
         }
     }
+
+    /// Returns the game at the root of the search, as the bot tracks it.
+    pub fn game_state(&self) -> Option<GameState<BitBoard>> {
+        self.graph.read().as_ref().map(|graph| graph.game_state())
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.suggest_with_stats()
+            .map(|(_, stats)| stats)
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug)]
 struct Worker {
-    graph: Arc<RwLock<Option<Graph<E>>>>,
+    graph: Arc<RwLock<Option<Box<dyn Search>>>>,
     abort: Arc<AtomicBool>,
 }
 
@@ -104,10 +225,15 @@ impl Worker {
         eprintln!("Worker {} starting", rayon::current_thread_index().unwrap());
 
         while !self.abort.load(std::sync::atomic::Ordering::Relaxed) {
-            let graph = self.graph.read();
-            if let Some(graph) = &*graph {
-                graph.work();
-            } else {
+            let idle = match &*self.graph.read() {
+                Some(graph) if !graph.is_full() => {
+                    graph.work();
+                    false
+                }
+                // wait for a new root without holding the lock
+                _ => true,
+            };
+            if idle {
                 std::thread::sleep(std::time::Duration::from_millis(10));
                 // TODO: replace with future
             }
@@ -117,18 +243,122 @@ impl Worker {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BotConfig {
     pub num_workers: usize,
+    /// Used by [`HikariFireflyBot::wait_for_limits`].
+    pub limits: SearchLimits,
+    pub policy: SearchPolicy,
+    pub evaluator: EvaluatorKind,
+    /// Used by [`EvaluatorKind::Standard`], see [`Weights::load`].
+    pub weights: Weights,
+    /// Bytes the search graph may hold. Once reached, the search stops expanding until the root
+    /// moves and frees the generation behind it.
+    pub memory_budget: Option<usize>,
+}
+
+/// The evaluator the search uses, see the `eval` module.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvaluatorKind {
+    /// Height and holes only.
+    #[default]
+    Simple,
+    /// Weighted stack features and attack rewards.
+    Standard,
+}
+
+impl FromStr for EvaluatorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "simple" => Ok(Self::Simple),
+            "standard" => Ok(Self::Standard),
+            _ => Err(format!(
+                "unknown evaluator {}, expected simple or standard",
+                s
+            )),
+        }
+    }
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            num_workers: 1,
+            limits: SearchLimits::default(),
+            policy: SearchPolicy::default(),
+            evaluator: EvaluatorKind::default(),
+            weights: Weights::default(),
+            memory_budget: Some(DEFAULT_MEMORY_BUDGET),
+        }
+    }
+}
+
+/// How a played placement was applied to the search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayOutcome {
+    /// The root moved to the searched child.
+    Advanced,
+    /// The placement was not searched, so the search starts over from the resulting game.
+    Rebuilt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayError {
+    NoGame,
+    /// The piece is neither the current piece, the held one, nor the next one behind an empty
+    /// hold.
+    WrongPiece,
+    /// The move generator cannot reach the placement.
+    IllegalPlacement,
+    /// The move is not a child of the root, as nothing was searched below it.
+    NotSearched,
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayError::NoGame => write!(f, "no game is running"),
+            PlayError::WrongPiece => write!(f, "the piece cannot be played now"),
+            PlayError::IllegalPlacement => write!(f, "the placement is not legal"),
+            PlayError::NotSearched => write!(f, "the move was not searched"),
+        }
+    }
+}
+
+impl std::error::Error for PlayError {}
+
+/// Search statistics since the root last moved.
+#[derive(Debug, Clone, Default)]
 pub struct Stats {
-    pub nodes: Vec<usize>,
+    /// Number of expanded nodes.
+    pub nodes: u64,
+    pub nps: f64,
+    /// Deepest expansion below the root, in pieces.
+    pub depth: u32,
+    /// Score of the best move.
+    pub eval: i32,
+    /// Number of moves in the principal variation.
+    pub pv_length: usize,
+    /// Number of nodes held by the graph.
+    pub graph_nodes: usize,
+    /// Bytes held by the graph, see [`BotConfig::memory_budget`].
+    pub memory: usize,
 }
 
 impl fmt::Display for Stats {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Stats {{ nodes: {:?} }}", self.nodes)
+        write!(
+            f,
+            "nodes {} nps {:.0} depth {} eval {} pv {} graph {} memory {:.1}MiB",
+            self.nodes,
+            self.nps,
+            self.depth,
+            self.eval,
+            self.pv_length,
+            self.graph_nodes,
+            self.memory as f64 / (1 << 20) as f64
+        )
     }
 }
@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+/// When a suggestion is good enough to answer with, counted since the root last moved.
+///
+/// The minimum time always applies. After it, the first of the other limits that is met ends the
+/// thinking, as does a full or exhausted graph. Without any other limit, the minimum time alone decides.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub min_time: Duration,
+    pub max_time: Option<Duration>,
+    /// Number of expanded nodes.
+    pub nodes: Option<u64>,
+    /// Number of expansions during which the best move did not change.
+    pub stable_nodes: Option<u64>,
+}
+
+/// How far the search has come, as seen by [`SearchLimits::is_met`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchProgress {
+    pub elapsed: Duration,
+    pub nodes: u64,
+    /// Expansions since the best move last changed.
+    pub stable_nodes: u64,
+    /// The graph reached its memory budget, so the search no longer grows.
+    pub full: bool,
+    /// The root is dead, so the search no longer grows.
+    pub exhausted: bool,
+}
+
+impl SearchLimits {
+    pub fn is_met(&self, progress: SearchProgress) -> bool {
+        if progress.elapsed < self.min_time {
+            return false;
+        }
+        if progress.full || progress.exhausted {
+            return true;
+        }
+        match (self.max_time, self.nodes, self.stable_nodes) {
+            (None, None, None) => true,
+            (max_time, nodes, stable_nodes) => {
+                reached(max_time, progress.elapsed)
+                    || reached(nodes, progress.nodes)
+                    || reached(stable_nodes, progress.stable_nodes)
+            }
+        }
+    }
+}
+
+fn reached<T: PartialOrd>(limit: Option<T>, value: T) -> bool {
+    limit.is_some_and(|limit| value >= limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BotConfig, EvaluatorKind, HikariFireflyBot};
+    use game::tetris::{BitBoard, GameState};
+    use std::time::Instant;
+
+    fn progress(millis: u64, nodes: u64, stable_nodes: u64) -> SearchProgress {
+        SearchProgress {
+            elapsed: Duration::from_millis(millis),
+            nodes,
+            stable_nodes,
+            full: false,
+            exhausted: false,
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        assert!(SearchLimits::default().is_met(progress(0, 0, 0)));
+
+        let min_time = SearchLimits {
+            min_time: Duration::from_millis(100),
+            ..Default::default()
+        };
+        assert!(!min_time.is_met(progress(99, 1000, 1000)));
+        assert!(min_time.is_met(progress(100, 0, 0)));
+
+        let limits = SearchLimits {
+            min_time: Duration::from_millis(100),
+            max_time: Some(Duration::from_millis(500)),
+            nodes: Some(1000),
+            stable_nodes: Some(200),
+        };
+        assert!(!limits.is_met(progress(50, 5000, 5000)));
+        assert!(!limits.is_met(progress(200, 999, 199)));
+        assert!(limits.is_met(progress(200, 1000, 0)));
+        assert!(limits.is_met(progress(200, 300, 200)));
+        assert!(limits.is_met(progress(500, 0, 0)));
+
+        let full = SearchProgress {
+            full: true,
+            ..progress(200, 0, 0)
+        };
+        assert!(limits.is_met(full));
+        assert!(!limits.is_met(SearchProgress {
+            elapsed: Duration::from_millis(50),
+            ..full
+        }));
+        assert!(limits.is_met(SearchProgress {
+            exhausted: true,
+            ..progress(200, 0, 0)
+        }));
+    }
+
+    #[test]
+    fn test_wait_for_limits() {
+        let mut state = GameState::new();
+        for _ in 0..7 {
+            state.fulfill_queue();
+        }
+        // only the node limit ends the wait, so that slow machines do the same work
+        let config = BotConfig {
+            num_workers: 1,
+            limits: SearchLimits {
+                min_time: Duration::from_millis(20),
+                max_time: None,
+                nodes: Some(50),
+                stable_nodes: None,
+            },
+            ..Default::default()
+        };
+        let bot = HikariFireflyBot::new(config);
+        bot.reset(Some(state.clone()));
+
+        // not searching, so there is nothing to wait for
+        let start = Instant::now();
+        bot.wait_for_limits();
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        bot.start();
+        let (plan, stats) = bot.suggest_within_limits().unwrap();
+        bot.stop();
+        assert!(!plan.is_empty());
+        assert!(stats.nodes >= 50);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // the same holds for the other evaluator
+        let bot = HikariFireflyBot::new(BotConfig {
+            evaluator: EvaluatorKind::Standard,
+            ..config
+        });
+        bot.reset(Some(state));
+        bot.start();
+        let (plan, stats) = bot.suggest_within_limits().unwrap();
+        bot.stop();
+        assert!(!plan.is_empty());
+        assert!(stats.nodes >= 50);
+    }
+
+    #[test]
+    fn test_wait_for_exhausted_search() {
+        // every placement is above the sky, as every row has a covered hole
+        let mut state = GameState::<BitBoard>::new();
+        for (x, col) in state.board.cols.iter_mut().enumerate() {
+            let height = if x == 9 { 21 } else { 20 };
+            *col = ((1 << height) - 1) & !(1 << x | 1 << (x + 10));
+        }
+        for _ in 0..7 {
+            state.fulfill_queue();
+        }
+        let bot = HikariFireflyBot::new(BotConfig {
+            num_workers: 1,
+            limits: SearchLimits {
+                nodes: Some(u64::MAX),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        bot.reset(Some(state));
+
+        // the node limit is never reached once the root is dead
+        let start = Instant::now();
+        bot.start();
+        bot.wait_for_limits();
+        bot.stop();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
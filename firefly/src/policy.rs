@@ -0,0 +1,200 @@
+/// How the search picks actions to explore, and the move to play.
+///
+/// Scores are normalised between the siblings of a node, so the exploration constant does not
+/// depend on the scale of the evaluator. Every action is valued as soon as its parent is expanded,
+/// so unvisited actions are not forced before visited ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchPolicy {
+    pub selection: Selection,
+    pub exploration: f32,
+    /// Softmax temperature of the prior over the normalised scores that actions had when they were
+    /// expanded. Without it, the prior is uniform. Only used by [`Selection::Puct`].
+    pub prior_temperature: Option<f32>,
+    pub final_move: FinalMove,
+    pub backup: Backup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// `q + c * sqrt(ln(n + 1) / (visits + 1))`
+    Uct,
+    /// `q + c * prior * sqrt(n) / (visits + 1)`
+    Puct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalMove {
+    /// The most visited move, as it is the most searched one.
+    Visits,
+    /// The move with the best score.
+    Value,
+}
+
+/// How a node is valued from its actions once expanded. Past the known queue, the values of the
+/// pieces are averaged by their probability, so [`Backup::Max`] is an expectimax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backup {
+    /// The best action.
+    Max,
+    /// The mean of the visited actions weighted by their visits, or the best action when none was
+    /// visited yet.
+    Mean,
+}
+
+/// What the policy knows about an action.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub score: i32,
+    pub visits: u32,
+    pub prior: f32,
+}
+
+impl Default for SearchPolicy {
+    fn default() -> Self {
+        Self {
+            selection: Selection::Puct,
+            exploration: 1.0,
+            prior_temperature: Some(0.25),
+            final_move: FinalMove::Visits,
+            backup: Backup::Max,
+        }
+    }
+}
+
+impl SearchPolicy {
+    /// Returns the index of the candidate to explore.
+    pub fn select(&self, candidates: &[Candidate]) -> usize {
+        let normalised = normalise(candidates.iter().map(|c| c.score));
+        let total_visits = candidates.iter().map(|c| c.visits as f32).sum::<f32>();
+        let bonus = |c: &Candidate| match self.selection {
+            Selection::Uct => ((total_visits + 1.0).ln() / (c.visits as f32 + 1.0)).sqrt(),
+            Selection::Puct => c.prior * total_visits.sqrt() / (c.visits as f32 + 1.0),
+        };
+        candidates
+            .iter()
+            .zip(normalised)
+            .map(|(c, q)| q + self.exploration * bonus(c))
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+            .expect("no candidates")
+    }
+
+    /// Returns the prior of each action from the scores the actions were expanded with.
+    pub fn priors(&self, scores: &[i32]) -> Vec<f32> {
+        let Some(temperature) = self.prior_temperature else {
+            return vec![1.0 / scores.len() as f32; scores.len()];
+        };
+        let weights = normalise(scores.iter().copied())
+            .map(|q| ((q - 1.0) / temperature).exp())
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<f32>();
+        weights.into_iter().map(|w| w / total).collect()
+    }
+
+    /// Returns the index of the candidate to play, if any.
+    pub fn best(&self, candidates: &[Candidate]) -> Option<usize> {
+        let key = |c: &Candidate| match self.final_move {
+            FinalMove::Visits => (c.visits, c.score),
+            FinalMove::Value => (0, c.score),
+        };
+        candidates
+            .iter()
+            .enumerate()
+            // the first one wins ties, as it is the best sorted
+            .rev()
+            .max_by_key(|(_, c)| key(c))
+            .map(|(i, _)| i)
+    }
+}
+
+/// Maps the scores to `0..=1` by the range of the siblings.
+fn normalise(scores: impl Iterator<Item = i32> + Clone) -> impl Iterator<Item = f32> {
+    // in i64, as saturated scores span the whole i32 range
+    let min = scores.clone().min().unwrap_or_default() as i64;
+    let max = scores.clone().max().unwrap_or_default() as i64;
+    scores.map(move |score| {
+        if max > min {
+            (score as i64 - min) as f32 / (max - min) as f32
+        } else {
+            0.5
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(score: i32, visits: u32) -> Candidate {
+        Candidate {
+            score,
+            visits,
+            prior: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let greedy = SearchPolicy {
+            exploration: 0.0,
+            ..Default::default()
+        };
+        let candidates = [candidate(-100, 10), candidate(-40, 10)];
+        assert_eq!(greedy.select(&candidates), 1);
+
+        // the scale of the scores does not matter
+        for selection in [Selection::Uct, Selection::Puct] {
+            let policy = SearchPolicy {
+                selection,
+                exploration: 1.0,
+                ..Default::default()
+            };
+            let small = [candidate(10, 40), candidate(11, 1)];
+            let large = [candidate(10000, 40), candidate(11000, 1)];
+            assert_eq!(policy.select(&small), 1);
+            assert_eq!(policy.select(&large), 1);
+
+            // a much less visited action is explored even though it scores worse
+            let candidates = [candidate(11, 200), candidate(10, 1)];
+            assert_eq!(policy.select(&candidates), 1);
+        }
+
+        // saturated scores keep their order
+        let extremes = [candidate(i32::MIN, 10), candidate(i32::MAX, 10)];
+        assert_eq!(greedy.select(&extremes), 1);
+        assert_eq!(
+            normalise([i32::MIN, 0, i32::MAX].into_iter()).collect::<Vec<_>>(),
+            [0.0, 0.5, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_priors() {
+        let uniform = SearchPolicy {
+            prior_temperature: None,
+            ..Default::default()
+        };
+        assert_eq!(uniform.priors(&[1, 5, 3, 3]), [0.25; 4]);
+
+        let priors = SearchPolicy::default().priors(&[-50, 0, -20]);
+        assert!((priors.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(priors[1] > priors[2] && priors[2] > priors[0]);
+
+        let priors = SearchPolicy::default().priors(&[i32::MIN, i32::MAX]);
+        assert!(priors[1] > priors[0]);
+    }
+
+    #[test]
+    fn test_best() {
+        let candidates = [candidate(5, 3), candidate(8, 2), candidate(5, 3)];
+        let by_visits = SearchPolicy::default();
+        assert_eq!(by_visits.best(&candidates), Some(0));
+        let by_value = SearchPolicy {
+            final_move: FinalMove::Value,
+            ..Default::default()
+        };
+        assert_eq!(by_value.best(&candidates), Some(1));
+        assert_eq!(by_value.best(&[]), None);
+    }
+}
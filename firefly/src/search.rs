@@ -1,78 +1,170 @@
-use std::{collections::VecDeque, ops::ControlFlow};
+use std::{
+    collections::VecDeque,
+    fmt,
+    mem::size_of,
+    ops::ControlFlow,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 
 use dashmap::DashMap;
-use game::tetris::*;
+use enumset::EnumSet;
+use game::tetris::{zobrist::HashedBoard, *};
 use once_cell::sync::Lazy;
-use rand::{distributions::WeightedIndex, prelude::*};
+use parking_lot::Mutex;
+use rand::prelude::*;
 use smallvec::{smallvec, SmallVec};
 
 use crate::{
     eval::{Accumulator, Evaluator},
-    storage::{Index, IndexRange, Rack},
+    policy::{Backup, Candidate, SearchPolicy},
+    storage::{Arena, Index, IndexRange},
+    PlayError, PlayOutcome,
 };
 
+/// Number of expansions between two measures of the memory, so the budget may be overshot by what
+/// that many expansions allocate.
+const MEMORY_CHECK_INTERVAL: u64 = 64;
+
 #[derive(Debug)]
 pub struct Graph<E: Evaluator> {
     root_gen: Box<Generation<E>>,
     root_state: State,
     queue: VecDeque<PieceKind>,
+    // after the queue, as in GameState. Taken as the real bag, since speculated pieces are equally
+    // likely: an uncertain bag is settled before it gets here, as with `BagTracker::likely_bag`
+    bag: SevenBag,
     evaluator: Box<E>,
+    policy: SearchPolicy,
+    // counted since the root last moved
+    expanded: AtomicU64,
+    max_depth: AtomicU32,
+    search_start: Instant,
+    /// Bytes the generations may hold before the search stops expanding.
+    memory_budget: Option<usize>,
+    // as last measured by memory_usage
+    memory: AtomicUsize,
 }
 
+/// The nodes at one depth below the root.
+///
+/// Each node has its own lock, which also guards its actions. A worker holding the lock of a node
+/// may lock nodes of the next generation, but never another node of the same one.
 #[derive(Debug)]
 pub struct Generation<E: Evaluator> {
-    nodes_rack: Rack<Node<E>>,
-    actions_rack: Rack<Action<E>>,
+    nodes: Arena<Mutex<Node<E>>>,
+    actions: Arena<Action<E>>,
     lookup: DashMap<State, Index>,
     parents_lookup: DashMap<Index, SmallVec<[Index; 3]>>,
+    // bytes of the parents that no longer fit inline
+    spilled_parents: AtomicUsize,
     next: Lazy<Box<Generation<E>>>,
 }
 
-#[derive(Debug, Clone)]
+// not Clone, as a copy would alias the actions of the node, see `Generation::with_actions`
+#[derive(Debug)]
 pub struct Node<E: Evaluator> {
+    // the evaluation of the state until expanded, then backed up from the actions
+    /// Past the known queue, the actions of every possible piece, grouped by piece.
     children: Option<ChildData>,
     value: E::Accumulator,
     dead: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct ChildData(IndexRange);
 
 #[derive(Debug)]
 pub struct Action<E: Evaluator> {
     node: Index,
-    mv: Move,
+    /// `None` when the piece cannot spawn, which is a death no move leads to.
+    mv: Option<Move>,
     current_piece: PieceKind,
     reward: E::TransientReward,
     acc: E::Accumulator,
     dead: bool, // Move itself is dead, or the resulting state is dead
+    // number of selections, counted when selected so that workers spread out
     visits: u32,
+    // among the actions of the same piece
+    prior: f32,
 }
 
 impl<E: Evaluator> Graph<E> {
-    pub fn new(state: &GameState<BitBoard>, evaluator: Box<E>) -> Self {
+    pub fn new(
+        state: &GameState<BitBoard>,
+        evaluator: Box<E>,
+        policy: SearchPolicy,
+        memory_budget: Option<usize>,
+    ) -> Self {
+        let mut graph = Self {
+            root_gen: Box::default(),
+            root_state: State::new(state),
+            queue: state.queue.clone(),
+            bag: state.bag,
+            evaluator,
+            policy,
+            expanded: AtomicU64::new(0),
+            max_depth: AtomicU32::new(0),
+            search_start: Instant::now(),
+            memory_budget,
+            memory: AtomicUsize::new(0),
+        };
+        graph.set_root(state);
+        graph
+    }
+
+    /// Discards the search and starts over from `state`.
+    fn set_root(&mut self, state: &GameState<BitBoard>) {
         let root_gen = Box::new(Generation::new());
         let root_state = State::new(state);
 
         // init first node
         let root_node = Node {
             children: None,
-            value: evaluator.evaluate_state(state),
+            value: self.evaluator.evaluate_state(state),
             dead: false,
         };
-        let root_node = root_gen.nodes_rack.alloc(root_node);
+        let root_node = root_gen.nodes.alloc(Mutex::new(root_node));
         root_gen.parents_lookup.insert(root_node, smallvec![]);
-        root_gen.lookup.insert(State::new(state), root_node);
+        root_gen.lookup.insert(root_state.clone(), root_node);
 
-        Self {
-            root_gen,
-            root_state,
-            queue: state.queue.clone(),
-            evaluator,
+        self.root_gen = root_gen;
+        self.root_state = root_state;
+        self.queue = state.queue.clone();
+        self.bag = state.bag;
+        self.reset_progress();
+        self.memory_usage();
+    }
+
+    fn reset_progress(&mut self) {
+        *self.expanded.get_mut() = 0;
+        *self.max_depth.get_mut() = 0;
+        self.search_start = Instant::now();
+    }
+
+    /// Returns whether the root is dead, so that the search cannot grow any further.
+    pub fn is_exhausted(&self) -> bool {
+        self.root_gen
+            .find_node_index(&self.root_state)
+            .is_some_and(|index| self.root_gen.with_node(index, |node| node.dead))
+    }
+
+    /// Returns the game at the root.
+    pub fn game_state(&self) -> GameState<BitBoard> {
+        GameState {
+            board: self.root_state.board.board().clone(),
+            hold: self.root_state.hold,
+            queue: self.queue.clone(),
+            bag: self.bag,
+            b2b: self.root_state.b2b,
+            ren: self.root_state.ren,
         }
     }
 
     pub fn work(&self) {
+        if self.is_full() {
+            return;
+        }
         let mut gen = &*self.root_gen;
         let mut state = self.root_state.clone();
         let mut queue = self.queue.clone();
@@ -82,22 +174,33 @@ impl<E: Evaluator> Graph<E> {
         let mut depth = 0;
         loop {
             // Dig down tree until we reach a leaf
-            match gen.select(&state, depth) {
+            match gen.select(&state, &self.policy) {
                 SelectResult::Ok(action) => {
                     _action_history.push(action);
-                    state.advance(action.mv, action.current_piece);
-                    queue.pop_front().unwrap();
+                    state.advance(
+                        action.mv.expect("dead actions are not selected"),
+                        action.current_piece,
+                    );
+                    // empty once speculating
+                    queue.pop_front();
                     gen = &*gen.next;
                     gen_history.push(gen);
                     depth += 1;
                 }
                 SelectResult::Expand => {
-                    if queue.is_empty() {
-                        // TODO: speculate
-                        return;
+                    // past the known queue, speculate on every piece the bag can deal
+                    let pieces = match queue.pop_front() {
+                        Some(piece) => EnumSet::only(piece),
+                        None => state.possible_pieces(),
+                    };
+                    gen.expand(&state, pieces, self.evaluator.as_ref(), &self.policy);
+                    let expanded = self.expanded.fetch_add(1, Ordering::Relaxed) + 1;
+                    if expanded.is_multiple_of(MEMORY_CHECK_INTERVAL) {
+                        self.memory_usage();
                     }
-                    gen.expand(&state, queue.pop_front().unwrap(), self.evaluator.as_ref());
-                    Self::backprop(gen_history, &state);
+                    self.max_depth.fetch_max(depth, Ordering::Relaxed);
+                    let index = gen.find_node_index(&state).unwrap();
+                    Self::backprop(&gen_history, vec![index], &self.policy);
                     break;
                 }
                 SelectResult::Failed => {
@@ -107,60 +210,95 @@ impl<E: Evaluator> Graph<E> {
         }
     }
 
+    /// Returns the number of expansions, and the deepest one, since the root last moved.
+    pub fn search_progress(&self) -> (u64, u32) {
+        (
+            self.expanded.load(Ordering::Relaxed),
+            self.max_depth.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Returns the time since the root last moved.
+    pub fn search_time(&self) -> Duration {
+        self.search_start.elapsed()
+    }
+
     pub fn count_nodes(&self) -> usize {
-        let mut count = 0;
-        let mut gen = &*self.root_gen;
-        loop {
-            let c = gen.nodes_rack.len();
-            if c == 0 {
-                break;
-            }
-            count += c;
-            gen = &*gen.next;
-        }
-        count
+        self.generations().map(|gen| gen.nodes.len()).sum()
+    }
+
+    /// Measures the bytes held by the generations, which is what the budget limits.
+    pub fn memory_usage(&self) -> usize {
+        let memory = self.generations().map(Generation::memory).sum();
+        self.memory.store(memory, Ordering::Relaxed);
+        memory
+    }
+
+    /// Returns whether the last measure of the memory reached the budget. The search stops
+    /// expanding until the root moves and frees the generations behind it.
+    pub fn is_full(&self) -> bool {
+        self.memory_budget
+            .is_some_and(|budget| self.memory.load(Ordering::Relaxed) >= budget)
+    }
+
+    /// Returns the generations from the root, without creating the ones nothing reached yet.
+    fn generations(&self) -> impl Iterator<Item = &Generation<E>> {
+        std::iter::successors(Some(&*self.root_gen), |gen| {
+            Lazy::get(&gen.next).map(|next| &**next)
+        })
     }
 
     // MARK: - Backpropagate
-    fn backprop(gen_history: Vec<&Generation<E>>, state: &State) {
+    /// Updates the actions and values of the nodes in the last generation of `gen_history`, then
+    /// those of their parents whenever a value changed, up to the root.
+    ///
+    /// The parents of `to_update` are always updated, as the caller already changed those nodes,
+    /// for instance by expanding a node into a dead one.
+    fn backprop(gen_history: &[&Generation<E>], mut to_update: Vec<Index>, policy: &SearchPolicy) {
         puffin::profile_function!();
-        let first = gen_history.last().unwrap().find_node_index(state).unwrap();
-        let mut to_update = vec![first];
 
-        for &current_gen in gen_history.iter().rev() {
-            let mut next_to_update = vec![];
+        for (depth, &current_gen) in gen_history.iter().rev().enumerate() {
+            let mut next_to_update: Vec<Index> = vec![];
             for index in to_update.iter() {
                 current_gen.with_node(*index, |node| {
+                    let (value, dead) = (node.value, node.dead);
                     if !node.dead {
                         // Update accumulated eval of self
-                        let mut children = current_gen
-                            .actions_rack
-                            .get_range(node.children.as_ref().unwrap().0);
-
-                        // Update accumulated eval of children
-                        children.iter_mut().for_each(|action| {
-                            let child_node = current_gen.next.nodes_rack.get(action.node);
-                            action.visits += 1;
-                            action.acc = child_node.value.accumulate(action.reward);
-                            action.dead |= child_node.dead;
-                        });
+                        let updated = current_gen.with_actions(node, |children| {
+                            // Update accumulated eval of children
+                            children.iter_mut().for_each(|action| {
+                                let child_node = current_gen.next.nodes.get(action.node).lock();
+                                action.acc = child_node.value.accumulate(action.reward);
+                                action.dead |= child_node.dead;
+                            });
 
-                        // if all of the children ended up dead, mark this node as dead
-                        if children.iter().all(|action| action.dead) {
-                            node.dead = true;
-                        }
+                            // best-to-worst sort, keeping speculated pieces grouped
+                            children.sort_by(|a, b| {
+                                a.current_piece.cmp(&b.current_piece).then_with(|| {
+                                    match (a.dead, b.dead) {
+                                        (false, false) => {
+                                            b.acc.select_score().cmp(&a.acc.select_score())
+                                        }
+                                        _ => a.dead.cmp(&b.dead),
+                                    }
+                                })
+                            });
 
-                        // best-to-worst sort
-                        children.sort_by(|a, b| match (a.dead, b.dead) {
-                            (false, false) => b.acc.select_score().cmp(&a.acc.select_score()),
-                            _ => a.dead.cmp(&b.dead),
+                            // if all of the children ended up dead, mark this node as dead
+                            let all_dead = children.iter().all(|action| action.dead);
+                            (backup(children, policy.backup), all_dead)
                         });
+                        let (backed_up, all_dead) = updated.expect("updated nodes are expanded");
+                        node.value = backed_up;
+                        node.dead = all_dead;
+                    }
+                    if depth > 0 && (node.value, node.dead) == (value, dead) {
+                        // the parents would not change
+                        return;
                     }
-                    // let best = children.first().unwrap();
-                    // node.acc = best.acc;
 
-                    // Enqueue parents of the current node
-                    // TODO: find a better way to lock parents
+                    // Enqueue parents of the current node, which are updated once this lock is
+                    // released
                     next_to_update.extend(
                         current_gen
                             .parents_lookup
@@ -170,10 +308,29 @@ impl<E: Evaluator> Graph<E> {
                     );
                 });
             }
+            // shared parents are updated once
+            next_to_update.sort_by_key(|index| (index.shelf, index.slot));
+            next_to_update.dedup();
             to_update = next_to_update;
         }
     }
 
+    /// Returns the best move at the root, without walking the rest of the plan.
+    pub fn best_move(&self) -> Option<Move> {
+        if self.queue.is_empty() {
+            // the root only speculates
+            return None;
+        }
+        let index = self.root_gen.find_node_index(&self.root_state)?;
+        self.root_gen.with_node(index, |node| {
+            self.root_gen
+                .with_actions(node, |actions| {
+                    best_action(actions, &self.policy).and_then(|action| action.mv)
+                })
+                .flatten()
+        })
+    }
+
     pub fn best_plan(&self) -> Plan {
         let mut gen = &*self.root_gen;
         let mut state = self.root_state.clone();
@@ -181,14 +338,21 @@ impl<E: Evaluator> Graph<E> {
         let mut value = None;
         let mut is_dead = None;
         let mut first_children = None;
+        let mut score = None;
 
         for &current_piece in self.queue.iter() {
             let index = gen.find_node_index(&state).unwrap();
             match gen.with_node(index, |node| {
-                if let Some(children) = node.children.as_ref() {
-                    let best =
-                        gen.with_actions(children.0, |actions| actions.first().unwrap().clone());
-                    moves.push(best.mv);
+                let children = node.children.as_ref().map(|children| children.0);
+                let best =
+                    gen.with_actions(node, |actions| *best_action(actions, &self.policy).unwrap());
+                if let (Some(children), Some(best), Some(mv)) =
+                    (children, best, best.and_then(|best| best.mv))
+                {
+                    moves.push(mv);
+                    if score.is_none() {
+                        score = Some(best.acc.select_score());
+                    }
                     if value.is_none() {
                         value = Some(node.value);
                     }
@@ -196,9 +360,9 @@ impl<E: Evaluator> Graph<E> {
                         is_dead = Some(node.dead);
                     }
                     if first_children.is_none() {
-                        first_children = Some(children.0);
+                        first_children = Some(children);
                     }
-                    ControlFlow::Continue(best.mv)
+                    ControlFlow::Continue(mv)
                 } else {
                     ControlFlow::Break(())
                 }
@@ -216,104 +380,413 @@ impl<E: Evaluator> Graph<E> {
         );
         eprintln!("Nodes: {}", self.count_nodes());
 
-        Plan { moves, score: 0 }
+        Plan {
+            moves,
+            score: score.unwrap_or_default(),
+        }
     }
 
-    pub fn advance(&mut self, mv: Move) -> Result<(), ()> {
-        let current_piece = self.queue.pop_front().unwrap();
+    pub fn advance(&mut self, mv: Move) -> Result<(), PlayError> {
+        let &current_piece = self.queue.front().ok_or(PlayError::WrongPiece)?;
 
         let index = self.root_gen.find_node_index(&self.root_state).unwrap();
-        self.root_gen.with_node(index, |node| {
-            if let Some(children) = node.children.as_ref() {
-                self.root_gen.with_actions(children.0, |actions| {
-                    if actions.iter().find(|action| action.mv == mv).is_some() {
-                        Ok(())
-                    } else {
-                        Err(())
-                    }
-                })
-            } else {
-                Err(())
-            }
-        })?;
+        let searched = self.root_gen.with_node(index, |node| {
+            self.root_gen.with_actions(node, |actions| {
+                actions.iter().any(|action| action.mv == Some(mv))
+            })
+        });
+        if searched != Some(true) {
+            return Err(PlayError::NotSearched);
+        }
+        self.queue.pop_front();
         self.root_state.advance(mv, current_piece);
+        self.reset_progress();
 
         let next = std::mem::take(&mut *self.root_gen.next);
         self.root_gen = next;
+        self.memory_usage();
         Ok(())
     }
 
+    /// Plays a placement chosen by the frontend, which may differ from the suggested one.
+    ///
+    /// A hold is inserted when the piece comes from the queue behind an empty hold. The search
+    /// below the matching action is kept, and the search starts over when there is none.
+    pub fn play(&mut self, piece: PieceState) -> Result<PlayOutcome, PlayError> {
+        let moves: SmallVec<[Move; 2]> = if self.needs_hold(piece.pos.kind)? {
+            smallvec![Move::Hold, Move::Place(piece)]
+        } else {
+            smallvec![Move::Place(piece)]
+        };
+        if !self.is_legal(&moves, piece) {
+            return Err(PlayError::IllegalPlacement);
+        }
+
+        for (i, &mv) in moves.iter().enumerate() {
+            let searched = match mv {
+                Move::Hold => self.searched_move(|searched| searched == Move::Hold),
+                Move::Place(_) => self.searched_move(|searched| match searched {
+                    Move::Place(searched) => same_placement(searched, piece),
+                    Move::Hold => false,
+                }),
+            };
+            match searched {
+                Some(searched) => self.advance(searched).expect("move was searched"),
+                None => {
+                    let mut state = self.game_state();
+                    for &mv in &moves[i..] {
+                        state.advance(mv);
+                    }
+                    eprintln!("resync: {:?} was not searched", piece);
+                    self.set_root(&state);
+                    return Ok(PlayOutcome::Rebuilt);
+                }
+            }
+        }
+        Ok(PlayOutcome::Advanced)
+    }
+
+    /// Returns whether the move generator reaches `piece` after the holds of `moves`.
+    fn is_legal(&self, moves: &[Move], piece: PieceState) -> bool {
+        let mut state = self.game_state();
+        for &mv in moves.iter().filter(|&&mv| mv == Move::Hold) {
+            state.advance(mv);
+        }
+        state.legal_moves(true).is_ok_and(|gen| {
+            gen.moves().into_iter().any(|mv| match mv {
+                Move::Place(legal) => same_placement(legal, piece),
+                Move::Hold => false,
+            })
+        })
+    }
+
+    /// Returns whether a piece of `kind` has to be held out of the queue before it is placed.
+    fn needs_hold(&self, kind: PieceKind) -> Result<bool, PlayError> {
+        let current = *self.queue.front().ok_or(PlayError::WrongPiece)?;
+        match self.root_state.hold {
+            _ if kind == current => Ok(false),
+            // swapped with the current piece
+            Some(hold) if hold == kind => Ok(false),
+            None if self.queue.get(1) == Some(&kind) => Ok(true),
+            _ => Err(PlayError::WrongPiece),
+        }
+    }
+
+    /// Returns the searched move at the root that matches.
+    fn searched_move(&self, matches: impl Fn(Move) -> bool) -> Option<Move> {
+        let index = self.root_gen.find_node_index(&self.root_state)?;
+        self.root_gen.with_node(index, |node| {
+            self.root_gen
+                .with_actions(node, |actions| {
+                    actions
+                        .iter()
+                        .filter_map(|action| action.mv)
+                        .find(|&mv| matches(mv))
+                })
+                .flatten()
+        })
+    }
+
     pub fn add_piece(&mut self, piece: PieceKind) {
         self.queue.push_back(piece);
+        // the frontend knows better than the bag
+        draw(&mut self.bag, piece);
+
+        self.reveal(self.queue.len() - 1, piece);
+    }
+
+    /// Replaces the bag after the queue, as when the phase of the bag turns out to differ.
+    ///
+    /// The nodes within the queue are kept under the new bag. The speculation past the queue is
+    /// kept when every piece leaves both bags the same, and is expanded again otherwise.
+    pub fn set_bag(&mut self, bag: SevenBag) {
+        if bag == self.bag {
+            return;
+        }
+        // the bags of the nodes at each depth within the queue, as in `State`
+        let bags = |bag: SevenBag| {
+            let mut bag = State::new(&GameState {
+                bag,
+                queue: self.queue.clone(),
+                ..GameState::new()
+            })
+            .bag;
+            let mut bags = vec![bag];
+            for &piece in &self.queue {
+                draw(&mut bag, piece);
+                bags.push(bag);
+            }
+            bags
+        };
+        let (old, new) = (bags(self.bag), bags(bag));
+        let (&old_last, &new_last) = (old.last().unwrap(), new.last().unwrap());
+        let same_speculation = EnumSet::<PieceKind>::all().iter().all(|piece| {
+            let (mut old, mut new) = (old_last, new_last);
+            draw(&mut old, piece);
+            draw(&mut new, piece);
+            old == new
+        });
+        self.bag = bag;
+        self.root_state.bag = new[0];
+
+        let mut gen = &mut *self.root_gen;
+        for depth in 0..new.len() {
+            let entries = gen
+                .lookup
+                .iter()
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect::<Vec<_>>();
+            gen.lookup.clear();
+            for (mut state, index) in entries {
+                debug_assert_eq!(state.bag, old[depth]);
+                state.bag = new[depth];
+                gen.lookup.insert(state, index);
+            }
+
+            if depth == new.len() - 1 {
+                if !same_speculation {
+                    // the speculated pieces or the bags they lead to changed
+                    for entry in gen.lookup.iter() {
+                        gen.with_node(*entry.value(), |node| node.children = None);
+                    }
+                    gen.next = Lazy::new(Box::default);
+                }
+                break;
+            }
+            match Lazy::get_mut(&mut gen.next) {
+                Some(next) => gen = next,
+                // nothing was searched this deep
+                None => break,
+            }
+        }
+        self.memory_usage();
+    }
+
+    /// Keeps the branch of `piece` at the nodes that speculated on the piece at `depth`, and prunes
+    /// the other pieces.
+    fn reveal(&self, depth: usize, piece: PieceKind) {
+        let mut gen_history = vec![&*self.root_gen];
+        for _ in 0..depth {
+            match Lazy::get(&gen_history.last().unwrap().next) {
+                Some(next) => gen_history.push(next),
+                // nothing was searched this deep
+                None => return,
+            }
+        }
+        let gen = *gen_history.last().unwrap();
+
+        let mut pruned = vec![];
+        for entry in gen.lookup.iter() {
+            let index = *entry.value();
+            gen.with_node(index, |node| {
+                let Some(ChildData(range)) = node.children else {
+                    return;
+                };
+                let kept = gen.with_actions(node, |actions| {
+                    // the actions of a piece are contiguous
+                    let start = actions.iter().position(|a| a.current_piece == piece);
+                    let end = actions.iter().rposition(|a| a.current_piece == piece);
+                    let kept = start.zip(end).map(|(start, end)| start..end + 1);
+                    for (i, action) in actions.iter().enumerate() {
+                        if kept.as_ref().is_some_and(|kept| kept.contains(&i)) {
+                            continue;
+                        }
+                        // unlink the pruned child
+                        if let Some(mut parents) = gen.next.parents_lookup.get_mut(&action.node) {
+                            if let Some(i) = parents.iter().position(|&parent| parent == index) {
+                                parents.swap_remove(i);
+                            }
+                        }
+                    }
+                    kept
+                });
+                match kept.flatten() {
+                    Some(kept) if kept.len() == range.end - range.start => {}
+                    Some(kept) => {
+                        node.children = Some(ChildData(IndexRange {
+                            start: range.start + kept.start,
+                            end: range.start + kept.end,
+                            ..range
+                        }));
+                        pruned.push(index);
+                    }
+                    // the piece was not expected, expand the node again
+                    None => node.children = None,
+                }
+            });
+        }
+
+        if !pruned.is_empty() {
+            // the kept branch may be dead
+            Self::backprop(&gen_history, pruned, &self.policy);
+        }
+    }
+}
+
+/// A [`Graph`] whose evaluator is only known at runtime.
+pub trait Search: fmt::Debug + Send + Sync {
+    fn game_state(&self) -> GameState<BitBoard>;
+    fn work(&self);
+    fn search_progress(&self) -> (u64, u32);
+    fn search_time(&self) -> Duration;
+    fn count_nodes(&self) -> usize;
+    fn memory_usage(&self) -> usize;
+    fn is_full(&self) -> bool;
+    fn is_exhausted(&self) -> bool;
+    fn best_move(&self) -> Option<Move>;
+    fn best_plan(&self) -> Plan;
+    fn advance(&mut self, mv: Move) -> Result<(), PlayError>;
+    fn play(&mut self, piece: PieceState) -> Result<PlayOutcome, PlayError>;
+    fn add_piece(&mut self, piece: PieceKind);
+    fn set_bag(&mut self, bag: SevenBag);
+}
+
+impl<E: Evaluator> Search for Graph<E> {
+    fn game_state(&self) -> GameState<BitBoard> {
+        Graph::game_state(self)
+    }
+
+    fn work(&self) {
+        Graph::work(self)
+    }
+
+    fn search_progress(&self) -> (u64, u32) {
+        Graph::search_progress(self)
+    }
+
+    fn search_time(&self) -> Duration {
+        Graph::search_time(self)
+    }
+
+    fn count_nodes(&self) -> usize {
+        Graph::count_nodes(self)
+    }
 
-        // TODO: speculate
+    fn memory_usage(&self) -> usize {
+        Graph::memory_usage(self)
+    }
+
+    fn is_full(&self) -> bool {
+        Graph::is_full(self)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        Graph::is_exhausted(self)
+    }
+
+    fn best_move(&self) -> Option<Move> {
+        Graph::best_move(self)
+    }
+
+    fn best_plan(&self) -> Plan {
+        Graph::best_plan(self)
+    }
+
+    fn advance(&mut self, mv: Move) -> Result<(), PlayError> {
+        Graph::advance(self, mv)
+    }
+
+    fn play(&mut self, piece: PieceState) -> Result<PlayOutcome, PlayError> {
+        Graph::play(self, piece)
+    }
+
+    fn add_piece(&mut self, piece: PieceKind) {
+        Graph::add_piece(self, piece)
+    }
+
+    fn set_bag(&mut self, bag: SevenBag) {
+        Graph::set_bag(self, bag)
     }
 }
 
 impl<E: Evaluator> Generation<E> {
     pub fn new() -> Self {
         Self {
-            nodes_rack: Rack::new(1 << 12),
-            actions_rack: Rack::new(1 << 12),
+            nodes: Arena::new(),
+            actions: Arena::new(),
             lookup: DashMap::new(),
             parents_lookup: DashMap::new(),
+            spilled_parents: AtomicUsize::new(0),
             next: Lazy::new(|| Box::new(Self::new())),
         }
     }
 
+    /// Returns the bytes held by this generation, without the next one.
+    fn memory(&self) -> usize {
+        size_of::<Self>()
+            + self.nodes.memory()
+            + self.actions.memory()
+            + map_memory(&self.lookup)
+            + map_memory(&self.parents_lookup)
+            + self.spilled_parents.load(Ordering::Relaxed)
+    }
+
     pub fn find_node_index(&self, state: &State) -> Option<Index> {
         self.lookup.get(state).map(|x| *x)
     }
 
     pub fn with_node<R>(&self, index: Index, f: impl FnOnce(&mut Node<E>) -> R) -> R {
-        f(&mut self.nodes_rack.get(index))
+        f(&mut self.nodes.get(index).lock())
     }
 
-    pub fn with_actions<R>(&self, range: IndexRange, f: impl FnOnce(&mut [Action<E>]) -> R) -> R {
-        f(&mut *self.actions_rack.get_range(range))
+    /// Passes the actions of a node of this generation to `f`, or returns `None` for a leaf.
+    ///
+    /// The node is borrowed mutably, so the caller holds its lock, see [`Self::with_node`].
+    pub fn with_actions<R>(
+        &self,
+        node: &mut Node<E>,
+        f: impl FnOnce(&mut [Action<E>]) -> R,
+    ) -> Option<R> {
+        let ChildData(range) = node.children.as_ref()?;
+        // Safety: a range of actions belongs to a single node, which is borrowed for the call
+        Some(f(unsafe { self.actions.get_range_mut(*range) }))
     }
 
     // MARK: - Select
-    pub fn select(&self, state: &State, depth: u32) -> SelectResult<E> {
+    pub fn select(&self, state: &State, policy: &SearchPolicy) -> SelectResult<E> {
         puffin::profile_function!();
         if let Some(index) = self.find_node_index(state) {
             self.with_node(index, |node| {
+                // another worker may have killed the node before its parents were backed up
                 if node.dead {
-                    eprintln!("{} {:?}", depth, node.children);
-                    unreachable!("dead node in selection");
+                    return SelectResult::Failed;
                 }
                 if node.children.is_none() {
                     return SelectResult::Expand;
                 }
 
-                let children = node.children.as_ref().unwrap();
-                let selection = self.with_actions(children.0, |actions| {
+                let selection = self.with_actions(node, |actions| {
                     // Ignore death
-                    let actions = actions
-                        .iter()
-                        .filter(|action| !action.dead)
+                    let live = (0..actions.len())
+                        .filter(|&i| !actions[i].dead)
                         .collect::<Vec<_>>();
-                    if actions.is_empty() {
-                        unreachable!("no valid actions in selection");
+                    // as for a dead node, until the backup marks it
+                    if live.is_empty() {
+                        return None;
                     }
 
-                    let min = actions
+                    // Draw a speculated piece, as every piece left in a known 7-bag is equally
+                    // likely
+                    let pieces = live
                         .iter()
-                        .map(|action| action.acc.select_score())
-                        .fold(i32::MAX, i32::min);
+                        .map(|&i| actions[i].current_piece)
+                        .collect::<EnumSet<_>>();
+                    let piece = pieces.iter().choose(&mut thread_rng()).unwrap();
+                    let live = live
+                        .into_iter()
+                        .filter(|&i| actions[i].current_piece == piece)
+                        .collect::<Vec<_>>();
 
-                    let weights = actions
+                    let candidates = live
                         .iter()
-                        .map(|action| action.acc.select_score() - min + 1);
-
-                    let dist = WeightedIndex::new(weights).unwrap();
-                    let mut rng = thread_rng();
-                    let index = dist.sample(&mut rng);
-                    let action = actions[index].clone();
-                    Some(action)
+                        .map(|&i| actions[i].candidate())
+                        .collect::<Vec<_>>();
+                    let action = &mut actions[live[policy.select(&candidates)]];
+                    action.visits += 1;
+                    Some(*action)
                 });
 
-                match selection {
+                match selection.flatten() {
                     Some(action) => SelectResult::Ok(action),
                     None => SelectResult::Failed,
                 }
@@ -324,76 +797,133 @@ impl<E: Evaluator> Generation<E> {
     }
 
     // MARK: - Expand
-    pub fn expand(&self, state: &State, current_piece: PieceKind, evaluator: &E) {
+    /// Expands the node with the actions of each of `pieces` as the current piece, which is a
+    /// single piece within the known queue.
+    ///
+    /// A piece that cannot spawn gets a single dead action, and the node dies once no piece can.
+    pub fn expand(
+        &self,
+        state: &State,
+        pieces: EnumSet<PieceKind>,
+        evaluator: &E,
+        policy: &SearchPolicy,
+    ) {
         puffin::profile_function!();
         let index = self.find_node_index(state).unwrap();
 
-        // Rent shelves to reduce locking
-        let mut actions_shelf = self.actions_rack.rent_shelf();
-        let mut next_nodes_shelf = self.next.nodes_rack.rent_shelf();
+        let next_nodes = &self.next.nodes;
         let next_lookup = &self.next.lookup;
         let next_parent_lookup = &self.next.parents_lookup;
-
-        // Reconstruct GameState
-        let game_state = state.reconstruct_with_first_piece(current_piece);
+        let next_spilled_parents = &self.next.spilled_parents;
 
         self.with_node(index, |node| {
             debug_assert!(node.children.is_none());
 
-            let moves = {
-                puffin::profile_scope!("legal_moves");
-                game_state.legal_moves(true).map(|gen| gen.moves())
-            };
-            let Ok(moves) = moves else {
-                // Dead node (suffocated)
-                node.dead = true;
-                return;
-            };
-            let actions = moves
-                .iter()
-                .map(|&mv| {
+            let mut actions = vec![];
+            let mut suffocated = vec![];
+            for current_piece in pieces {
+                // Reconstruct GameState
+                let game_state = state.reconstruct_with_first_piece(current_piece);
+
+                let moves = {
+                    puffin::profile_scope!("legal_moves");
+                    game_state.legal_moves(true).map(|gen| gen.moves())
+                };
+                let Ok(moves) = moves else {
+                    suffocated.push(current_piece);
+                    continue;
+                };
+                let mut scores = vec![];
+                let start = actions.len();
+                actions.extend(moves.iter().map(|&mv| {
                     let mut game_state = game_state.clone();
                     let placement = game_state.advance(mv);
+                    let evaluate = || {
+                        if placement.death {
+                            // piece is placed above the sky limit
+                            evaluator.death_state()
+                        } else {
+                            evaluator.evaluate_state(&game_state)
+                        }
+                    };
+                    let mut created_value = None;
 
                     let node_index = next_lookup
                         .entry(State::new(&game_state))
                         .and_modify(|present| {
-                            next_parent_lookup.get_mut(present).unwrap().push(index);
+                            let mut parents = next_parent_lookup.get_mut(present).unwrap();
+                            let spilled = spilled_memory(&parents);
+                            parents.push(index);
+                            next_spilled_parents
+                                .fetch_add(spilled_memory(&parents) - spilled, Ordering::Relaxed);
                         })
                         .or_insert_with(|| {
-                            let value = if placement.death {
-                                // piece is placed above the sky limit
-                                evaluator.death_state()
-                            } else {
-                                evaluator.evaluate_state(&game_state)
-                            };
+                            let value = evaluate();
+                            created_value = Some(value);
                             let node = Node {
                                 children: None,
                                 value,
                                 dead: false,
                             };
-                            let created = next_nodes_shelf.append(node);
+                            let created = next_nodes.alloc(Mutex::new(node));
                             next_parent_lookup.insert(created, smallvec![index]);
                             created
                         });
 
                     let reward = evaluator.evaluate_move(mv, placement, &game_state);
-                    // let child_node = next_nodes_shelf.get(*node_index.value());
+                    if policy.prior_temperature.is_some() {
+                        let value = created_value.unwrap_or_else(evaluate);
+                        scores.push(value.accumulate(reward).select_score());
+                    }
                     let act = Action {
                         node: *node_index.value(),
-                        mv,
+                        mv: Some(mv),
                         reward,
                         current_piece,
                         acc: E::Accumulator::default(), // will be updated in backprop
                         dead: placement.death,
                         visits: 0,
+                        prior: 0.0, // set below, once all actions of the piece are known
                     };
 
                     act
-                })
-                .collect::<Vec<_>>();
+                }));
+                if scores.is_empty() {
+                    scores = vec![0; actions.len() - start];
+                }
+                let priors = policy.priors(&scores);
+                for (action, prior) in actions[start..].iter_mut().zip(priors) {
+                    action.prior = prior;
+                }
+            }
+
+            if suffocated.len() == pieces.len() {
+                // Dead node (suffocated)
+                node.dead = true;
+                return;
+            }
+            // a speculated piece that cannot spawn is a death the other pieces are averaged with
+            for current_piece in suffocated {
+                // no state follows, so the child is left out of the lookup and stays a dead leaf
+                let node = next_nodes.alloc(Mutex::new(Node {
+                    children: None,
+                    value: evaluator.death_state(),
+                    dead: true,
+                }));
+                next_parent_lookup.insert(node, smallvec![index]);
+                actions.push(Action {
+                    node,
+                    mv: None,
+                    reward: E::TransientReward::default(),
+                    current_piece,
+                    acc: E::Accumulator::default(), // will be updated in backprop
+                    dead: true,
+                    visits: 0,
+                    prior: 1.0,
+                });
+            }
 
-            let child_data = ChildData(actions_shelf.append_vec(actions));
+            let child_data = ChildData(self.actions.alloc_range(actions));
             node.children = Some(child_data);
         });
     }
@@ -402,15 +932,109 @@ impl<E: Evaluator> Generation<E> {
 impl<E: Evaluator> Default for Generation<E> {
     fn default() -> Self {
         Self {
-            nodes_rack: Rack::empty(),
-            actions_rack: Rack::empty(),
+            nodes: Arena::new(),
+            actions: Arena::new(),
             lookup: DashMap::new(),
             parents_lookup: DashMap::new(),
+            spilled_parents: AtomicUsize::new(0),
             next: Lazy::new(|| Box::new(Self::default())),
         }
     }
 }
 
+/// Returns the bytes of a map, as hashbrown keeps an eighth of its buckets empty and a control
+/// byte per bucket.
+fn map_memory<K: Eq + std::hash::Hash, V>(map: &DashMap<K, V>) -> usize {
+    map.capacity() * 8 / 7 * (size_of::<(K, V)>() + 1)
+}
+
+/// Returns the bytes a small vector allocated on the heap.
+fn spilled_memory<A: smallvec::Array>(vec: &SmallVec<A>) -> usize {
+    if vec.spilled() {
+        vec.capacity() * size_of::<A::Item>()
+    } else {
+        0
+    }
+}
+
+/// Returns the value of a node from its actions, see [`Backup`].
+///
+/// Speculated pieces weigh the same, as the chance nodes assume the bag of the state is known.
+fn backup<E: Evaluator>(actions: &[Action<E>], backup: Backup) -> E::Accumulator {
+    let pieces = actions
+        .iter()
+        .map(|action| action.current_piece)
+        .collect::<EnumSet<_>>();
+    let values = pieces
+        .iter()
+        .map(|piece| {
+            let group = actions
+                .iter()
+                .filter(|action| action.current_piece == piece);
+            // only dead actions count once the piece is sure to kill
+            let live = group
+                .clone()
+                .filter(|action| !action.dead)
+                .collect::<Vec<_>>();
+            let actions = if live.is_empty() {
+                group.collect()
+            } else {
+                live
+            };
+            let best = actions
+                .iter()
+                .max_by_key(|action| action.acc.select_score())
+                .unwrap()
+                .acc;
+            let visits = actions.iter().map(|action| action.visits).sum::<u32>();
+            let value = match backup {
+                Backup::Mean if visits > 0 => E::Accumulator::mean(
+                    &actions
+                        .iter()
+                        .filter(|action| action.visits > 0)
+                        .map(|action| (action.acc, action.visits as f32 / visits as f32))
+                        .collect::<Vec<_>>(),
+                ),
+                _ => best,
+            };
+            (value, 1.0 / pieces.len() as f32)
+        })
+        .collect::<Vec<_>>();
+    match values.as_slice() {
+        [(value, _)] => *value,
+        values => E::Accumulator::mean(values),
+    }
+}
+
+impl<E: Evaluator> Action<E> {
+    fn candidate(&self) -> Candidate {
+        Candidate {
+            score: self.acc.select_score(),
+            visits: self.visits,
+            prior: self.prior,
+        }
+    }
+}
+
+/// Returns the action to play among the live ones, or the first one when all of them are dead.
+fn best_action<'a, E: Evaluator>(
+    actions: &'a [Action<E>],
+    policy: &SearchPolicy,
+) -> Option<&'a Action<E>> {
+    let live = actions
+        .iter()
+        .filter(|action| !action.dead)
+        .collect::<Vec<_>>();
+    let candidates = live
+        .iter()
+        .map(|action| action.candidate())
+        .collect::<Vec<_>>();
+    match policy.best(&candidates) {
+        Some(i) => Some(live[i]),
+        None => actions.first(),
+    }
+}
+
 // We need to implement Clone and Copy manually because of the generic parameter
 impl<E: Evaluator> Clone for Action<E> {
     fn clone(&self) -> Self {
@@ -457,7 +1081,9 @@ impl<E: Evaluator> Copy for Action<E> {}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct State {
-    board: BitBoard,
+    // hashes as its incrementally updated zobrist key, equality still compares the cells
+    board: HashedBoard,
+    // before the current piece is drawn, so speculated pieces lead to different states
     bag: SevenBag,
     hold: Option<PieceKind>,
     ren: i32,
@@ -471,7 +1097,7 @@ impl State {
         state.queue.iter().rev().for_each(|&piece| bag.put(piece));
 
         Self {
-            board: state.board.clone(),
+            board: state.board.clone().into(),
             bag,
             hold: state.hold,
             ren: state.ren,
@@ -480,6 +1106,7 @@ impl State {
     }
 
     fn advance(&mut self, mv: Move, mut current_piece: PieceKind) -> PlacementResult {
+        draw(&mut self.bag, current_piece);
         match mv {
             Move::Hold => {
                 if self.hold.is_some() {
@@ -529,10 +1156,20 @@ impl State {
         }
     }
 
+    /// Returns the pieces the bag can deal next.
+    fn possible_pieces(&self) -> EnumSet<PieceKind> {
+        match self.bag.0 {
+            pieces if pieces.is_empty() => EnumSet::all(),
+            pieces => pieces,
+        }
+    }
+
     fn reconstruct_with_first_piece(&self, current_piece: PieceKind) -> GameState<BitBoard> {
+        let mut bag = self.bag;
+        draw(&mut bag, current_piece);
         GameState {
-            board: self.board.clone(),
-            bag: self.bag,
+            board: self.board.board().clone(),
+            bag,
             queue: VecDeque::from_iter([current_piece]),
             hold: self.hold,
             ren: self.ren,
@@ -541,6 +1178,25 @@ impl State {
     }
 }
 
+/// Takes the piece from the bag, or from a new bag when the frontend deals a piece the bag does not
+/// have.
+fn draw(bag: &mut SevenBag, piece: PieceKind) {
+    if !bag.has(piece) {
+        *bag = SevenBag::default();
+    }
+    bag.take(piece);
+}
+
+/// Whether two placements leave the same cells and spin, as rotation states of symmetric
+/// pieces can describe the same placement.
+fn same_placement(a: PieceState, b: PieceState) -> bool {
+    let mut a_cells = a.pos.cells();
+    let mut b_cells = b.pos.cells();
+    a_cells.sort();
+    b_cells.sort();
+    a.pos.kind == b.pos.kind && a.spin == b.spin && a_cells == b_cells
+}
+
 #[derive(Debug)]
 pub enum SelectResult<E: Evaluator> {
     /// Node has children, return the best one
@@ -554,5 +1210,406 @@ pub enum SelectResult<E: Evaluator> {
 #[derive(Debug, Clone)]
 pub struct Plan {
     pub moves: Vec<Move>,
+    /// Accumulated score of the first move, 0 if the root has not been expanded.
     pub score: i32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval::SimpleEvaluator, policy::Selection};
+
+    fn graph(pieces: usize) -> Graph<SimpleEvaluator> {
+        let mut state = GameState::new();
+        for _ in 0..pieces {
+            state.fulfill_queue();
+        }
+        Graph::new(
+            &state,
+            Box::new(SimpleEvaluator),
+            SearchPolicy::default(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_search_progress() {
+        let mut graph = graph(7);
+        assert_eq!(graph.search_progress(), (0, 0));
+
+        for _ in 0..50 {
+            graph.work();
+        }
+        let (expanded, depth) = graph.search_progress();
+        assert!(expanded > 1);
+        assert!(depth >= 1);
+
+        let plan = graph.best_plan();
+        assert!(!plan.moves.is_empty());
+        graph.advance(plan.moves[0]).unwrap();
+        assert_eq!(graph.search_progress(), (0, 0));
+    }
+
+    #[test]
+    fn test_topped_out() {
+        for selection in [Selection::Uct, Selection::Puct] {
+            for backup in [Backup::Max, Backup::Mean] {
+                let policy = SearchPolicy {
+                    selection,
+                    backup,
+                    ..Default::default()
+                };
+                for _ in 0..10 {
+                    // every row has a covered hole, so only the first piece into the two cells deep
+                    // well under the spawn places below the sky, and may block the spawn
+                    let mut state = GameState::<BitBoard>::new();
+                    for (x, col) in state.board.cols.iter_mut().enumerate() {
+                        let height = match x {
+                            4 => 18,
+                            9 => 21,
+                            _ => 20,
+                        };
+                        *col = ((1 << height) - 1) & !(1 << x | 1 << (x + 10));
+                    }
+                    for _ in 0..5 {
+                        state.fulfill_queue();
+                    }
+                    let graph = Graph::new(&state, Box::new(SimpleEvaluator), policy, None);
+                    // the dead nodes reach the root instead of being selected
+                    for _ in 0..500 {
+                        if graph.is_exhausted() {
+                            break;
+                        }
+                        graph.work();
+                    }
+                    assert!(graph.is_exhausted());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_suffocated_piece() {
+        // only the I overlaps the tower where it spawns
+        let mut state = GameState::<BitBoard>::new();
+        state.board.cols[6] = (1 << 21) - 1;
+        let graph = Graph::new(
+            &state,
+            Box::new(SimpleEvaluator),
+            SearchPolicy::default(),
+            None,
+        );
+        for _ in 0..50 {
+            graph.work();
+        }
+
+        let gen = &*graph.root_gen;
+        let index = gen.find_node_index(&graph.root_state).unwrap();
+        gen.with_node(index, |node| {
+            assert!(!node.dead);
+            gen.with_actions(node, |actions| {
+                let (i, live): (Vec<&Action<_>>, Vec<_>) = actions
+                    .iter()
+                    .partition(|action| action.current_piece == PieceKind::I);
+                assert_eq!(i.len(), 1);
+                assert!(i[0].dead);
+                assert_eq!(i[0].mv, None);
+                assert_eq!(i[0].acc, SimpleEvaluator.death_state());
+                assert!(live.iter().any(|action| !action.dead));
+            })
+            .unwrap();
+        });
+
+        // the node dies when the only piece cannot spawn
+        state.queue.push_back(PieceKind::I);
+        let graph = Graph::new(
+            &state,
+            Box::new(SimpleEvaluator),
+            SearchPolicy::default(),
+            None,
+        );
+        graph.work();
+        let gen = &*graph.root_gen;
+        let index = gen.find_node_index(&graph.root_state).unwrap();
+        assert!(gen.with_node(index, |node| node.dead && node.children.is_none()));
+    }
+
+    #[test]
+    fn test_speculation() {
+        let mut graph = graph(2);
+        for _ in 0..300 {
+            graph.work();
+        }
+        // the search goes on past the known queue
+        let (_, depth) = graph.search_progress();
+        assert!(depth > 2);
+
+        // the pieces the expanded nodes after the queue branch over
+        fn speculated(graph: &Graph<SimpleEvaluator>) -> Vec<EnumSet<PieceKind>> {
+            let gen = &*graph.root_gen.next.next;
+            let mut expanded = gen
+                .lookup
+                .iter()
+                .map(|entry| *entry.value())
+                .collect::<Vec<_>>();
+            expanded.sort_by_key(|index| (index.shelf, index.slot));
+            expanded
+                .into_iter()
+                .filter_map(|index| {
+                    gen.with_node(index, |node| {
+                        gen.with_actions(node, |actions| {
+                            actions
+                                .iter()
+                                .map(|action| action.current_piece)
+                                .collect::<EnumSet<_>>()
+                        })
+                    })
+                })
+                .collect()
+        }
+        let before = speculated(&graph);
+        assert!(!before.is_empty());
+        assert!(before.iter().all(|pieces| pieces.len() > 1));
+
+        let piece = graph.bag.0.iter().next().unwrap();
+        graph.add_piece(piece);
+        let after = speculated(&graph);
+        assert_eq!(after.len(), before.len());
+        assert!(after.iter().all(|&pieces| pieces == EnumSet::only(piece)));
+
+        // the kept branch is searched on
+        for _ in 0..50 {
+            graph.work();
+        }
+        let plan = graph.best_plan();
+        assert!((1..=3).contains(&plan.moves.len()));
+    }
+
+    #[test]
+    fn test_set_bag() {
+        let expanded = |graph: &Graph<SimpleEvaluator>, depth: usize| {
+            let mut gen = &*graph.root_gen;
+            for _ in 0..depth {
+                gen = &gen.next;
+            }
+            gen.lookup
+                .iter()
+                .filter(|entry| gen.with_node(*entry.value(), |node| node.children.is_some()))
+                .count()
+        };
+        // a full bag deals the same pieces as an empty one
+        let mut speculating = graph(0);
+        for _ in 0..50 {
+            speculating.work();
+        }
+        let root = expanded(&speculating, 0);
+        speculating.set_bag(SevenBag(EnumSet::all()));
+        assert_eq!(expanded(&speculating, 0), root);
+        assert!(expanded(&speculating, 1) > 0);
+
+        let mut graph = graph(2);
+        for _ in 0..300 {
+            graph.work();
+        }
+        let best = graph.best_move();
+        let within_queue = expanded(&graph, 1);
+        assert!(within_queue > 0);
+        assert!(expanded(&graph, 2) > 0);
+
+        // a piece of the bag was dealt before the queue
+        let mut bag = graph.bag;
+        bag.take(bag.0.iter().next().unwrap());
+        graph.set_bag(bag);
+        assert_eq!(graph.game_state().bag, bag);
+        assert_eq!(graph.best_move(), best);
+        assert_eq!(expanded(&graph, 1), within_queue);
+        assert_eq!(expanded(&graph, 2), 0);
+        assert!(Lazy::get(&graph.root_gen.next.next.next).is_none());
+
+        // the speculation starts over from the new bag
+        for _ in 0..100 {
+            graph.work();
+        }
+        assert!(expanded(&graph, 2) > 0);
+        let gen = &*graph.root_gen.next.next;
+        for entry in gen.lookup.iter() {
+            gen.with_node(*entry.value(), |node| {
+                gen.with_actions(node, |actions| {
+                    assert!(actions
+                        .iter()
+                        .all(|action| bag.0.contains(action.current_piece)));
+                });
+            });
+        }
+    }
+
+    #[test]
+    fn test_backup() {
+        let graph = graph(4);
+        for _ in 0..200 {
+            graph.work();
+        }
+
+        // every expanded node is valued by the best of its actions, and each action by its child
+        let mut gen = &*graph.root_gen;
+        let mut checked = 0;
+        for _ in 0..4 {
+            for entry in gen.lookup.iter() {
+                gen.with_node(*entry.value(), |node| {
+                    if node.dead || node.children.is_none() {
+                        return;
+                    }
+                    let value = node.value;
+                    gen.with_actions(node, |actions| {
+                        for action in actions.iter() {
+                            let child = gen.next.nodes.get(action.node).lock().value;
+                            assert_eq!(action.acc, child.accumulate(action.reward));
+                        }
+                        assert_eq!(value, backup(actions, Backup::Max));
+                        let best = actions.iter().filter(|action| !action.dead);
+                        let best = best.map(|action| action.acc).max().unwrap();
+                        assert_eq!(value, best);
+                    });
+                    checked += 1;
+                });
+            }
+            gen = &*gen.next;
+        }
+        assert!(checked > 1);
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let mut graph = graph(7);
+        let root = graph.memory_usage();
+        assert!(!graph.is_full());
+        for _ in 0..200 {
+            graph.work();
+        }
+        // every node and action is accounted for
+        let used = graph.memory_usage();
+        assert!(used > root + graph.count_nodes() * size_of::<Node<SimpleEvaluator>>());
+
+        let budget = used + 1;
+        graph.memory_budget = Some(budget);
+        while !graph.is_full() {
+            graph.work();
+        }
+        // overshot by the expansions between two measures at most
+        let (expanded, _) = graph.search_progress();
+        let full = graph.memory_usage();
+        assert!(full < 2 * budget);
+        for _ in 0..100 {
+            graph.work();
+        }
+        assert_eq!(graph.search_progress().0, expanded);
+
+        // the generation behind the root is freed
+        let plan = graph.best_plan();
+        graph.advance(plan.moves[0]).unwrap();
+        assert!(graph.memory_usage() < full);
+    }
+
+    /// Returns the lowest placement of the piece after `hold` moves.
+    fn lowest_placement(state: &GameState<BitBoard>, hold: bool) -> PieceState {
+        let mut state = state.clone();
+        if hold {
+            state.advance(Move::Hold);
+        }
+        let moves = state.legal_moves(false).unwrap().moves();
+        moves
+            .into_iter()
+            .filter_map(|mv| match mv {
+                Move::Place(piece) => Some(piece),
+                Move::Hold => None,
+            })
+            .min_by_key(|piece| piece.pos.cells().iter().map(|&(_, y)| y).max())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_play() {
+        let mut graph = graph(7);
+        let state = graph.game_state();
+        for _ in 0..50 {
+            graph.work();
+        }
+
+        // the root has been expanded, so every placement was searched
+        let piece = lowest_placement(&state, false);
+        assert_eq!(graph.play(piece), Ok(PlayOutcome::Advanced));
+        assert_eq!(graph.queue.len(), 6);
+
+        // the next piece is held out of the queue first
+        let state = graph.game_state();
+        let piece = lowest_placement(&state, true);
+        let outcome = graph.play(piece).unwrap();
+        assert_eq!(graph.queue.len(), 4);
+        assert_eq!(graph.root_state.hold, Some(state.queue[0]));
+        let mut expected = state;
+        expected.advance(Move::Hold);
+        expected.advance(Move::Place(piece));
+        assert_eq!(graph.game_state(), expected);
+        if outcome == PlayOutcome::Rebuilt {
+            assert_eq!(graph.search_progress(), (0, 0));
+        }
+
+        // nothing was searched from the new root once the search is discarded
+        let state = graph.game_state();
+        graph.set_root(&state);
+        let piece = lowest_placement(&state, false);
+        assert_eq!(graph.play(piece), Ok(PlayOutcome::Rebuilt));
+        let mut expected = state;
+        expected.advance(Move::Place(piece));
+        assert_eq!(graph.game_state(), expected);
+        for _ in 0..10 {
+            graph.work();
+        }
+        assert!(graph.search_progress().0 > 0);
+    }
+
+    #[test]
+    fn test_play_errors() {
+        let mut graph = graph(3);
+        let state = graph.game_state();
+        let piece = lowest_placement(&state, false);
+
+        let floating = PieceState {
+            pos: PiecePosition {
+                y: piece.pos.y + 5,
+                ..piece.pos
+            },
+            ..piece
+        };
+        assert_eq!(graph.play(floating), Err(PlayError::IllegalPlacement));
+        let sunk = PieceState {
+            pos: PiecePosition {
+                y: piece.pos.y - 1,
+                ..piece.pos
+            },
+            ..piece
+        };
+        assert_eq!(graph.play(sunk), Err(PlayError::IllegalPlacement));
+        // resting on the floor is not enough, the move generator must reach it as played
+        let spun = PieceState {
+            spin: SpinKind::Full,
+            ..piece
+        };
+        assert_eq!(graph.play(spun), Err(PlayError::IllegalPlacement));
+
+        use PieceKind::*;
+        let missing = [I, O, T, L, J, S, Z]
+            .into_iter()
+            .find(|kind| !state.queue.contains(kind))
+            .unwrap();
+        let wrong = PieceState {
+            pos: PiecePosition {
+                kind: missing,
+                ..piece.pos
+            },
+            ..piece
+        };
+        assert_eq!(graph.play(wrong), Err(PlayError::WrongPiece));
+        assert_eq!(graph.game_state(), state);
+    }
+}
@@ -1,12 +1,33 @@
-use std::{ops::DerefMut, time::Duration};
+//! Append-only storage of the search graph.
+//!
+//! The search stores its nodes and actions in [`Arena`]s. [`Rack`] is the storage it used before,
+//! kept as the baseline of the `storage_bench` binary.
 
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    mem::{size_of, MaybeUninit},
+    ops::DerefMut,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use once_cell::sync::OnceCell;
 use parking_lot::{Mutex, MutexGuard};
 use rand::prelude::*;
 
+/// Slots of the first chunk of an [`Arena`], each chunk being twice as large as the previous one.
+const FIRST_CHUNK: usize = 1 << 10;
+
+/// Enough chunks for more slots than an [`Index`] of the search can address.
+const CHUNKS: usize = 32;
+
 /// Represents a rack that contains multiple shelves.
 #[derive(Debug)]
 pub struct Rack<T> {
     shelves: Vec<Shelf<T>>,
+    // bytes reserved by the shelves, tracked as they grow so that reading it takes no lock
+    allocated: AtomicUsize,
 }
 
 /// Represents a shelf that holds data of type `T`.
@@ -15,7 +36,8 @@ pub struct Shelf<T> {
     data: Mutex<Vec<T>>,
 }
 
-/// Represents an index that points to a specific location in the rack.
+/// Represents an index that points to a specific location in the rack, or in the arena, where the
+/// shelf is the chunk.
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct Index {
     pub shelf: usize,
@@ -34,6 +56,7 @@ pub struct IndexRange {
 #[derive(Debug)]
 pub struct ShelfRef<'a, T> {
     data: MutexGuard<'a, Vec<T>>,
+    allocated: &'a AtomicUsize,
     pub shelf: usize,
 }
 
@@ -42,24 +65,20 @@ impl<T> Rack<T> {
     pub fn new(num_shelves: u32) -> Self {
         Self {
             shelves: (0..num_shelves).into_iter().map(|_| Shelf::new()).collect(),
+            allocated: AtomicUsize::new(0),
         }
     }
 
     pub fn empty() -> Self {
         Self {
             shelves: Vec::new(),
+            allocated: AtomicUsize::new(0),
         }
     }
 
     /// Allocates a single item to a random shelf and returns its index.
     pub fn alloc(&self, item: T) -> Index {
-        // Use random number generator for now
-        let shelf = thread_rng().gen_range(0..self.shelves.len());
-
-        Index {
-            shelf: shelf,
-            slot: self.shelves[shelf].alloc(item),
-        }
+        self.rent_shelf().append(item)
     }
 
     /// Rents a random shelf and returns a reference to its data.
@@ -67,6 +86,7 @@ impl<T> Rack<T> {
         let shelf = thread_rng().gen_range(0..self.shelves.len());
         ShelfRef {
             data: self.shelves[shelf].rent(),
+            allocated: &self.allocated,
             shelf,
         }
     }
@@ -97,6 +117,17 @@ impl<T> Rack<T> {
             .map(|shelf| shelf.data.lock().len())
             .sum()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.shelves
+            .iter()
+            .all(|shelf| shelf.data.lock().is_empty())
+    }
+
+    /// Returns the bytes held by the rack, including the capacity its shelves reserved.
+    pub fn memory(&self) -> usize {
+        self.shelves.capacity() * size_of::<Shelf<T>>() + self.allocated.load(Ordering::Relaxed)
+    }
 }
 
 impl<T> Shelf<T> {
@@ -107,16 +138,6 @@ impl<T> Shelf<T> {
         }
     }
 
-    /// Allocates an item to the shelf and returns its slot index.
-    pub fn alloc(&self, item: T) -> usize {
-        let mut data = self
-            .data
-            .try_lock_for(Duration::from_secs(1))
-            .expect("Shelf::alloc deadlock");
-        data.push(item);
-        data.len() - 1
-    }
-
     /// Rents the data vector of the shelf.
     pub fn rent(&self) -> MutexGuard<'_, Vec<T>> {
         self.data
@@ -125,10 +146,18 @@ impl<T> Shelf<T> {
     }
 }
 
+impl<T> Default for Shelf<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> ShelfRef<'_, T> {
     /// Appends an item to the shelf's data vector and returns its index.
     pub fn append(&mut self, item: T) -> Index {
+        let capacity = self.data.capacity();
         self.data.push(item);
+        self.record_growth(capacity);
         Index {
             shelf: self.shelf,
             slot: self.data.len() - 1,
@@ -138,7 +167,9 @@ impl<T> ShelfRef<'_, T> {
     /// Appends a vector of items to the shelf's data vector and returns the range of indices.
     pub fn append_vec(&mut self, vec: Vec<T>) -> IndexRange {
         let len = vec.len();
+        let capacity = self.data.capacity();
         self.data.extend(vec);
+        self.record_growth(capacity);
 
         IndexRange {
             shelf: self.shelf,
@@ -157,6 +188,200 @@ impl<T> ShelfRef<'_, T> {
         assert!(index.shelf == self.shelf);
         &self.data[index.slot]
     }
+
+    fn record_growth(&self, old_capacity: usize) {
+        let grown = self.data.capacity() - old_capacity;
+        self.allocated
+            .fetch_add(grown * size_of::<T>(), Ordering::Relaxed);
+    }
+}
+
+/// An append-only arena whose items never move, so that they are shared without locking it.
+///
+/// Items live in chunks that double in size and are allocated once, the first time an index lands
+/// in them. Allocating an item only bumps a counter, and reading one goes straight to its slot.
+/// Mutation goes through the item, such as a [`Mutex`] per node, or through a range whose owner
+/// guarantees exclusive access, see [`Arena::get_range_mut`].
+pub struct Arena<T> {
+    chunks: [OnceCell<Chunk<T>>; CHUNKS],
+    // slots handed out, including the ones skipped so that ranges fit in a chunk
+    len: AtomicUsize,
+    // bytes of the allocated chunks
+    allocated: AtomicUsize,
+}
+
+struct Chunk<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    written: Box<[AtomicBool]>,
+}
+
+// Safety: a slot is written once by the thread that allocated it, before its index is shared, and
+// is then only shared as `&T` or handed out exclusively by `get_range_mut`.
+unsafe impl<T: Send> Send for Arena<T> {}
+unsafe impl<T: Send + Sync> Sync for Arena<T> {}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Self {
+            chunks: std::array::from_fn(|_| OnceCell::new()),
+            len: AtomicUsize::new(0),
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// Allocates a single item and returns its index.
+    pub fn alloc(&self, item: T) -> Index {
+        let position = self.len.fetch_add(1, Ordering::Relaxed);
+        let (shelf, slot) = locate(position);
+        self.write(shelf, slot, item);
+        Index { shelf, slot }
+    }
+
+    /// Allocates the items next to each other and returns their range.
+    pub fn alloc_range(&self, items: Vec<T>) -> IndexRange {
+        let mut len = self.len.load(Ordering::Relaxed);
+        let start = loop {
+            // a range never straddles two chunks, so the end of a chunk may be skipped
+            let mut start = len;
+            while locate(start).1 + items.len() > chunk_len(locate(start).0) {
+                start = chunk_start(locate(start).0 + 1);
+            }
+            match self.len.compare_exchange_weak(
+                len,
+                start + items.len(),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break start,
+                Err(actual) => len = actual,
+            }
+        };
+
+        let (shelf, slot) = locate(start);
+        let end = slot + items.len();
+        for (slot, item) in (slot..end).zip(items) {
+            self.write(shelf, slot, item);
+        }
+        IndexRange {
+            shelf,
+            start: slot,
+            end,
+        }
+    }
+
+    fn write(&self, shelf: usize, slot: usize, item: T) {
+        let chunk = self.chunks[shelf].get_or_init(|| {
+            let len = chunk_len(shelf);
+            self.allocated
+                .fetch_add(len * Chunk::<T>::SLOT_SIZE, Ordering::Relaxed);
+            Chunk {
+                slots: (0..len)
+                    .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                    .collect(),
+                written: (0..len).map(|_| AtomicBool::new(false)).collect(),
+            }
+        });
+        // Safety: the slot was handed out to this call alone
+        unsafe { (*chunk.slots[slot].get()).write(item) };
+        chunk.written[slot].store(true, Ordering::Release);
+    }
+
+    /// Returns the item at the specified index.
+    pub fn get(&self, index: Index) -> &T {
+        let chunk = self.chunks[index.shelf].get().expect("chunk not allocated");
+        assert!(
+            chunk.written[index.slot].load(Ordering::Acquire),
+            "Arena::get unwritten slot {:?}",
+            index
+        );
+        // Safety: the slot is written and never written again
+        unsafe { (*chunk.slots[index.slot].get()).assume_init_ref() }
+    }
+
+    /// Returns the items within the specified range mutably.
+    ///
+    /// # Safety
+    ///
+    /// No other reference to the items may exist while the slice is alive. The search ensures it by
+    /// giving each range to a single node, and only touching the actions of a node while it holds
+    /// the node mutably: a range must never be aliased by two nodes.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_range_mut(&self, range: IndexRange) -> &mut [T] {
+        if range.start == range.end {
+            return &mut [];
+        }
+        let chunk = self.chunks[range.shelf].get().expect("chunk not allocated");
+        let slots = &chunk.slots[range.start..range.end];
+        assert!(
+            chunk.written[range.start..range.end]
+                .iter()
+                .all(|written| written.load(Ordering::Acquire)),
+            "Arena::get_range_mut unwritten slots {:?}",
+            range
+        );
+        // UnsafeCell and MaybeUninit have the layout of the item
+        std::slice::from_raw_parts_mut(slots.as_ptr() as *mut T, slots.len())
+    }
+
+    /// Returns the number of slots handed out, including the ones skipped by ranges.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the bytes held by the arena, including the slots of its chunks not used yet.
+    pub fn memory(&self) -> usize {
+        size_of::<Self>() + self.allocated.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Chunk<T> {
+    const SLOT_SIZE: usize = size_of::<T>() + size_of::<AtomicBool>();
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Arena<T> {
+    fn drop(&mut self) {
+        for chunk in self.chunks.iter_mut().filter_map(OnceCell::get_mut) {
+            for (slot, written) in chunk.slots.iter_mut().zip(chunk.written.iter_mut()) {
+                if *written.get_mut() {
+                    // Safety: the slot is written, and dropped once as the arena is
+                    unsafe { slot.get_mut().assume_init_drop() };
+                }
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Arena<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Arena")
+            .field("len", &self.len())
+            .field("memory", &self.memory())
+            .finish()
+    }
+}
+
+/// Returns the chunk and the slot of the item at `position` in allocation order.
+fn locate(position: usize) -> (usize, usize) {
+    let chunk = (position / FIRST_CHUNK + 1).ilog2() as usize;
+    (chunk, position - chunk_start(chunk))
+}
+
+fn chunk_start(chunk: usize) -> usize {
+    FIRST_CHUNK * ((1 << chunk) - 1)
+}
+
+fn chunk_len(chunk: usize) -> usize {
+    FIRST_CHUNK << chunk
 }
 
 #[cfg(test)]
@@ -201,4 +426,92 @@ mod tests {
         let data = rack.get_range(range);
         assert_eq!(*data, [1, 2, 3]);
     }
+
+    #[test]
+    fn test_memory() {
+        let rack: Rack<u64> = Rack::new(1);
+        let empty = rack.memory();
+        assert_eq!(empty, size_of::<Shelf<u64>>());
+
+        let mut shelf_ref = rack.rent_shelf();
+        shelf_ref.append_vec(vec![0; 100]);
+        let capacity = shelf_ref.data.capacity();
+        drop(shelf_ref);
+        assert!(capacity >= 100);
+        assert_eq!(rack.memory(), empty + capacity * 8);
+    }
+
+    #[test]
+    fn test_arena() {
+        let arena = Arena::new();
+        let indices = (0..3000).map(|i| arena.alloc(i)).collect::<Vec<_>>();
+        assert_eq!(indices[1], Index { shelf: 0, slot: 1 });
+        assert_eq!(indices[FIRST_CHUNK], Index { shelf: 1, slot: 0 });
+        assert!(indices
+            .iter()
+            .enumerate()
+            .all(|(i, &index)| *arena.get(index) == i));
+
+        // ranges never straddle two chunks
+        let range = arena.alloc_range((0..2000).collect());
+        assert_eq!(range.end - range.start, 2000);
+        assert_eq!(range.shelf, 2);
+        assert_eq!(range.start, 0);
+        assert_eq!(arena.len(), chunk_start(2) + 2000);
+        let items = unsafe { arena.get_range_mut(range) };
+        items.reverse();
+        assert_eq!(items[0], 1999);
+
+        let empty = arena.alloc_range(vec![]);
+        assert!(unsafe { arena.get_range_mut(empty) }.is_empty());
+    }
+
+    #[test]
+    fn test_arena_threads() {
+        let arena = Arena::new();
+        let indices = std::thread::scope(|scope| {
+            let workers = (0..4)
+                .map(|worker| {
+                    let arena = &arena;
+                    scope.spawn(move || {
+                        (0..1000)
+                            .map(|i| {
+                                (
+                                    arena.alloc(Mutex::new(worker * 1000 + i)),
+                                    worker * 1000 + i,
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(arena.len(), 4000);
+        for (index, value) in indices {
+            let mut item = arena.get(index).lock();
+            assert_eq!(*item, value);
+            *item += 1;
+        }
+    }
+
+    #[test]
+    fn test_arena_drop() {
+        let item = std::sync::Arc::new(());
+        let arena = Arena::new();
+        arena.alloc(item.clone());
+        // the end of the first chunk is skipped
+        arena.alloc_range(vec![item.clone(); FIRST_CHUNK]);
+        assert_eq!(std::sync::Arc::strong_count(&item), FIRST_CHUNK + 2);
+        assert_eq!(
+            arena.memory(),
+            size_of::<Arena<std::sync::Arc<()>>>()
+                + 3 * FIRST_CHUNK * Chunk::<std::sync::Arc<()>>::SLOT_SIZE
+        );
+        drop(arena);
+        assert_eq!(std::sync::Arc::strong_count(&item), 1);
+    }
 }
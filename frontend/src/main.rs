@@ -2,7 +2,7 @@
 #![allow(rustdoc::missing_crate_level_docs)] // it's an example
 
 use eframe::egui;
-use game::tetris::{ColoredBoard, GameState};
+use game::tetris::{fumen::Fumen, ColoredBoard, GameState};
 use render::render_tetris;
 
 mod render;
@@ -29,6 +29,8 @@ struct MyApp {
     name: String,
     age: u32,
     state: GameState<ColoredBoard>,
+    fumen: String,
+    fumen_error: Option<String>,
 }
 
 impl Default for MyApp {
@@ -37,6 +39,8 @@ impl Default for MyApp {
             name: "Arthur".to_owned(),
             age: 42,
             state: GameState::new(),
+            fumen: String::new(),
+            fumen_error: None,
         }
     }
 }
@@ -54,6 +58,29 @@ impl eframe::App for MyApp {
             if ui.button("Increment").clicked() {
                 self.age += 1;
             }
+            ui.horizontal(|ui| {
+                let fumen_label = ui.label("Fumen: ");
+                ui.text_edit_singleline(&mut self.fumen)
+                    .labelled_by(fumen_label.id);
+                if ui.button("Load").clicked() {
+                    match Fumen::decode(&self.fumen) {
+                        Ok(fumen) => {
+                            if let Some(page) = fumen.pages.first() {
+                                self.state = page.state();
+                            }
+                            self.fumen_error = None;
+                        }
+                        Err(err) => self.fumen_error = Some(err.to_string()),
+                    }
+                }
+                if ui.button("Copy").clicked() {
+                    let fumen = Fumen::from_state(&self.state).encode();
+                    ui.output_mut(|o| o.copied_text = fumen);
+                }
+            });
+            if let Some(err) = &self.fumen_error {
+                ui.colored_label(egui::Color32::RED, err);
+            }
             ui.horizontal(|ui| {
                 render_tetris(ui, &self.state);
             })
@@ -0,0 +1,202 @@
+//! Compares the column-major [`BitBoard`] with the row-major [`RowBoard`].
+//!
+//! Run with `cargo run --release --bin board_bench`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use game::tetris::{features::BoardFeatures, rows::RowBoard, BitBoard, Board, GameState, Move};
+
+const POSITIONS: usize = 200;
+const ROUNDS: u32 = 20;
+
+fn main() {
+    let positions = sample_positions();
+    let rows = positions
+        .iter()
+        .map(|state| GameState {
+            board: RowBoard::<64>::from(&state.board),
+            hold: state.hold,
+            queue: state.queue.clone(),
+            bag: state.bag,
+            ren: state.ren,
+            b2b: state.b2b,
+        })
+        .collect::<Vec<_>>();
+    println!("{} positions, {} rounds each", positions.len(), ROUNDS);
+
+    report(
+        "movegen",
+        bench(|| movegen(&positions)),
+        bench(|| movegen(&rows)),
+    );
+    report(
+        "row transitions",
+        bench(|| {
+            positions
+                .iter()
+                .map(|state| transitions((0..64).map(|y| state.board.get_row(y))))
+                .sum::<u32>()
+        }),
+        bench(|| {
+            rows.iter()
+                .map(|state| transitions(state.board.rows.iter().map(|&row| row as u64)))
+                .sum::<u32>()
+        }),
+    );
+    report(
+        "column heights",
+        bench(|| heights(&positions)),
+        bench(|| heights(&rows)),
+    );
+    report(
+        "placements",
+        bench(|| place_all(&positions)),
+        bench(|| place_all(&rows)),
+    );
+    report(
+        "features",
+        bench(|| {
+            positions
+                .iter()
+                .map(|state| BoardFeatures::extract(&state.board).holes)
+                .sum::<u32>()
+        }),
+        // the features are extracted from bit boards, so row boards are converted first
+        bench(|| {
+            rows.iter()
+                .map(|state| BoardFeatures::extract(&BitBoard::from(&state.board)).holes)
+                .sum::<u32>()
+        }),
+    );
+    report(
+        "board score",
+        bench(|| score_all(&positions)),
+        bench(|| score_all(&rows)),
+    );
+    report(
+        "convert from",
+        bench(|| {
+            positions
+                .iter()
+                .map(|state| RowBoard::<64>::from(&state.board).rows[0])
+                .sum::<u16>()
+        }),
+        bench(|| {
+            rows.iter()
+                .map(|state| BitBoard::from(&state.board).cols[0])
+                .sum::<u64>()
+        }),
+    );
+}
+
+/// Plays random games to collect positions of various heights.
+fn sample_positions() -> Vec<GameState<BitBoard>> {
+    let mut positions = vec![];
+    let mut state = GameState::<BitBoard>::new();
+    while positions.len() < POSITIONS {
+        while state.queue.len() < 6 {
+            state.fulfill_queue();
+        }
+        let moves = state.legal_moves(true).map(|gen| gen.moves());
+        let Some(&mv) = moves
+            .as_deref()
+            .ok()
+            .and_then(|moves| moves.get(rand::random::<usize>() % moves.len().max(1)))
+        else {
+            state = GameState::new();
+            continue;
+        };
+        positions.push(state.clone());
+        if state.advance(mv).death || state.board.height_of(4) > 16 {
+            state = GameState::new();
+        }
+        if positions.len() % 9 == 0 {
+            state
+                .board
+                .add_garbage_line((rand::random::<u8>() % 10) as i8);
+        }
+    }
+    positions
+}
+
+fn movegen<B: Board>(states: &[GameState<B>]) -> usize {
+    states
+        .iter()
+        .map(|state| state.legal_moves(true).map_or(0, |gen| gen.moves().len()))
+        .sum()
+}
+
+fn transitions(rows: impl Iterator<Item = u64>) -> u32 {
+    rows.map(|row| ((row | 0b1_00000_00000) ^ (1 | row << 1)).count_ones())
+        .sum()
+}
+
+fn heights<B: Board>(states: &[GameState<B>]) -> u32 {
+    states
+        .iter()
+        .map(|state| (0..10).map(|x| state.board.height_of(x)).sum::<u32>())
+        .sum()
+}
+
+fn place_all<B: Board>(states: &[GameState<B>]) -> u32 {
+    let mut cleared = 0;
+    for state in states {
+        let Ok(gen) = state.legal_moves(false) else {
+            continue;
+        };
+        for mv in gen.moves() {
+            if let Move::Place(piece) = mv {
+                let mut board = state.board.clone();
+                cleared += board.add_piece_and_clear(piece);
+            }
+        }
+    }
+    cleared
+}
+
+/// Scores the boards by height, bumpiness and holes through [`Board`] alone.
+///
+/// This is not the evaluator of the bot, whose features are extracted from bit boards only.
+fn score_all<B: Board>(states: &[GameState<B>]) -> i32 {
+    states
+        .iter()
+        .map(|state| {
+            let heights = (0..10).map(|x| state.board.height_of(x) as i32);
+            let bumpiness = heights
+                .clone()
+                .zip(heights.clone().skip(1))
+                .map(|(a, b)| (a - b).abs())
+                .sum::<i32>();
+            let holes = (0..10)
+                .map(|x| {
+                    (0..state.board.height_of(x) as i8)
+                        .filter(|&y| !state.board.occupied((x, y)))
+                        .count() as i32
+                })
+                .sum::<i32>();
+            -heights.max().unwrap() * 10 - bumpiness * 5 - holes * 40
+        })
+        .sum()
+}
+
+fn bench<T>(mut f: impl FnMut() -> T) -> Duration {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        black_box(f());
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn report(name: &str, bit_board: Duration, row_board: Duration) {
+    println!(
+        "{:<16} BitBoard {:>10.3?}  RowBoard {:>10.3?}  ({:.2}x)",
+        name,
+        bit_board,
+        row_board,
+        row_board.as_secs_f64() / bit_board.as_secs_f64()
+    );
+}
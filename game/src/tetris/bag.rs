@@ -0,0 +1,222 @@
+//! Inference of the 7-bag state from observed pieces.
+//!
+//! The tracker considers every bag phase, which is the number of pieces of the first bag that were
+//! drawn before the first observed piece. A phase is consistent when no bag of it contains the
+//! same piece twice. Consistent phases are weighted by the probability of dealing the observed
+//! pieces, assuming each phase is equally likely beforehand.
+
+use std::{error::Error, fmt};
+
+use super::*;
+
+const BAG_SIZE: usize = 7;
+
+/// The observed pieces cannot come from a 7-bag randomizer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BagDesync {
+    /// Index of the offending piece in the history.
+    pub index: usize,
+    pub piece: PieceKind,
+}
+
+impl fmt::Display for BagDesync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "piece {} at {} does not fit any 7-bag phase",
+            self.piece, self.index
+        )
+    }
+}
+
+impl Error for BagDesync {}
+
+#[derive(Clone, Debug, Default)]
+pub struct BagTracker {
+    history: Vec<PieceKind>,
+}
+
+/// What a phase says about the bag the next piece is drawn from.
+struct PhaseState {
+    likelihood: f64,
+    /// Pieces already observed from the current bag.
+    seen: EnumSet<PieceKind>,
+    /// Whether some pieces of the current bag were drawn before the first observation.
+    partial: bool,
+}
+
+impl BagTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_history(pieces: impl IntoIterator<Item = PieceKind>) -> Result<Self, BagDesync> {
+        let mut tracker = Self::new();
+        for piece in pieces {
+            tracker.observe(piece)?;
+        }
+        Ok(tracker)
+    }
+
+    pub fn history(&self) -> &[PieceKind] {
+        &self.history
+    }
+
+    /// Records the next piece.
+    ///
+    /// On a desync the history is dropped, and tracking starts over from this piece.
+    pub fn observe(&mut self, piece: PieceKind) -> Result<(), BagDesync> {
+        self.history.push(piece);
+        if self.phases().is_empty() {
+            let index = self.history.len() - 1;
+            self.history = vec![piece];
+            return Err(BagDesync { index, piece });
+        }
+        Ok(())
+    }
+
+    /// Returns the consistent phases.
+    pub fn phases(&self) -> Vec<usize> {
+        (0..BAG_SIZE)
+            .filter(|&phase| self.phase_state(phase).is_some())
+            .collect()
+    }
+
+    /// Returns the probability of each phase given the history.
+    pub fn phase_probabilities(&self) -> [f64; BAG_SIZE] {
+        let mut probabilities =
+            std::array::from_fn(|phase| self.phase_state(phase).map_or(0.0, |s| s.likelihood));
+        normalize(&mut probabilities);
+        probabilities
+    }
+
+    /// Returns the probability of each piece being drawn next, indexed by `PieceKind as usize`.
+    pub fn next_piece_probabilities(&self) -> [f64; 7] {
+        let mut probabilities = [0.0; 7];
+        for state in (0..BAG_SIZE).filter_map(|phase| self.phase_state(phase)) {
+            // Pieces drawn before the first observation are a uniformly random subset of the unseen
+            // ones, so every unseen piece is equally likely to come next.
+            let unseen = !state.seen;
+            for piece in unseen {
+                probabilities[piece as usize] += state.likelihood / unseen.len() as f64;
+            }
+        }
+        normalize(&mut probabilities);
+        probabilities
+    }
+
+    /// Returns the pieces left in the current bag if every consistent phase agrees on them.
+    pub fn bag(&self) -> Option<SevenBag> {
+        let mut states = (0..BAG_SIZE).filter_map(|phase| self.phase_state(phase));
+        let first = states.next()?;
+        if first.partial {
+            return None;
+        }
+        states
+            .all(|state| !state.partial && state.seen == first.seen)
+            .then(|| remaining(first.seen))
+    }
+
+    /// Returns the bag of the most likely phase. When pieces of the current bag were drawn before
+    /// the first observation, they are assumed to be still in the bag.
+    pub fn likely_bag(&self) -> SevenBag {
+        (0..BAG_SIZE)
+            .filter_map(|phase| self.phase_state(phase))
+            .max_by(|a, b| a.likelihood.total_cmp(&b.likelihood))
+            .map_or(SevenBag::default(), |state| remaining(state.seen))
+    }
+
+    fn phase_state(&self, phase: usize) -> Option<PhaseState> {
+        let mut likelihood = 1.0;
+        let mut seen = EnumSet::empty();
+        for (i, &piece) in self.history.iter().enumerate() {
+            if (phase + i).is_multiple_of(BAG_SIZE) {
+                seen = EnumSet::empty();
+            }
+            if seen.contains(piece) {
+                return None;
+            }
+            likelihood /= (BAG_SIZE - seen.len()) as f64;
+            seen.insert(piece);
+        }
+
+        let next = phase + self.history.len();
+        if next.is_multiple_of(BAG_SIZE) {
+            seen = EnumSet::empty();
+        }
+        Some(PhaseState {
+            likelihood,
+            seen,
+            partial: !next.is_multiple_of(BAG_SIZE) && next < BAG_SIZE && phase > 0,
+        })
+    }
+}
+
+fn remaining(seen: EnumSet<PieceKind>) -> SevenBag {
+    // an empty set is a full bag
+    SevenBag(if seen.is_empty() { seen } else { !seen })
+}
+
+fn normalize(values: &mut [f64]) {
+    let sum = values.iter().sum::<f64>();
+    if sum > 0.0 {
+        values.iter_mut().for_each(|value| *value /= sum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PieceKind::*;
+
+    #[test]
+    fn test_phases() {
+        let tracker = BagTracker::with_history([I, O, T, I]).unwrap();
+        assert_eq!(tracker.phases(), [4, 5, 6]);
+
+        let probabilities = tracker.phase_probabilities();
+        assert_eq!(probabilities[..4], [0.0; 4]);
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let empty = BagTracker::new();
+        assert_eq!(empty.phases().len(), 7);
+        assert_eq!(empty.bag(), None);
+        assert_eq!(empty.likely_bag(), SevenBag::default());
+    }
+
+    #[test]
+    fn test_known_bag() {
+        let tracker = BagTracker::with_history([I, O, T, S, Z, L, J, T, S, Z, L, J, O]).unwrap();
+        assert_eq!(tracker.phases(), [0]);
+        assert_eq!(tracker.bag(), Some(SevenBag(EnumSet::only(I))));
+        assert_eq!(tracker.likely_bag(), SevenBag(EnumSet::only(I)));
+        let next = tracker.next_piece_probabilities();
+        assert!((next[I as usize] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_next_piece() {
+        // a full bag is by far the most likely explanation, so a new bag starts next
+        let tracker = BagTracker::with_history([I, O, T, S, Z, L, J]).unwrap();
+        assert_eq!(tracker.phases().len(), 7);
+        let phases = tracker.phase_probabilities();
+        assert!(phases[0] > 0.5);
+        let next = tracker.next_piece_probabilities();
+        assert!(next.iter().all(|&p| p > 0.0));
+        assert!((next.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        // repeating a piece needs a new bag
+        let tracker = BagTracker::with_history([I]).unwrap();
+        let next = tracker.next_piece_probabilities();
+        assert_eq!(next[I as usize], next.iter().copied().fold(f64::MAX, f64::min));
+    }
+
+    #[test]
+    fn test_desync() {
+        let mut tracker = BagTracker::with_history([I, I]).unwrap();
+        assert_eq!(tracker.phases(), [6]);
+        assert_eq!(tracker.observe(I), Err(BagDesync { index: 2, piece: I }));
+        assert_eq!(tracker.history(), [I]);
+        assert!(tracker.observe(O).is_ok());
+    }
+}
@@ -0,0 +1,315 @@
+//! Serialization helpers and the compact binary encoding of game states.
+
+use std::{error::Error, fmt};
+
+use super::*;
+
+/// Size of a packed [`GameState<BitBoard>`].
+pub const PACKED_STATE_LEN: usize = 96;
+/// Maximum queue length that fits in a packed state.
+pub const PACKED_QUEUE_LEN: usize = 21;
+
+const NO_PIECE: u8 = 7;
+
+pub type PackedState = [u8; PACKED_STATE_LEN];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PackError {
+    QueueTooLong,
+    ComboOutOfRange,
+    InvalidPiece,
+    InvalidBag,
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueTooLong => write!(f, "queue is longer than {} pieces", PACKED_QUEUE_LEN),
+            Self::ComboOutOfRange => write!(f, "ren does not fit in 16 bits"),
+            Self::InvalidPiece => write!(f, "invalid piece code"),
+            Self::InvalidBag => write!(f, "invalid bag mask"),
+        }
+    }
+}
+
+impl Error for PackError {}
+
+impl GameState<BitBoard> {
+    /// Encodes the state into a fixed-size binary form.
+    ///
+    /// | bytes    | content                                                      |
+    /// |----------|--------------------------------------------------------------|
+    /// | `0..80`  | board columns, little-endian `u64` each                      |
+    /// | `80..88` | queue, 3 bits per piece from the lowest bits, `7` terminates |
+    /// | `88`     | hold, `7` if empty                                           |
+    /// | `89`     | bag as a bit set in `PieceKind` order                        |
+    /// | `90..92` | ren, little-endian `i16`                                     |
+    /// | `92`     | b2b                                                          |
+    /// | `93..96` | reserved, zero                                               |
+    pub fn to_bytes(&self) -> Result<PackedState, PackError> {
+        if self.queue.len() > PACKED_QUEUE_LEN {
+            return Err(PackError::QueueTooLong);
+        }
+        let ren = i16::try_from(self.ren).map_err(|_| PackError::ComboOutOfRange)?;
+
+        let mut bytes = [0; PACKED_STATE_LEN];
+        for (x, col) in self.board.cols.iter().enumerate() {
+            bytes[x * 8..(x + 1) * 8].copy_from_slice(&col.to_le_bytes());
+        }
+        let queue = (0..PACKED_QUEUE_LEN).fold(0u64, |acc, i| {
+            let code = self.queue.get(i).map_or(NO_PIECE, |&p| piece_code(p));
+            acc | (code as u64) << (3 * i)
+        });
+        bytes[80..88].copy_from_slice(&queue.to_le_bytes());
+        bytes[88] = self.hold.map_or(NO_PIECE, piece_code);
+        bytes[89] = self.bag.0.as_u8();
+        bytes[90..92].copy_from_slice(&ren.to_le_bytes());
+        bytes[92] = self.b2b as u8;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &PackedState) -> Result<Self, PackError> {
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+
+        let mut board = BitBoard::default();
+        for (x, col) in board.cols.iter_mut().enumerate() {
+            *col = u64_at(x * 8);
+        }
+
+        let packed_queue = u64_at(80);
+        let mut queue = VecDeque::new();
+        for i in 0..PACKED_QUEUE_LEN {
+            match (packed_queue >> (3 * i) & 0b111) as u8 {
+                NO_PIECE => break,
+                code => queue.push_back(code_piece(code)?),
+            }
+        }
+
+        let hold = match bytes[88] {
+            NO_PIECE => None,
+            code => Some(code_piece(code)?),
+        };
+        let bag = EnumSet::try_from_u8(bytes[89]).ok_or(PackError::InvalidBag)?;
+
+        Ok(GameState {
+            board,
+            hold,
+            queue,
+            bag: SevenBag(bag),
+            ren: i16::from_le_bytes([bytes[90], bytes[91]]) as i32,
+            b2b: bytes[92] != 0,
+        })
+    }
+}
+
+fn piece_code(piece: PieceKind) -> u8 {
+    EnumSet::only(piece).as_u8().trailing_zeros() as u8
+}
+
+fn code_piece(code: u8) -> Result<PieceKind, PackError> {
+    EnumSet::<PieceKind>::try_from_u8(1u8.checked_shl(code as u32).unwrap_or(0))
+        .and_then(|set| set.iter().next())
+        .ok_or(PackError::InvalidPiece)
+}
+
+/// Serializes a [`BitBoard`] as 10 columns of 64 booleans, from the floor up.
+/// This is the board layout the trainer reads.
+///
+/// Use with `#[serde(with = "game::tetris::codec::bool_grid")]`.
+pub mod bool_grid {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::BitBoard;
+
+    pub fn serialize<S: Serializer>(board: &BitBoard, serializer: S) -> Result<S::Ok, S::Error> {
+        board
+            .cols
+            .map(|col| std::array::from_fn::<bool, 64, _>(|y| col >> y & 1 == 1))
+            .map(Vec::from)
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BitBoard, D::Error> {
+        let grid = Vec::<Vec<bool>>::deserialize(deserializer)?;
+        if grid.len() != 10 || grid.iter().any(|col| col.len() > 64) {
+            return Err(serde::de::Error::invalid_length(
+                grid.len(),
+                &"10 columns of at most 64 cells",
+            ));
+        }
+        let mut board = BitBoard::default();
+        for (col, cells) in board.cols.iter_mut().zip(grid) {
+            *col = cells
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (y, &cell)| acc | (cell as u64) << y);
+        }
+        Ok(board)
+    }
+}
+
+/// Serializes the board of a [`GameState`] as 10 columns of 64 cells, from the floor up.
+///
+/// Unlike the TBP rows of the boards themselves, no row is dropped, so states round-trip exactly.
+pub mod full_height {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{CellKind, ColoredBoard};
+
+    pub fn serialize<B, S>(board: &B, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        B: Clone + Into<ColoredBoard>,
+        S: Serializer,
+    {
+        let board: ColoredBoard = board.clone().into();
+        board.cols.map(Vec::from).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, B, D>(deserializer: D) -> Result<B, D::Error>
+    where
+        ColoredBoard: Into<B>,
+        D: Deserializer<'de>,
+    {
+        let grid = Vec::<Vec<CellKind>>::deserialize(deserializer)?;
+        if grid.len() != 10 || grid.iter().any(|col| col.len() > 64) {
+            return Err(serde::de::Error::invalid_length(
+                grid.len(),
+                &"10 columns of at most 64 cells",
+            ));
+        }
+        let mut board = ColoredBoard::default();
+        for (col, cells) in board.cols.iter_mut().zip(grid) {
+            col[..cells.len()].copy_from_slice(&cells);
+        }
+        Ok(board.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_board;
+
+    fn sample_state() -> GameState<BitBoard> {
+        let mut state = GameState {
+            board: bit_board! {
+                "____xx____",
+                "xxx_xxxxxx",
+                "xxxxxxx_xx"
+            },
+            hold: Some(PieceKind::I),
+            ren: 3,
+            b2b: true,
+            ..GameState::new()
+        };
+        for piece in [PieceKind::T, PieceKind::S, PieceKind::O] {
+            state.add_piece(piece);
+        }
+        state
+    }
+
+    #[test]
+    fn test_packed_round_trip() {
+        let state = sample_state();
+        let bytes = state.to_bytes().unwrap();
+        assert_eq!(GameState::from_bytes(&bytes).unwrap(), state);
+
+        let empty = GameState::<BitBoard>::new();
+        assert_eq!(
+            GameState::from_bytes(&empty.to_bytes().unwrap()).unwrap(),
+            empty
+        );
+    }
+
+    #[test]
+    fn test_packed_errors() {
+        let mut state = sample_state();
+        state.queue = std::iter::repeat_n(PieceKind::T, 22).collect();
+        assert_eq!(state.to_bytes(), Err(PackError::QueueTooLong));
+
+        let mut bytes = sample_state().to_bytes().unwrap();
+        bytes[89] = 0x80;
+        assert_eq!(GameState::from_bytes(&bytes), Err(PackError::InvalidBag));
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let state = sample_state();
+        let json = serde_json::to_string(&state).unwrap();
+        let restored = serde_json::from_str::<GameState<BitBoard>>(&json).unwrap();
+        assert_eq!(restored, state);
+
+        // the state keeps the rows above the TBP board
+        let mut high = sample_state();
+        high.board.cols[2] |= 1 << 45 | 1 << 63;
+        let json = serde_json::to_string(&high).unwrap();
+        let restored = serde_json::from_str::<GameState<BitBoard>>(&json).unwrap();
+        assert_eq!(restored, high);
+        let colored = GameState {
+            board: high.board.clone().into_colored(CellKind::T),
+            hold: high.hold,
+            queue: high.queue.clone(),
+            bag: high.bag,
+            b2b: high.b2b,
+            ren: high.ren,
+        };
+        let json = serde_json::to_string(&colored).unwrap();
+        assert_eq!(
+            serde_json::from_str::<GameState<ColoredBoard>>(&json).unwrap(),
+            colored
+        );
+
+        // TBP rows never grow past the TBP board, nor silently drop the cells above it
+        let err = serde_json::to_value(&high.board).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("board has cells above its 40 TBP rows"));
+        let rows = serde_json::to_value(&sample_state().board).unwrap();
+        assert_eq!(rows.as_array().unwrap().len(), TBP_BOARD_HEIGHT);
+        let mut rows = serde_json::to_value(&sample_state().board).unwrap();
+        let floor = rows[0].clone();
+        rows.as_array_mut().unwrap().push(floor);
+        let err = serde_json::from_value::<BitBoard>(rows).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("board has 41 rows, expected exactly 40"));
+
+        let mv = Move::Place(PieceState::new(PieceKind::T, (4, 1), Rotation::South));
+        let json = serde_json::to_string(&mv).unwrap();
+        assert_eq!(serde_json::from_str::<Move>(&json).unwrap(), mv);
+
+        let result = PlacementResult::default();
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PlacementResult>(&json).unwrap(),
+            result
+        );
+    }
+
+    #[test]
+    fn test_bool_grid() {
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper(#[serde(with = "bool_grid")] BitBoard);
+
+        let board = sample_state().board;
+        let json = serde_json::to_string(&Wrapper(board.clone())).unwrap();
+        let grid = serde_json::from_str::<Vec<Vec<bool>>>(&json).unwrap();
+        assert_eq!(grid.len(), 10);
+        assert!(grid.iter().all(|col| col.len() == 64));
+        assert!(grid[4][2]);
+        assert!(!grid[3][1]);
+        assert_eq!(serde_json::from_str::<Wrapper>(&json).unwrap().0, board);
+
+        // high stacks are kept, unlike in TBP rows
+        let mut high = board;
+        high.cols[2] |= 1 << 45 | 1 << 63;
+        let json = serde_json::to_string(&Wrapper(high.clone())).unwrap();
+        assert_eq!(serde_json::from_str::<Wrapper>(&json).unwrap().0, high);
+        let mut state = sample_state();
+        state.board = high;
+        assert_eq!(
+            GameState::from_bytes(&state.to_bytes().unwrap()).unwrap(),
+            state
+        );
+    }
+}
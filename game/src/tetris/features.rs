@@ -0,0 +1,311 @@
+//! Board features shared by evaluators, data generation and statistics tools.
+
+use super::{rows::RowBoard, BitBoard, Board};
+
+/// Number of values in [`BoardFeatures::to_array`].
+pub const FEATURE_COUNT: usize = 23;
+
+/// Names of the values in [`BoardFeatures::to_array`], in order.
+pub const FEATURE_NAMES: [&str; FEATURE_COUNT] = [
+    "height_0",
+    "height_1",
+    "height_2",
+    "height_3",
+    "height_4",
+    "height_5",
+    "height_6",
+    "height_7",
+    "height_8",
+    "height_9",
+    "max_height",
+    "holes",
+    "well_x",
+    "well_depth",
+    "bumpiness",
+    "bumpiness_sq",
+    "max_height_diff",
+    "covered_cells",
+    "covered_cells_sq",
+    "cavities",
+    "overhangs",
+    "row_transitions",
+    "filled_cells",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct BoardFeatures {
+    pub heights: [u32; 10],
+    pub max_height: u32,
+    pub holes: u32,
+    pub well_x: i8,
+    pub well_depth: u32,
+    pub bumpiness: u32,
+    pub bumpiness_sq: u32,
+    pub max_height_diff: u32,
+    pub covered_cells: u32,
+    pub covered_cells_sq: u32,
+    pub cavities: u32,
+    pub overhangs: u32,
+    pub row_transitions: u32,
+    pub filled_cells: u32,
+}
+
+impl BoardFeatures {
+    pub fn extract(board: &BitBoard) -> Self {
+        let heights = column_heights(board);
+        let (well_x, well_depth) = well(board);
+        let (bumpiness, bumpiness_sq) = bumpiness(board, well_x);
+        let (covered_cells, covered_cells_sq) = covered_cells(board);
+        let (cavities, overhangs) = cavities_and_overhangs(board);
+        Self {
+            heights,
+            max_height: heights.into_iter().max().unwrap(),
+            holes: holes(board),
+            well_x,
+            well_depth,
+            bumpiness,
+            bumpiness_sq,
+            max_height_diff: max_height_diff(board, well_x),
+            covered_cells,
+            covered_cells_sq,
+            cavities,
+            overhangs,
+            row_transitions: row_transitions(board),
+            filled_cells: board.cols.iter().map(|col| col.count_ones()).sum(),
+        }
+    }
+
+    /// Returns the features as a vector laid out as [`FEATURE_NAMES`].
+    pub fn to_array(&self) -> [f32; FEATURE_COUNT] {
+        let mut array = [0.0; FEATURE_COUNT];
+        for (value, height) in array.iter_mut().zip(self.heights) {
+            *value = height as f32;
+        }
+        array[10..].copy_from_slice(&[
+            self.max_height as f32,
+            self.holes as f32,
+            self.well_x as f32,
+            self.well_depth as f32,
+            self.bumpiness as f32,
+            self.bumpiness_sq as f32,
+            self.max_height_diff as f32,
+            self.covered_cells as f32,
+            self.covered_cells_sq as f32,
+            self.cavities as f32,
+            self.overhangs as f32,
+            self.row_transitions as f32,
+            self.filled_cells as f32,
+        ]);
+        array
+    }
+}
+
+pub fn column_heights(board: &BitBoard) -> [u32; 10] {
+    std::array::from_fn(|x| board.height_of(x as i8))
+}
+
+pub fn max_height(board: &BitBoard) -> u32 {
+    (0..10).map(|x| board.height_of(x)).max().unwrap()
+}
+
+/// Returns the number of empty cells below the top of their column.
+pub fn holes(board: &BitBoard) -> u32 {
+    (0..10)
+        .map(|x| board.height_of(x) - board.cols[x as usize].count_ones())
+        .sum()
+}
+
+#[inline(always)]
+/// Returns the number of covered cells and the sum of squares of the number of covered cells.
+/// A cell is considered covered if there is a block above it.
+pub fn covered_cells(board: &BitBoard) -> (u32, u32) {
+    let mut covered = 0;
+    let mut sq = 0;
+
+    for x in 0..10 {
+        for y in (0..(board.height_of(x).max(3) - 2 - 1)).rev() {
+            if board.occupied((x, y as i8)) {
+                continue;
+            }
+            let cells = board.height_of(x) - y - 1;
+            covered += cells;
+            sq += cells * cells;
+        }
+    }
+
+    (covered, sq)
+}
+
+#[inline(always)]
+/// Returns the well position and depth.
+/// "Well" means the column with the lowest height, column 0 excluded.
+/// Depth is the number of lines below the well that is ready to be cleared.
+pub fn well(board: &BitBoard) -> (i8, u32) {
+    let well = (1..10).min_by_key(|&x| board.height_of(x)).unwrap_or(0);
+
+    let mut depth = 0;
+    for x in 0..10 {
+        let mut y = board.height_of(x) as i32 - 1;
+        while y >= 0 && board.occupied((x, y as i8)) {
+            y -= 1;
+        }
+        if y >= 0 {
+            depth += y;
+        }
+    }
+
+    (well, depth as u32)
+}
+
+/// Returns the bumpiness and the sum of squares of the bumpiness.
+/// Bumpiness is the sum of the absolute differences in height between adjacent columns excluding the well.
+#[inline(always)]
+pub fn bumpiness(board: &BitBoard, well: i8) -> (u32, u32) {
+    let mut bumpiness = 0;
+    let mut bumpiness_sq = 0;
+
+    let mut prev = 0;
+
+    for x in 0..10i8 {
+        if x == well {
+            continue;
+        }
+        let dh = u32::abs_diff(prev, board.height_of(x));
+        bumpiness += dh;
+        bumpiness_sq += dh * dh;
+
+        prev = board.height_of(x);
+    }
+
+    (bumpiness, bumpiness_sq)
+}
+
+/// Returns the largest height difference between adjacent columns, ignoring the pair that starts at the well.
+pub fn max_height_diff(board: &BitBoard, well: i8) -> u32 {
+    (0..9)
+        .filter(|&x| x != well)
+        .map(|x| u32::abs_diff(board.height_of(x), board.height_of(x + 1)))
+        .max()
+        .unwrap()
+}
+
+/// Evaluates the holes in the playfield.
+///
+/// The first returned value is the number of cells that make up fully enclosed spaces (cavities).
+/// The second is the number of cells that make up partially enclosed spaces (overhangs).
+pub fn cavities_and_overhangs(board: &BitBoard) -> (u32, u32) {
+    let mut cavities = 0;
+    let mut overhangs = 0;
+
+    for x in 0..10 {
+        for y in 0..board.height_of(x) as i32 {
+            if board.occupied((x, y as i8)) {
+                continue;
+            }
+
+            if x > 1 && (board.height_of(x - 1) as i32) < y && board.height_of(x - 2) as i32 <= y {
+                overhangs += 1;
+                continue;
+            }
+
+            if x < 8 && (board.height_of(x + 1) as i32) < y && board.height_of(x + 2) as i32 <= y {
+                overhangs += 1;
+                continue;
+            }
+
+            cavities += 1;
+        }
+    }
+
+    (cavities, overhangs)
+}
+
+/// Returns the number of horizontal changes between empty and filled cells, walls counting as filled.
+pub fn row_transitions(board: &BitBoard) -> u32 {
+    // Transposing once is cheaper than rebuilding every row from the columns
+    let rows = RowBoard::<64>::from(board);
+    rows.rows
+        .iter()
+        .map(|&row| ((row | 0b1_00000_00000) ^ (1 | row << 1)).count_ones())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_board;
+
+    fn sample() -> BitBoard {
+        bit_board! {
+            "x_________",
+            "xx___x___x",
+            "xx_x__x_x_",
+            "xxxxxxx_xx"
+        }
+    }
+
+    #[test]
+    fn test_heights_and_holes() {
+        let board = sample();
+        assert_eq!(column_heights(&board), [4, 3, 1, 2, 1, 3, 2, 0, 2, 3]);
+        assert_eq!(max_height(&board), 4);
+        assert_eq!(holes(&board), 2);
+        assert_eq!(holes(&BitBoard::default()), 0);
+    }
+
+    #[test]
+    fn test_well_and_bumpiness() {
+        let board = sample();
+        let (well_x, _) = well(&board);
+        assert_eq!(well_x, 7);
+        // 4, 3, 1, 2, 1, 3, 2, [well], 2, 3
+        // the columns beside the well are compared with each other, and are level
+        assert_eq!(
+            bumpiness(&board, well_x),
+            (
+                4 + 1 + 2 + 1 + 1 + 2 + 1 + 1,
+                16 + 1 + 4 + 1 + 1 + 4 + 1 + 1
+            )
+        );
+        assert_eq!(max_height_diff(&board, well_x), 2);
+    }
+
+    #[test]
+    fn test_holes_kinds() {
+        let board = bit_board! {
+            "xxx_______",
+            "x_x_______",
+            "xxx_______"
+        };
+        assert_eq!(cavities_and_overhangs(&board), (1, 0));
+        assert_eq!(holes(&board), 1);
+
+        let board = bit_board! {
+            "xx________",
+            "x_________",
+            "xx________"
+        };
+        assert_eq!(cavities_and_overhangs(&board), (0, 1));
+    }
+
+    #[test]
+    fn test_row_transitions() {
+        assert_eq!(row_transitions(&BitBoard::default()), 2 * 64);
+        let board = bit_board! { "xxxx_xxxxx" };
+        assert_eq!(row_transitions(&board), 2 + 2 * 63);
+    }
+
+    #[test]
+    fn test_feature_vector() {
+        let board = sample();
+        let features = BoardFeatures::extract(&board);
+        let array = features.to_array();
+        assert_eq!(array.len(), FEATURE_NAMES.len());
+        assert_eq!(array[0], 4.0);
+        let at = |name| array[FEATURE_NAMES.iter().position(|&n| n == name).unwrap()];
+        assert_eq!(at("max_height"), 4.0);
+        assert_eq!(at("holes"), 2.0);
+        assert_eq!(at("well_x"), 7.0);
+        assert_eq!(at("filled_cells"), 19.0);
+    }
+}
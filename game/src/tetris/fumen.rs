@@ -0,0 +1,1025 @@
+//! Encoding and decoding of fumen (v115) strings.
+//!
+//! A fumen is a sequence of pages, each holding a field, an optional piece operation and a comment.
+//! Queues are carried in the comment using the quiz notation `#Q=[hold](current)next`, which is
+//! what solution-finder and the fumen editor use.
+
+use std::{error::Error, fmt, str::FromStr};
+
+use super::*;
+
+const FIELD_TOP: usize = 23;
+const FIELD_WIDTH: usize = 10;
+/// Number of cells including the garbage row below the floor.
+const FIELD_BLOCKS: usize = (FIELD_TOP + 1) * FIELD_WIDTH;
+const ENCODE_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const COMMENT_TABLE_LEN: u32 = 96;
+const MAX_COMMENT_LEN: usize = 4095;
+const QUIZ_PREFIX: &str = "#Q=";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fumen {
+    pub pages: Vec<Page>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    /// Field shown on this page, before the operation is applied.
+    /// Only the lowest 23 rows can be represented.
+    pub field: ColoredBoard,
+    /// The row below the floor, which is pushed into the field when `flags.rise` is set.
+    pub garbage: [CellKind; 10],
+    pub piece: Option<PiecePosition>,
+    pub comment: String,
+    pub flags: PageFlags,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct PageFlags {
+    /// Place the piece and clear lines before moving to the next page.
+    pub lock: bool,
+    /// Raise the field by the garbage row after locking.
+    pub rise: bool,
+    /// Mirror the field horizontally after locking.
+    pub mirror: bool,
+    /// Only stored on the first page, later pages inherit it.
+    pub colorize: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FumenError {
+    UnsupportedVersion,
+    InvalidCharacter(char),
+    UnexpectedEnd,
+    InvalidField,
+    InvalidPiece,
+    InvalidComment,
+}
+
+impl fmt::Display for FumenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion => write!(f, "unsupported fumen version"),
+            Self::InvalidCharacter(c) => write!(f, "invalid character {:?} in fumen data", c),
+            Self::UnexpectedEnd => write!(f, "fumen data ended unexpectedly"),
+            Self::InvalidField => write!(f, "field data overflows the field"),
+            Self::InvalidPiece => write!(f, "invalid piece operation"),
+            Self::InvalidComment => write!(f, "invalid comment"),
+        }
+    }
+}
+
+impl Error for FumenError {}
+
+impl Default for PageFlags {
+    fn default() -> Self {
+        Self {
+            lock: true,
+            rise: false,
+            mirror: false,
+            colorize: true,
+        }
+    }
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self {
+            field: ColoredBoard::default(),
+            garbage: [CellKind::None; 10],
+            piece: None,
+            comment: String::new(),
+            flags: PageFlags::default(),
+        }
+    }
+}
+
+impl Page {
+    /// Creates a page showing the board of `state`, with its hold and queue as a quiz comment.
+    pub fn from_state(state: &GameState<ColoredBoard>) -> Self {
+        Self {
+            field: state.board.clone(),
+            comment: Quiz::from_state(state).to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Reconstructs a game state from this page.
+    ///
+    /// The hold and queue are read from a quiz comment if present.
+    /// Fumen has no notion of a bag, so the bag is assumed to restart whenever the queue repeats a piece.
+    pub fn state(&self) -> GameState<ColoredBoard> {
+        let mut state = GameState {
+            board: self.field.clone(),
+            ..GameState::new()
+        };
+        if let Some(quiz) = Quiz::parse(&self.comment) {
+            state.hold = quiz.hold;
+            for piece in quiz.current.into_iter().chain(quiz.next) {
+                if !state.bag.has(piece) {
+                    state.bag = SevenBag::default();
+                }
+                state.add_piece(piece);
+            }
+        }
+        state
+    }
+}
+
+impl Fumen {
+    /// Creates a single page fumen from `state`.
+    pub fn from_state(state: &GameState<ColoredBoard>) -> Self {
+        Self {
+            pages: vec![Page::from_state(state)],
+        }
+    }
+
+    /// Creates a fumen with one page per placement in `moves`, followed by a page with the resulting board.
+    /// Holds are not pages of their own; they are implied by the quiz comments.
+    pub fn from_moves(state: &GameState<ColoredBoard>, moves: &[Move]) -> Self {
+        let mut state = state.clone();
+        let mut before_hold = state.clone();
+        let mut pages = vec![];
+        for &mv in moves {
+            let Move::Place(piece) = mv else {
+                state.advance(mv);
+                continue;
+            };
+            pages.push(Page {
+                piece: Some(piece.pos),
+                ..Page::from_state(&before_hold)
+            });
+            state.advance(mv);
+            before_hold = state.clone();
+        }
+        pages.push(Page::from_state(&state));
+        Self { pages }
+    }
+
+    pub fn decode(s: &str) -> Result<Self, FumenError> {
+        // Accept bare data as well as editor URLs
+        let start = s.find("115@").ok_or(FumenError::UnsupportedVersion)?;
+        if !matches!(
+            s[..start].chars().last(),
+            None | Some('v' | 'm' | 'd' | 'D')
+        ) {
+            return Err(FumenError::UnsupportedVersion);
+        }
+        let data = s[start + 4..]
+            .trim()
+            .chars()
+            .filter(|&c| c != '?')
+            .collect::<String>();
+        let mut values = Reader::new(&data);
+
+        let mut pages: Vec<Page> = vec![];
+        let mut prev = RawField::default();
+        let mut repeat = 0;
+        let mut last_comment = String::new();
+        let mut quiz: Option<Quiz> = None;
+        while !values.is_empty() {
+            let field = if repeat > 0 {
+                repeat -= 1;
+                prev.clone()
+            } else {
+                let (field, changed) = decode_field(&mut values, &prev)?;
+                if !changed {
+                    repeat = values.poll(1)?;
+                }
+                field
+            };
+
+            let action = values.poll(3)?;
+            let (piece, mut flags, has_comment) = decode_action(action)?;
+            if let Some(first) = pages.first() {
+                flags.colorize = first.flags.colorize;
+            }
+
+            let comment = if has_comment {
+                let comment = decode_comment(&mut values)?;
+                quiz = Quiz::parse(&comment);
+                last_comment = comment.clone();
+                comment
+            } else if let Some(quiz) = &quiz {
+                quiz.to_string()
+            } else {
+                last_comment.clone()
+            };
+
+            if let (true, Some(piece), Some(q)) = (flags.lock, piece, &quiz) {
+                if let Some(next) = q.operate(piece.kind) {
+                    quiz = Some(next);
+                }
+            }
+
+            let page = Page {
+                field: field.board(),
+                garbage: field.garbage(),
+                piece,
+                comment,
+                flags,
+            };
+            prev = field.after(&page);
+            pages.push(page);
+        }
+
+        Ok(Self { pages })
+    }
+
+    pub fn encode(&self) -> String {
+        let mut values = Writer::default();
+        let mut prev = RawField::default();
+        // Position of the repeat counter of the last unchanged field, if it can still be extended
+        let mut repeat_at: Option<usize> = None;
+        let mut last_comment = String::new();
+        let mut quiz: Option<Quiz> = None;
+
+        for (i, page) in self.pages.iter().enumerate() {
+            let field = RawField::from_page(page);
+            match (encode_field(&mut values, &prev, &field), repeat_at) {
+                (true, _) => repeat_at = None,
+                (false, Some(at)) if values.get(at) < 63 => values.set(at, values.get(at) + 1),
+                (false, _) => {
+                    values.push_field_unchanged();
+                    values.push(0, 1);
+                    repeat_at = Some(values.len() - 1);
+                }
+            }
+
+            let inferred = match &quiz {
+                Some(quiz) => quiz.to_string(),
+                None => last_comment.clone(),
+            };
+            let has_comment = page.comment != inferred;
+            values.push(encode_action(page, i == 0, has_comment), 3);
+            if has_comment {
+                encode_comment(&mut values, &page.comment);
+                quiz = Quiz::parse(&page.comment);
+                last_comment = page.comment.clone();
+            }
+
+            if let (true, Some(piece), Some(q)) = (page.flags.lock, page.piece, &quiz) {
+                if let Some(next) = q.operate(piece.kind) {
+                    quiz = Some(next);
+                }
+            }
+
+            prev = field.after(page);
+        }
+
+        let data = values.into_string();
+        let mut out = String::from("v115@");
+        // The editor inserts a '?' every 47 characters, counting the 5 character prefix
+        let (head, tail) = data.split_at(data.len().min(42));
+        out.push_str(head);
+        for chunk in tail.as_bytes().chunks(47) {
+            out.push('?');
+            out.push_str(std::str::from_utf8(chunk).unwrap());
+        }
+        out
+    }
+}
+
+impl FromStr for Fumen {
+    type Err = FumenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::decode(s)
+    }
+}
+
+impl fmt::Display for Fumen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.encode())
+    }
+}
+
+/// Field in fumen layout: cell values indexed from the top row, with the garbage row last.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RawField([u8; FIELD_BLOCKS]);
+
+impl Default for RawField {
+    fn default() -> Self {
+        Self([0; FIELD_BLOCKS])
+    }
+}
+
+impl RawField {
+    /// `y == -1` is the garbage row.
+    fn index(x: usize, y: i32) -> usize {
+        (FIELD_TOP as i32 - y - 1) as usize * FIELD_WIDTH + x
+    }
+
+    fn from_page(page: &Page) -> Self {
+        let mut field = Self::default();
+        for x in 0..FIELD_WIDTH {
+            for y in 0..FIELD_TOP {
+                field.0[Self::index(x, y as i32)] = cell_to_fumen(page.field.cols[x][y]);
+            }
+            field.0[Self::index(x, -1)] = cell_to_fumen(page.garbage[x]);
+        }
+        field
+    }
+
+    fn board(&self) -> ColoredBoard {
+        let mut board = ColoredBoard::default();
+        for x in 0..FIELD_WIDTH {
+            for y in 0..FIELD_TOP {
+                board.cols[x][y] = fumen_to_cell(self.0[Self::index(x, y as i32)]);
+            }
+        }
+        board
+    }
+
+    fn garbage(&self) -> [CellKind; 10] {
+        std::array::from_fn(|x| fumen_to_cell(self.0[Self::index(x, -1)]))
+    }
+
+    /// Returns the field the next page is based on.
+    fn after(&self, page: &Page) -> Self {
+        let mut field = self.clone();
+        if !page.flags.lock {
+            return field;
+        }
+
+        if let Some(piece) = page.piece {
+            for (x, y) in piece.cells() {
+                if (0..FIELD_WIDTH as i8).contains(&x) && (0..FIELD_TOP as i8).contains(&y) {
+                    field.0[Self::index(x as usize, y as i32)] = piece_to_fumen(piece.kind);
+                }
+            }
+        }
+
+        // Clear filled rows, the garbage row is never cleared
+        let rows = field.0[..FIELD_TOP * FIELD_WIDTH]
+            .chunks(FIELD_WIDTH)
+            .filter(|row| row.contains(&0))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        let cleared = FIELD_TOP * FIELD_WIDTH - rows.len();
+        field.0[..cleared].fill(0);
+        field.0[cleared..FIELD_TOP * FIELD_WIDTH].copy_from_slice(&rows);
+
+        if page.flags.rise {
+            field.0.copy_within(FIELD_WIDTH.., 0);
+            field.0[FIELD_TOP * FIELD_WIDTH..].fill(0);
+        }
+        if page.flags.mirror {
+            field.0[..FIELD_TOP * FIELD_WIDTH]
+                .chunks_mut(FIELD_WIDTH)
+                .for_each(|row| row.reverse());
+        }
+        field
+    }
+}
+
+fn decode_field(values: &mut Reader, prev: &RawField) -> Result<(RawField, bool), FumenError> {
+    let mut field = prev.clone();
+    let mut changed = true;
+    let mut index = 0;
+    while index < FIELD_BLOCKS {
+        let run = values.poll(2)? as usize;
+        let diff = run / FIELD_BLOCKS;
+        let count = run % FIELD_BLOCKS + 1;
+        if diff == 8 && count == FIELD_BLOCKS {
+            changed = false;
+        }
+        if index + count > FIELD_BLOCKS {
+            return Err(FumenError::InvalidField);
+        }
+        for cell in &mut field.0[index..index + count] {
+            let value = *cell as usize + diff;
+            if !(8..=16).contains(&value) {
+                return Err(FumenError::InvalidField);
+            }
+            *cell = (value - 8) as u8;
+        }
+        index += count;
+    }
+    Ok((field, changed))
+}
+
+/// Writes the difference between two fields and returns whether they differ.
+fn encode_field(values: &mut Writer, prev: &RawField, current: &RawField) -> bool {
+    if prev == current {
+        return false;
+    }
+    let diff = |i: usize| (current.0[i] as usize + 8 - prev.0[i] as usize) as u32;
+
+    let mut run_diff = diff(0);
+    let mut run_len = 1;
+    for i in 1..FIELD_BLOCKS {
+        let d = diff(i);
+        if d != run_diff {
+            values.push(run_diff * FIELD_BLOCKS as u32 + run_len - 1, 2);
+            run_diff = d;
+            run_len = 0;
+        }
+        run_len += 1;
+    }
+    values.push(run_diff * FIELD_BLOCKS as u32 + run_len - 1, 2);
+    true
+}
+
+fn decode_action(mut value: u32) -> Result<(Option<PiecePosition>, PageFlags, bool), FumenError> {
+    let kind = value % 8;
+    value /= 8;
+    let rot = match value % 4 {
+        0 => Rotation::South,
+        1 => Rotation::East,
+        2 => Rotation::North,
+        _ => Rotation::West,
+    };
+    value /= 4;
+    let coordinate = value % FIELD_BLOCKS as u32;
+    value /= FIELD_BLOCKS as u32;
+    let rise = value % 2 == 1;
+    value /= 2;
+    let mirror = value % 2 == 1;
+    value /= 2;
+    let colorize = value % 2 == 1;
+    value /= 2;
+    let comment = value % 2 == 1;
+    value /= 2;
+    let no_lock = value % 2 == 1;
+
+    let piece = match fumen_to_cell(kind as u8) {
+        CellKind::None => None,
+        cell => {
+            let kind = cell.piece().ok_or(FumenError::InvalidPiece)?;
+            let x = (coordinate as usize % FIELD_WIDTH) as i8;
+            let y = (FIELD_TOP - coordinate as usize / FIELD_WIDTH - 1) as i8;
+            let (dx, dy) = center_offset(kind, rot);
+            Some(PiecePosition {
+                kind,
+                x: x - dx,
+                y: y - dy,
+                rot,
+            })
+        }
+    };
+
+    let flags = PageFlags {
+        lock: !no_lock,
+        rise,
+        mirror,
+        colorize,
+    };
+    Ok((piece, flags, comment))
+}
+
+fn encode_action(page: &Page, first_page: bool, has_comment: bool) -> u32 {
+    let (kind, rot, coordinate) = match page.piece {
+        Some(piece) => {
+            let (dx, dy) = center_offset(piece.kind, piece.rot);
+            let x = (piece.x + dx) as u32;
+            let y = (piece.y + dy) as i32;
+            let rot = match piece.rot {
+                Rotation::South => 0,
+                Rotation::East => 1,
+                Rotation::North => 2,
+                Rotation::West => 3,
+            };
+            let coordinate = (FIELD_TOP as i32 - y - 1) as u32 * FIELD_WIDTH as u32 + x;
+            (piece_to_fumen(piece.kind) as u32, rot, coordinate)
+        }
+        None => (0, 0, 0),
+    };
+
+    let mut value = !page.flags.lock as u32;
+    value = value * 2 + has_comment as u32;
+    value = value * 2 + (first_page && page.flags.colorize) as u32;
+    value = value * 2 + page.flags.mirror as u32;
+    value = value * 2 + page.flags.rise as u32;
+    value = value * FIELD_BLOCKS as u32 + coordinate;
+    value = value * 4 + rot;
+    value * 8 + kind
+}
+
+/// Fumen places some pieces by a different center cell than SRS.
+/// Returns the offset from the SRS center to the fumen one.
+const fn center_offset(kind: PieceKind, rot: Rotation) -> (i8, i8) {
+    match (kind, rot) {
+        (PieceKind::O, Rotation::North) => (0, 1),
+        (PieceKind::O, Rotation::South) => (-1, 0),
+        (PieceKind::O, Rotation::West) => (-1, 1),
+        (PieceKind::I, Rotation::South) => (-1, 0),
+        (PieceKind::I, Rotation::West) => (0, 1),
+        (PieceKind::S, Rotation::North) => (0, 1),
+        (PieceKind::S, Rotation::East) => (1, 0),
+        (PieceKind::Z, Rotation::North) => (0, 1),
+        (PieceKind::Z, Rotation::West) => (-1, 0),
+        _ => (0, 0),
+    }
+}
+
+fn decode_comment(values: &mut Reader) -> Result<String, FumenError> {
+    let len = values.poll(2)? as usize;
+    let mut escaped = String::with_capacity(len);
+    while escaped.len() < len {
+        let mut value = values.poll(5)?;
+        for _ in 0..(len - escaped.len()).min(4) {
+            escaped.push((value % COMMENT_TABLE_LEN + 32) as u8 as char);
+            value /= COMMENT_TABLE_LEN;
+        }
+    }
+    unescape(&escaped).ok_or(FumenError::InvalidComment)
+}
+
+fn encode_comment(values: &mut Writer, comment: &str) {
+    let mut escaped = escape(comment);
+    escaped.truncate(MAX_COMMENT_LEN);
+    values.push(escaped.len() as u32, 2);
+    for chunk in escaped.as_bytes().chunks(4) {
+        let value = chunk
+            .iter()
+            .rev()
+            .fold(0, |acc, &c| acc * COMMENT_TABLE_LEN + (c as u32 - 32));
+        values.push(value, 5);
+    }
+}
+
+/// Equivalent of JavaScript's `escape`, which the editor applies to comments.
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for unit in s.encode_utf16() {
+        match char::from_u32(unit as u32) {
+            Some(c) if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) => out.push(c),
+            _ if unit < 0x100 => out.push_str(&format!("%{:02X}", unit)),
+            _ => out.push_str(&format!("%u{:04X}", unit)),
+        }
+    }
+    out
+}
+
+/// Equivalent of JavaScript's `unescape`.
+fn unescape(s: &str) -> Option<String> {
+    let mut units = vec![];
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let hex = |digits: &str| u16::from_str_radix(digits, 16).ok();
+        let (unit, len) = match (c, rest.get(1..2)) {
+            ('%', Some("u")) => match rest.get(2..6).and_then(hex) {
+                Some(unit) => (unit, 6),
+                None => ('%' as u16, 1),
+            },
+            ('%', _) => match rest.get(1..3).and_then(hex) {
+                Some(unit) => (unit, 3),
+                None => ('%' as u16, 1),
+            },
+            _ => (c as u16, c.len_utf8()),
+        };
+        units.push(unit);
+        rest = &rest[len..];
+    }
+    String::from_utf16(&units).ok()
+}
+
+/// Queue notation used by the editor's quiz mode: `#Q=[hold](current)next`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Quiz {
+    hold: Option<PieceKind>,
+    current: Option<PieceKind>,
+    next: Vec<PieceKind>,
+}
+
+impl Quiz {
+    fn from_state<B: Board>(state: &GameState<B>) -> Self {
+        Self {
+            hold: state.hold,
+            current: state.queue.front().copied(),
+            next: state.queue.iter().skip(1).copied().collect(),
+        }
+    }
+
+    fn parse(comment: &str) -> Option<Self> {
+        let rest = comment.strip_prefix(QUIZ_PREFIX)?;
+        let rest = rest.strip_prefix('[')?;
+        let (hold, rest) = rest.split_once(']')?;
+        let rest = rest.strip_prefix('(')?;
+        let (current, rest) = rest.split_once(')')?;
+        let next = rest.split(|c: char| c == ';' || c.is_whitespace()).next()?;
+
+        let piece = |s: &str| match s {
+            "" => Some(None),
+            s => char_to_piece(s.chars().next()?).map(Some),
+        };
+        Some(Self {
+            hold: piece(hold)?,
+            current: piece(current)?,
+            next: next.chars().map(char_to_piece).collect::<Option<_>>()?,
+        })
+    }
+
+    /// Returns the quiz after `used` has been placed, holding if needed.
+    fn operate(&self, used: PieceKind) -> Option<Self> {
+        let mut next = self.next.clone();
+        let mut pop = || (!next.is_empty()).then(|| next.remove(0));
+        let (hold, current) = if self.current == Some(used) {
+            (self.hold, pop())
+        } else if self.hold == Some(used) {
+            (self.current, pop())
+        } else if self.hold.is_none() && self.next.first() == Some(&used) {
+            pop();
+            (self.current, pop())
+        } else {
+            return None;
+        };
+        Some(Self {
+            hold,
+            current,
+            next,
+        })
+    }
+}
+
+impl fmt::Display for Quiz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let piece = |p: Option<PieceKind>| p.map_or(String::new(), |p| p.to_string());
+        write!(
+            f,
+            "{}[{}]({}){}",
+            QUIZ_PREFIX,
+            piece(self.hold),
+            piece(self.current),
+            self.next.iter().map(|p| p.to_string()).collect::<String>()
+        )
+    }
+}
+
+fn char_to_piece(c: char) -> Option<PieceKind> {
+    match c.to_ascii_uppercase() {
+        'S' => Some(PieceKind::S),
+        'Z' => Some(PieceKind::Z),
+        'J' => Some(PieceKind::J),
+        'L' => Some(PieceKind::L),
+        'T' => Some(PieceKind::T),
+        'O' => Some(PieceKind::O),
+        'I' => Some(PieceKind::I),
+        _ => None,
+    }
+}
+
+const fn piece_to_fumen(kind: PieceKind) -> u8 {
+    match kind {
+        PieceKind::I => 1,
+        PieceKind::L => 2,
+        PieceKind::O => 3,
+        PieceKind::Z => 4,
+        PieceKind::T => 5,
+        PieceKind::J => 6,
+        PieceKind::S => 7,
+    }
+}
+
+const fn cell_to_fumen(cell: CellKind) -> u8 {
+    match cell {
+        CellKind::None => 0,
+        CellKind::I => 1,
+        CellKind::L => 2,
+        CellKind::O => 3,
+        CellKind::Z => 4,
+        CellKind::T => 5,
+        CellKind::J => 6,
+        CellKind::S => 7,
+        CellKind::Gbg => 8,
+    }
+}
+
+const fn fumen_to_cell(value: u8) -> CellKind {
+    match value {
+        1 => CellKind::I,
+        2 => CellKind::L,
+        3 => CellKind::O,
+        4 => CellKind::Z,
+        5 => CellKind::T,
+        6 => CellKind::J,
+        7 => CellKind::S,
+        8 => CellKind::Gbg,
+        _ => CellKind::None,
+    }
+}
+
+/// Reads little-endian base64 numbers.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a str) -> Self {
+        Self {
+            data: data.as_bytes(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn poll(&mut self, digits: usize) -> Result<u32, FumenError> {
+        if self.data.len() < digits {
+            return Err(FumenError::UnexpectedEnd);
+        }
+        let (head, rest) = self.data.split_at(digits);
+        self.data = rest;
+        head.iter().rev().try_fold(0, |acc, &c| {
+            let digit = ENCODE_TABLE
+                .iter()
+                .position(|&e| e == c)
+                .ok_or(FumenError::InvalidCharacter(c as char))?;
+            Ok(acc * 64 + digit as u32)
+        })
+    }
+}
+
+/// Writes base64 digits, keeping them addressable so repeat counters can be patched.
+#[derive(Default)]
+struct Writer {
+    digits: Vec<u32>,
+}
+
+impl Writer {
+    fn push(&mut self, mut value: u32, digits: usize) {
+        for _ in 0..digits {
+            self.digits.push(value % 64);
+            value /= 64;
+        }
+    }
+
+    fn push_field_unchanged(&mut self) {
+        self.push(8 * FIELD_BLOCKS as u32 + FIELD_BLOCKS as u32 - 1, 2);
+    }
+
+    fn len(&self) -> usize {
+        self.digits.len()
+    }
+
+    fn get(&self, index: usize) -> u32 {
+        self.digits[index]
+    }
+
+    fn set(&mut self, index: usize, digit: u32) {
+        self.digits[index] = digit;
+    }
+
+    fn into_string(self) -> String {
+        self.digits
+            .into_iter()
+            .map(|d| ENCODE_TABLE[d as usize] as char)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_board;
+
+    #[test]
+    fn test_empty() {
+        let fumen = Fumen::decode("v115@vhAAgH").unwrap();
+        assert_eq!(fumen.pages, vec![Page::default()]);
+        assert_eq!(fumen.encode(), "v115@vhAAgH");
+    }
+
+    #[test]
+    fn test_single_piece() {
+        let fumen = Fumen::decode("https://fumen.zui.jp/?v115@vhARQJ").unwrap();
+        assert_eq!(
+            fumen.pages[0].piece,
+            Some(PiecePosition {
+                kind: PieceKind::I,
+                x: 4,
+                y: 0,
+                rot: Rotation::North
+            })
+        );
+        assert_eq!(fumen.encode(), "v115@vhARQJ");
+    }
+
+    #[test]
+    fn test_center_offset() {
+        // Every orientation must round trip through the fumen coordinate system
+        for kind in EnumSet::<PieceKind>::all() {
+            for rot in [
+                Rotation::North,
+                Rotation::East,
+                Rotation::South,
+                Rotation::West,
+            ] {
+                let piece = PiecePosition {
+                    kind,
+                    x: 4,
+                    y: 5,
+                    rot,
+                };
+                let page = Page {
+                    piece: Some(piece),
+                    flags: PageFlags {
+                        lock: false,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                let fumen = Fumen { pages: vec![page] };
+                let decoded = Fumen::decode(&fumen.encode()).unwrap();
+                assert_eq!(decoded.pages[0].piece, Some(piece));
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_state() {
+        let board = bit_board! {
+            "xxxx___xxx",
+            "xxx____xxx",
+            "xxxx___xxx",
+            "xxxxx__xxx"
+        };
+        let state = GameState {
+            board: board.into_colored(CellKind::Gbg),
+            hold: Some(PieceKind::T),
+            queue: [PieceKind::S, PieceKind::Z, PieceKind::O].into(),
+            bag: SevenBag(PieceKind::T | PieceKind::S | PieceKind::Z | PieceKind::O),
+            ..GameState::new()
+        };
+        let encoded = Fumen::from_state(&state).encode();
+        let decoded = Fumen::decode(&encoded).unwrap();
+        assert_eq!(decoded.pages[0].comment, "#Q=[T](S)ZO");
+
+        let restored = decoded.pages[0].state();
+        assert_eq!(restored.board, state.board);
+        assert_eq!(restored.hold, state.hold);
+        assert_eq!(restored.queue, state.queue);
+    }
+
+    #[test]
+    fn test_pages() {
+        let state = GameState {
+            queue: [PieceKind::I, PieceKind::O, PieceKind::T, PieceKind::S].into(),
+            ..GameState::new()
+        };
+        let moves = [
+            Move::Place(PieceState::new(PieceKind::I, (1, 0), Rotation::North)),
+            Move::Hold,
+            Move::Place(PieceState::new(PieceKind::T, (5, 0), Rotation::North)),
+        ];
+        let fumen = Fumen::from_moves(&state, &moves);
+        assert_eq!(fumen.pages.len(), 3);
+
+        let encoded = fumen.encode();
+        let decoded = Fumen::decode(&encoded).unwrap();
+        assert_eq!(decoded, fumen);
+        assert_eq!(decoded.encode(), encoded);
+
+        let last = decoded.pages.last().unwrap().state();
+        assert_eq!(last.hold, Some(PieceKind::O));
+        assert_eq!(last.queue, [PieceKind::S]);
+        assert_eq!(
+            Into::<BitBoard>::into(last.board),
+            bit_board! {
+                "_____x____",
+                "xxxxxxx___"
+            }
+        );
+    }
+
+    #[test]
+    fn test_line_clear_and_repeat() {
+        let mut board = ColoredBoard::default();
+        for x in 0..9 {
+            board.cols[x][0] = CellKind::Gbg;
+        }
+        let mut cleared = ColoredBoard::default();
+        for y in 0..3 {
+            cleared.cols[9][y] = CellKind::I;
+        }
+        let page = Page {
+            field: cleared,
+            ..Default::default()
+        };
+        let fumen = Fumen {
+            pages: vec![
+                Page {
+                    field: board,
+                    piece: Some(PiecePosition {
+                        kind: PieceKind::I,
+                        x: 9,
+                        y: 2,
+                        rot: Rotation::East,
+                    }),
+                    ..Default::default()
+                },
+                page.clone(),
+                page.clone(),
+                Page {
+                    comment: "hello, fumen?".to_owned(),
+                    ..page
+                },
+            ],
+        };
+        let encoded = fumen.encode();
+        // The unchanged fields are stored as a single repeat
+        assert!(encoded.contains("vhC"));
+        let decoded = Fumen::decode(&encoded).unwrap();
+        assert_eq!(decoded, fumen);
+    }
+
+    #[test]
+    fn test_decode_rise() {
+        // An I clears the bottom row and the garbage row rises, then an O rises on an empty garbage row
+        let fumen = Fumen::decode("v115@bhI8AeD8AeE85ALvhBTcDAAA").unwrap();
+        assert_eq!(fumen.pages.len(), 3);
+
+        let first = &fumen.pages[0];
+        assert_eq!(
+            Into::<BitBoard>::into(first.field.clone()),
+            bit_board! { "xxxxxxxxx_" }
+        );
+        assert_eq!(
+            first.garbage.map(|cell| cell != CellKind::None),
+            [true, true, true, true, false, true, true, true, true, true]
+        );
+        assert_eq!(
+            first.piece,
+            Some(PiecePosition {
+                kind: PieceKind::I,
+                x: 9,
+                y: 1,
+                rot: Rotation::West
+            })
+        );
+        assert!(first.flags.rise);
+
+        let second = &fumen.pages[1];
+        assert_eq!(
+            Into::<BitBoard>::into(second.field.clone()),
+            bit_board! {
+                "_________x",
+                "_________x",
+                "_________x",
+                "xxxx_xxxxx"
+            }
+        );
+        assert_eq!(second.field.cols[9][1], CellKind::I);
+        assert_eq!(second.field.cols[0][0], CellKind::Gbg);
+        assert_eq!(second.garbage, [CellKind::None; 10]);
+
+        let third = &fumen.pages[2];
+        assert_eq!(
+            Into::<BitBoard>::into(third.field.clone()),
+            bit_board! {
+                "_________x",
+                "xx_______x",
+                "xx_______x",
+                "xxxx_xxxxx",
+                "__________"
+            }
+        );
+        assert_eq!(third.field.cols[0][2], CellKind::O);
+        assert_eq!(third.piece, None);
+    }
+
+    #[test]
+    fn test_decode_center_offset() {
+        // Unlocked pages with every piece in every orientation at (4, 5), in the order below
+        let fumen = Fumen::decode(
+            "v115@vhbR3mJXfhWfZSfTSfLXfjWf7RfVXfNXfFXfdXfSXf?KXfCXfaXfWXfOXfGXfeXfXSfvXfHXffXfUSfMXfEXf8Wf",
+        )
+        .unwrap();
+        let kinds = [
+            PieceKind::I,
+            PieceKind::O,
+            PieceKind::T,
+            PieceKind::L,
+            PieceKind::J,
+            PieceKind::S,
+            PieceKind::Z,
+        ];
+        let rots = [
+            Rotation::North,
+            Rotation::East,
+            Rotation::South,
+            Rotation::West,
+        ];
+        let expected = kinds
+            .iter()
+            .flat_map(|&kind| {
+                rots.iter().map(move |&rot| PiecePosition {
+                    kind,
+                    x: 4,
+                    y: 5,
+                    rot,
+                })
+            })
+            .collect::<Vec<_>>();
+        let actual = fumen
+            .pages
+            .iter()
+            .map(|page| page.piece.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+        assert!(fumen.pages.iter().all(|page| page.field.is_empty()));
+    }
+}
@@ -8,8 +8,17 @@ use movegen::MoveGenerator;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 
+pub mod bag;
+pub mod codec;
+pub mod features;
+pub mod fumen;
 pub mod movegen;
+pub mod pc;
+pub mod rows;
 pub mod tbp;
+pub mod text;
+pub mod tslot;
+pub mod zobrist;
 
 #[derive(Debug, Hash, PartialOrd, Ord, Serialize, Deserialize, EnumSetType)]
 pub enum Instruction {
@@ -88,6 +97,21 @@ pub enum CellKind {
     Gbg,
 }
 
+impl CellKind {
+    pub const fn piece(&self) -> Option<PieceKind> {
+        match self {
+            Self::S => Some(PieceKind::S),
+            Self::Z => Some(PieceKind::Z),
+            Self::J => Some(PieceKind::J),
+            Self::L => Some(PieceKind::L),
+            Self::T => Some(PieceKind::T),
+            Self::O => Some(PieceKind::O),
+            Self::I => Some(PieceKind::I),
+            Self::None | Self::Gbg => None,
+        }
+    }
+}
+
 impl From<PieceKind> for CellKind {
     fn from(value: PieceKind) -> Self {
         match value {
@@ -170,7 +194,8 @@ pub struct PieceState {
     pub spin: SpinKind,
 }
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Move {
     Hold, // hold_only
     Place(PieceState),
@@ -204,7 +229,7 @@ impl PieceState {
     }
 }
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct PlacementResult {
     pub lines_cleared: u32,
     pub ren: i32,
@@ -258,10 +283,42 @@ impl PlacementResult {
     }
 }
 
+/// Garbage that rises beneath the stack when the next piece locks.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingGarbage {
+    pub lines: u32,
+    /// The column of the hole in every garbage line.
+    pub column: i8,
+}
+
+impl PendingGarbage {
+    pub fn new(lines: u32, column: i8) -> Self {
+        Self { lines, column }
+    }
+}
+
+/// The current piece cannot spawn, so there is no move to generate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Suffocated;
+
+impl fmt::Display for Suffocated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the current piece cannot spawn")
+    }
+}
+
+impl std::error::Error for Suffocated {}
+
 /// A 7-bag implementation as per guideline.
 /// If the bag is full, the internal set must be empty.
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct SevenBag(pub EnumSet<PieceKind>);
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SevenBag(
+    #[serde(
+        deserialize_with = "tbp::collect_enumset",
+        serialize_with = "tbp::serialize_enumset"
+    )]
+    pub EnumSet<PieceKind>,
+);
 
 impl SevenBag {
     pub fn has(&self, piece: PieceKind) -> bool {
@@ -320,22 +377,39 @@ pub trait Board: Clone + Default {
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize)]
-#[serde(from = "Vec<[Option<char>; 10]>")]
+#[serde(try_from = "Vec<[Option<char>; 10]>")]
 pub struct BitBoard {
     pub cols: [u64; 10],
 }
 
-impl From<Vec<[Option<char>; 10]>> for BitBoard {
-    fn from(v: Vec<[Option<char>; 10]>) -> Self {
-        let mut cols = [0; 10];
-        for x in 0..10 {
-            for y in 0..40 {
-                if v[y][x].is_some() {
-                    cols[x] |= 1 << y;
-                }
-            }
+impl TryFrom<Vec<[Option<char>; 10]>> for BitBoard {
+    type Error = BoardError;
+
+    fn try_from(v: Vec<[Option<char>; 10]>) -> Result<Self, Self::Error> {
+        ColoredBoard::from_tbp_rows(&v).map(Into::into)
+    }
+}
+
+impl From<BitBoard> for Vec<[Option<char>; 10]> {
+    /// The TBP rows. Cells above them are dropped, use [`codec::bool_grid`] to keep them.
+    fn from(board: BitBoard) -> Self {
+        (0..TBP_BOARD_HEIGHT)
+            .map(|y| std::array::from_fn(|x| (board.cols[x] >> y & 1 == 1).then_some('G')))
+            .collect()
+    }
+}
+
+impl Serialize for BitBoard {
+    /// The TBP rows. A board with cells above them fails instead of losing them, use
+    /// [`codec::bool_grid`] to keep them.
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.cols.iter().any(|col| col >> TBP_BOARD_HEIGHT != 0) {
+            return Err(serde::ser::Error::custom(format!(
+                "board has cells above its {} TBP rows",
+                TBP_BOARD_HEIGHT
+            )));
         }
-        BitBoard { cols }
+        Vec::<[Option<char>; 10]>::from(self.clone()).serialize(serializer)
     }
 }
 
@@ -406,8 +480,14 @@ impl BitBoard {
     }
 }
 
+impl From<BitBoard> for ColoredBoard {
+    fn from(board: BitBoard) -> Self {
+        board.into_colored(CellKind::Gbg)
+    }
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-#[serde(from = "Vec<Vec<Option<char>>>", into = "Vec<Vec<Option<char>>>")]
+#[serde(try_from = "Vec<Vec<Option<char>>>", into = "Vec<Vec<Option<char>>>")]
 pub struct ColoredBoard {
     pub cols: [[CellKind; 64]; 10],
 }
@@ -420,12 +500,71 @@ impl Default for ColoredBoard {
     }
 }
 
-impl From<Vec<Vec<Option<char>>>> for ColoredBoard {
-    fn from(v: Vec<Vec<Option<char>>>) -> Self {
-        let mut cols = [[CellKind::None; 64]; 10];
-        for x in 0..10 {
-            for y in 0..40 {
-                cols[x][y] = v[y][x].map_or(CellKind::None, |c| match c {
+/// Number of rows in a TBP board.
+pub const TBP_BOARD_HEIGHT: usize = 40;
+
+/// Why a TBP board was rejected. Rows are counted from the floor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoardError {
+    RowCount(usize),
+    RowWidth {
+        row: usize,
+        width: usize,
+    },
+    InvalidCell {
+        row: usize,
+        column: usize,
+        cell: char,
+    },
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RowCount(rows) => {
+                write!(
+                    f,
+                    "board has {} rows, expected exactly {}",
+                    rows, TBP_BOARD_HEIGHT
+                )
+            }
+            Self::RowWidth { row, width } => {
+                write!(f, "board row {} has {} cells, expected 10", row, width)
+            }
+            Self::InvalidCell { row, column, cell } => {
+                write!(
+                    f,
+                    "invalid cell {:?} at row {} column {}",
+                    cell, row, column
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+impl ColoredBoard {
+    /// Reads TBP rows, from the floor up.
+    pub fn from_tbp_rows<R: AsRef<[Option<char>]>>(rows: &[R]) -> Result<Self, BoardError> {
+        if rows.len() != TBP_BOARD_HEIGHT {
+            return Err(BoardError::RowCount(rows.len()));
+        }
+
+        let mut board = ColoredBoard::default();
+        for (y, row) in rows.iter().enumerate() {
+            let row = row.as_ref();
+            if row.len() != 10 {
+                return Err(BoardError::RowWidth {
+                    row: y,
+                    width: row.len(),
+                });
+            }
+            for (x, &cell) in row.iter().enumerate() {
+                let Some(c) = cell else {
+                    continue;
+                };
+                board.cols[x][y] = match c {
                     'S' => CellKind::S,
                     'Z' => CellKind::Z,
                     'J' => CellKind::J,
@@ -434,11 +573,25 @@ impl From<Vec<Vec<Option<char>>>> for ColoredBoard {
                     'O' => CellKind::O,
                     'I' => CellKind::I,
                     'G' => CellKind::Gbg,
-                    _ => CellKind::None,
-                });
+                    _ => {
+                        return Err(BoardError::InvalidCell {
+                            row: y,
+                            column: x,
+                            cell: c,
+                        })
+                    }
+                };
             }
         }
-        ColoredBoard { cols }
+        Ok(board)
+    }
+}
+
+impl TryFrom<Vec<Vec<Option<char>>>> for ColoredBoard {
+    type Error = BoardError;
+
+    fn try_from(v: Vec<Vec<Option<char>>>) -> Result<Self, Self::Error> {
+        Self::from_tbp_rows(&v)
     }
 }
 
@@ -511,13 +664,13 @@ impl Board for ColoredBoard {
     fn add_garbage_line(&mut self, col: i8) {
         debug_assert!(0 <= col && col < 10);
         for x in 0..10 {
-            for y in 0..62 {
+            for y in (0..63).rev() {
                 self.cols[x][y + 1] = self.cols[x][y];
             }
             self.cols[x][0] = if x == col as usize {
-                CellKind::Gbg
-            } else {
                 CellKind::None
+            } else {
+                CellKind::Gbg
             };
         }
     }
@@ -537,11 +690,11 @@ impl Board for ColoredBoard {
         for clear in &cleared {
             for y in (clear - offset)..63 {
                 for x in 0..10 {
-                    self.cols[y as usize][x] = self.cols[(y + 1) as usize][x];
+                    self.cols[x][y as usize] = self.cols[x][(y + 1) as usize];
                 }
             }
             for x in 0..10 {
-                self.cols[63][x] = CellKind::None;
+                self.cols[x][63] = CellKind::None;
             }
             offset += 1;
         }
@@ -549,30 +702,22 @@ impl Board for ColoredBoard {
     }
 }
 
-impl From<Vec<[Option<char>; 10]>> for ColoredBoard {
-    fn from(v: Vec<[Option<char>; 10]>) -> Self {
-        let mut cols = [[CellKind::None; u64::BITS as usize]; 10];
-        for x in 0..10 {
-            for y in 0..40 {
-                cols[x][y] = v[y][x].map_or(CellKind::None, |c| match c {
-                    'S' => CellKind::S,
-                    'Z' => CellKind::Z,
-                    'J' => CellKind::J,
-                    'L' => CellKind::L,
-                    'T' => CellKind::T,
-                    'O' => CellKind::O,
-                    'I' => CellKind::I,
-                    'G' => CellKind::Gbg,
-                    _ => CellKind::None,
-                });
-            }
-        }
-        ColoredBoard { cols }
+impl TryFrom<Vec<[Option<char>; 10]>> for ColoredBoard {
+    type Error = BoardError;
+
+    fn try_from(v: Vec<[Option<char>; 10]>) -> Result<Self, Self::Error> {
+        Self::from_tbp_rows(&v)
     }
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "B: Clone + Into<ColoredBoard>",
+    deserialize = "ColoredBoard: Into<B>"
+))]
 pub struct GameState<B: Board> {
+    /// Every row is kept, see [`codec::full_height`].
+    #[serde(with = "codec::full_height")]
     pub board: B,
     pub hold: Option<PieceKind>,
     pub queue: VecDeque<PieceKind>,
@@ -761,6 +906,25 @@ impl<B: Board> GameState<B> {
         }
     }
 
+    /// Like [`GameState::advance`], then raises the pending garbage if the placement cleared no
+    /// lines, as garbage is blocked by line clears in guideline games.
+    ///
+    /// The placement is also a death when the next piece cannot spawn after the rise.
+    pub fn advance_with_garbage(&mut self, mv: Move, garbage: PendingGarbage) -> PlacementResult {
+        let mut result = self.advance(mv);
+        if let Move::Place(_) = mv {
+            if result.lines_cleared == 0 && garbage.lines > 0 {
+                for _ in 0..garbage.lines {
+                    self.board.add_garbage_line(garbage.column);
+                }
+                if let Some(&next) = self.queue.front() {
+                    result.death |= self.spawn(next).is_none();
+                }
+            }
+        }
+        result
+    }
+
     pub fn add_piece(&mut self, piece: PieceKind) {
         // eprintln!("{:?} / {:?}", self.bag.0, piece);
         debug_assert!(self.bag.has(piece));
@@ -768,11 +932,20 @@ impl<B: Board> GameState<B> {
         self.queue.push_back(piece);
     }
 
-    pub fn legal_moves(&self, use_hold: bool) -> Result<MoveGenerator, ()> {
+    pub fn legal_moves(&self, use_hold: bool) -> Result<MoveGenerator, Suffocated> {
         let gen = MoveGenerator::generate_for(self, use_hold)?;
         Ok(gen)
     }
 
+    /// Returns the moves that survive the pending garbage, see [`GameState::advance_with_garbage`].
+    pub fn legal_moves_with_garbage(
+        &self,
+        use_hold: bool,
+        garbage: PendingGarbage,
+    ) -> Result<MoveGenerator, Suffocated> {
+        MoveGenerator::generate_with_garbage(self, use_hold, garbage)
+    }
+
     pub fn add_garbage(&mut self, amount: u32) {
         let mut rng = thread_rng();
         let mut x = rng.gen_range(0..10);
@@ -960,6 +1133,58 @@ mod test {
         }
     }
 
+    mod colored_board {
+        pub use super::*;
+
+        #[test]
+        fn test_add_garbage_line() {
+            let mut board: ColoredBoard = bit_board! {
+                "x_________",
+                "xx________"
+            }
+            .into();
+            board.cols[1][0] = CellKind::T;
+            board.add_garbage_line(3);
+
+            let expected = bit_board! {
+                "x_________",
+                "xx________",
+                "xxx_xxxxxx"
+            };
+            assert_eq!(Into::<BitBoard>::into(board.clone()), expected);
+            assert_eq!(board.cols[1][1], CellKind::T);
+            assert_eq!(board.cols[3][0], CellKind::None);
+            assert_eq!(board.cols[4][0], CellKind::Gbg);
+        }
+
+        #[test]
+        fn test_clear_lines() {
+            let board = bit_board! {
+                "x_________",
+                "xxxxxxxxx_",
+                "xx________",
+                "xxxxxxxxx_"
+            };
+            let piece = PieceState::new(PieceKind::I, (9, 1), Rotation::West);
+            let mut colored: ColoredBoard = board.clone().into();
+            colored.cols[1][1] = CellKind::S;
+            let mut bits = board;
+
+            assert_eq!(colored.add_piece_and_clear(piece), 2);
+            assert_eq!(bits.add_piece_and_clear(piece), 2);
+            assert_eq!(
+                bits,
+                bit_board! {
+                    "x________x",
+                    "xx_______x"
+                }
+            );
+            assert_eq!(Into::<BitBoard>::into(colored.clone()), bits);
+            assert_eq!(colored.cols[1][0], CellKind::S);
+            assert_eq!(colored.cols[9][0], CellKind::I);
+        }
+    }
+
     #[test]
     fn test_bit_board_macro() {
         let expected = BitBoard {
@@ -976,4 +1201,121 @@ mod test {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_tbp_board() {
+        let mut rows = vec![vec![None; 10]; TBP_BOARD_HEIGHT];
+        rows[0][3] = Some('T');
+        rows[1][9] = Some('G');
+        let board = ColoredBoard::try_from(rows.clone()).unwrap();
+        assert_eq!(board.cols[3][0], CellKind::T);
+        assert_eq!(board.cols[9][1], CellKind::Gbg);
+        let back: Vec<Vec<Option<char>>> = board.into();
+        assert_eq!(back, rows);
+
+        assert_eq!(
+            ColoredBoard::from_tbp_rows::<Vec<_>>(&[]),
+            Err(BoardError::RowCount(0))
+        );
+        let mut ragged = rows.clone();
+        ragged[5].pop();
+        assert_eq!(
+            ColoredBoard::try_from(ragged),
+            Err(BoardError::RowWidth { row: 5, width: 9 })
+        );
+        let mut invalid = rows;
+        invalid[2][4] = Some('x');
+        assert_eq!(
+            ColoredBoard::try_from(invalid),
+            Err(BoardError::InvalidCell {
+                row: 2,
+                column: 4,
+                cell: 'x'
+            })
+        );
+
+        let json = serde_json::to_string(&vec![[None::<char>; 10]; 39]).unwrap();
+        let err = serde_json::from_str::<BitBoard>(&json).unwrap_err();
+        assert!(err.to_string().contains("board has 39 rows"));
+    }
+
+    #[test]
+    fn test_pending_garbage() {
+        let mut board = BitBoard::default();
+        for x in 3..6 {
+            board.cols[x] = (1 << 18) - 1;
+        }
+        let state = GameState {
+            board,
+            queue: VecDeque::from([PieceKind::O, PieceKind::T]),
+            ..GameState::new()
+        };
+        let is_left = |mv: &Move| matches!(mv, Move::Place(piece) if piece.pos.cells().iter().all(|&(x, y)| x < 3 && y < 2));
+        let mv = state
+            .legal_moves(false)
+            .unwrap()
+            .moves()
+            .into_iter()
+            .find(is_left)
+            .unwrap();
+
+        // the T still spawns one row higher
+        let mut next = state.clone();
+        assert!(
+            !next
+                .advance_with_garbage(mv, PendingGarbage::new(1, 0))
+                .death
+        );
+        assert_eq!(next.board.height_of(4), 19);
+        assert!(state
+            .legal_moves_with_garbage(false, PendingGarbage::new(1, 0))
+            .unwrap()
+            .moves()
+            .iter()
+            .any(is_left));
+
+        let mut next = state.clone();
+        assert!(
+            next.advance_with_garbage(mv, PendingGarbage::new(3, 0))
+                .death
+        );
+        assert!(state
+            .legal_moves_with_garbage(false, PendingGarbage::new(3, 0))
+            .unwrap()
+            .moves()
+            .is_empty());
+
+        // the O cannot even spawn once the stack has risen
+        let mut risen = state.clone();
+        risen.advance_with_garbage(mv, PendingGarbage::new(3, 0));
+        risen.queue.push_front(PieceKind::O);
+        assert_eq!(
+            risen
+                .legal_moves_with_garbage(false, PendingGarbage::new(1, 0))
+                .err(),
+            Some(Suffocated)
+        );
+    }
+
+    #[test]
+    fn test_garbage_blocked_by_clear() {
+        let state = GameState {
+            board: bit_board! { "xxxxxxxx__" },
+            queue: VecDeque::from([PieceKind::O]),
+            ..GameState::new()
+        };
+        let mv = state
+            .legal_moves(false)
+            .unwrap()
+            .moves()
+            .into_iter()
+            .find(|mv| matches!(mv, Move::Place(piece) if piece.pos.cells().contains(&(9, 0))))
+            .unwrap();
+
+        let mut next = state.clone();
+        let result = next.advance_with_garbage(mv, PendingGarbage::new(5, 0));
+        assert_eq!(result.lines_cleared, 1);
+        assert_eq!(next.board.height_of(0), 0);
+        assert_eq!(next.board.height_of(9), 1);
+    }
 }
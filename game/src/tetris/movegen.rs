@@ -60,10 +60,13 @@ pub struct MoveGenerator {
 }
 
 impl MoveGenerator {
-    pub fn generate_for<B: Board>(state: &GameState<B>, use_hold: bool) -> Result<Self, ()> {
+    pub fn generate_for<B: Board>(
+        state: &GameState<B>,
+        use_hold: bool,
+    ) -> Result<Self, Suffocated> {
         let mut state = state.clone();
 
-        let spawn = state.spawn_next().ok_or(())?;
+        let spawn = state.spawn_next().ok_or(Suffocated)?;
 
         let mut gen = Self {
             original_piece: spawn.pos.kind,
@@ -91,6 +94,36 @@ impl MoveGenerator {
         Ok(gen)
     }
 
+    /// Generates moves for the current board, dropping the placements that die once the pending
+    /// garbage has risen.
+    pub fn generate_with_garbage<B: Board>(
+        state: &GameState<B>,
+        use_hold: bool,
+        garbage: PendingGarbage,
+    ) -> Result<Self, Suffocated> {
+        let mut gen = Self::generate_for(state, use_hold)?;
+        if garbage.lines > 0 {
+            // whichever piece is placed, the second piece of the queue spawns next
+            let next = state.queue.get(1).copied();
+            let mut after = state.clone();
+            gen.locked.retain(|_, (piece, _, _)| {
+                // same as the death of `GameState::advance_with_garbage`, on the board alone
+                if piece.pos.cells().iter().all(|(_, y)| *y >= 20) {
+                    return false;
+                }
+                after.board.clone_from(&state.board);
+                if after.board.add_piece_and_clear(*piece) > 0 {
+                    return true;
+                }
+                for _ in 0..garbage.lines {
+                    after.board.add_garbage_line(garbage.column);
+                }
+                next.is_none_or(|next| after.spawn(next).is_some())
+            });
+        }
+        Ok(gen)
+    }
+
     fn generate_internal<B: Board>(&mut self, state: &GameState<B>, spawn: PieceState) {
         let root_step = Step {
             parent: None,
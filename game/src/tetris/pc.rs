@@ -0,0 +1,340 @@
+//! Perfect clear solver.
+//!
+//! Solutions are searched with [`GameState::legal_moves`], so every placement is reachable with
+//! the same movement rules as the bot. Only placements that fit below the target height are tried,
+//! and positions known to fail are remembered.
+
+use std::collections::HashSet;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcSolver {
+    /// Maximum number of placements in a solution.
+    pub max_pieces: usize,
+    /// Maximum height of the perfect clear, in rows.
+    pub max_height: u32,
+    pub use_hold: bool,
+}
+
+impl Default for PcSolver {
+    fn default() -> Self {
+        Self {
+            max_pieces: 10,
+            max_height: 4,
+            use_hold: true,
+        }
+    }
+}
+
+/// Outcome of [`PcSolver::chance`] over every bag-consistent queue.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PcChance {
+    /// Number of distinct queues that were tried.
+    pub sequences: usize,
+    /// Number of those queues that have a solution.
+    pub solvable: usize,
+    /// Probability of drawing a solvable queue. Queues are weighted by how likely the bag deals them.
+    pub probability: f64,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct SearchKey {
+    board: BitBoard,
+    hold: Option<PieceKind>,
+    queue: VecDeque<PieceKind>,
+    pieces: usize,
+    height: u32,
+}
+
+struct Search<'a> {
+    solver: &'a PcSolver,
+    failed: HashSet<SearchKey>,
+    path: Vec<Move>,
+    solutions: Vec<Vec<Move>>,
+    find_all: bool,
+}
+
+impl PcSolver {
+    /// Returns every sequence of moves that ends in a perfect clear with the known queue.
+    /// A [`Move::Hold`] is included when the hold slot is empty and gets filled.
+    pub fn solutions(&self, state: &GameState<BitBoard>) -> Vec<Vec<Move>> {
+        let mut search = Search::new(self, true);
+        for height in self.heights(&state.board) {
+            search.run(state, height);
+        }
+        let mut solutions = search.solutions;
+        solutions.sort();
+        solutions.dedup();
+        solutions
+    }
+
+    /// Returns the first solution found with the known queue.
+    pub fn solve(&self, state: &GameState<BitBoard>) -> Option<Vec<Move>> {
+        let mut search = Search::new(self, false);
+        self.heights(&state.board)
+            .into_iter()
+            .find(|&height| search.run(state, height))
+            .map(|_| search.path)
+    }
+
+    /// Fills the queue up to `max_pieces` (and one more when the hold slot is empty) with every
+    /// sequence the bag can deal, and counts how many of them can be solved.
+    ///
+    /// Like solution-finder, each queue is solved as if it was entirely visible.
+    pub fn chance(&self, state: &GameState<BitBoard>) -> PcChance {
+        let extra = (self.use_hold && state.hold.is_none()) as usize;
+        let unknown = (self.max_pieces + extra).saturating_sub(state.queue.len());
+
+        let mut search = Search::new(self, false);
+        let mut chance = PcChance {
+            sequences: 0,
+            solvable: 0,
+            probability: 0.0,
+        };
+        let mut state = state.clone();
+        self.draw(&mut state, unknown, 1.0, &mut search, &mut chance);
+        chance
+    }
+
+    fn draw(
+        &self,
+        state: &mut GameState<BitBoard>,
+        unknown: usize,
+        weight: f64,
+        search: &mut Search,
+        chance: &mut PcChance,
+    ) {
+        if unknown == 0 {
+            chance.sequences += 1;
+            if self
+                .heights(&state.board)
+                .into_iter()
+                .any(|height| search.run(state, height))
+            {
+                chance.solvable += 1;
+                chance.probability += weight;
+            }
+            return;
+        }
+
+        let bag = state.bag;
+        let pieces = if bag.0.is_empty() {
+            EnumSet::all()
+        } else {
+            bag.0
+        };
+        for piece in pieces {
+            state.add_piece(piece);
+            self.draw(
+                state,
+                unknown - 1,
+                weight / pieces.len() as f64,
+                search,
+                chance,
+            );
+            state.queue.pop_back();
+            state.bag = bag;
+        }
+    }
+
+    /// Returns the perfect clear heights that the board can reach within `max_pieces`.
+    fn heights(&self, board: &BitBoard) -> Vec<u32> {
+        let filled = filled_cells(board);
+        let highest = (0..10).map(|x| board.height_of(x)).max().unwrap();
+        (highest.max(1)..=self.max_height)
+            .filter(|height| {
+                let empty = height * 10 - filled;
+                empty.is_multiple_of(4) && empty > 0 && empty as usize / 4 <= self.max_pieces
+            })
+            .collect()
+    }
+}
+
+impl<'a> Search<'a> {
+    fn new(solver: &'a PcSolver, find_all: bool) -> Self {
+        Self {
+            solver,
+            failed: HashSet::new(),
+            path: vec![],
+            solutions: vec![],
+            find_all,
+        }
+    }
+
+    fn run(&mut self, state: &GameState<BitBoard>, height: u32) -> bool {
+        let pieces = (height * 10 - filled_cells(&state.board)) as usize / 4;
+        self.path.clear();
+        self.search(state, pieces, height)
+    }
+
+    /// Returns whether a solution was found below this state.
+    fn search(&mut self, state: &GameState<BitBoard>, pieces: usize, height: u32) -> bool {
+        if pieces == 0 {
+            if state.board.is_empty() {
+                self.solutions.push(self.path.clone());
+                return true;
+            }
+            return false;
+        }
+        // every placement takes a piece from the queue, hold only swaps them
+        if state.queue.len() < pieces {
+            return false;
+        }
+
+        let key = SearchKey {
+            board: state.board.clone(),
+            hold: state.hold,
+            queue: state.queue.clone(),
+            pieces,
+            height,
+        };
+        if self.failed.contains(&key) {
+            return false;
+        }
+
+        let Ok(moves) = state.legal_moves(self.solver.use_hold) else {
+            return false;
+        };
+
+        let mut found = false;
+        for mv in moves.moves() {
+            let mut next = state.clone();
+            let (pieces, height) = match mv {
+                Move::Hold => {
+                    next.advance(mv);
+                    (pieces, height)
+                }
+                Move::Place(piece) => {
+                    if piece.pos.cells().iter().any(|&(_, y)| y as u32 >= height) {
+                        continue;
+                    }
+                    let result = next.advance(mv);
+                    (pieces - 1, height - result.lines_cleared)
+                }
+            };
+
+            self.path.push(mv);
+            if self.search(&next, pieces, height) {
+                found = true;
+                if !self.find_all {
+                    return true;
+                }
+            }
+            self.path.pop();
+        }
+
+        if !found {
+            self.failed.insert(key);
+        }
+        found
+    }
+}
+
+fn filled_cells(board: &BitBoard) -> u32 {
+    board.cols.iter().map(|col| col.count_ones()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_board;
+
+    fn state(board: BitBoard, hold: Option<PieceKind>, queue: &[PieceKind]) -> GameState<BitBoard> {
+        GameState {
+            board,
+            hold,
+            queue: queue.iter().copied().collect(),
+            ..GameState::new()
+        }
+    }
+
+    #[test]
+    fn test_single_line() {
+        let board = bit_board! {
+            "xxxxxx____"
+        };
+        let solver = PcSolver::default();
+
+        let solutions = solver.solutions(&state(board.clone(), None, &[PieceKind::I]));
+        assert_eq!(solutions.len(), 1);
+        let Move::Place(piece) = solutions[0][0] else {
+            panic!("expected a placement");
+        };
+        assert_eq!(piece.pos.cells(), [(6, 0), (7, 0), (8, 0), (9, 0)]);
+
+        assert!(solver
+            .solve(&state(board, None, &[PieceKind::O]))
+            .is_none());
+    }
+
+    #[test]
+    fn test_hold() {
+        let board = bit_board! {
+            "xxxxxxxx__",
+            "xxxxxxxx__"
+        };
+        let queue = [PieceKind::T, PieceKind::O];
+
+        let solutions = PcSolver::default().solutions(&state(board.clone(), None, &queue));
+        assert_eq!(solutions.len(), 1);
+        assert_eq!(solutions[0][0], Move::Hold);
+        assert!(matches!(solutions[0][1], Move::Place(piece) if piece.pos.kind == PieceKind::O));
+
+        // the O is held, and swapped back in
+        let solution = PcSolver::default()
+            .solve(&state(board.clone(), Some(PieceKind::O), &queue[..1]))
+            .unwrap();
+        assert!(matches!(solution[..], [Move::Place(piece)] if piece.pos.kind == PieceKind::O));
+
+        let no_hold = PcSolver {
+            use_hold: false,
+            ..Default::default()
+        };
+        assert!(no_hold.solutions(&state(board, None, &queue)).is_empty());
+    }
+
+    #[test]
+    fn test_multiple_pieces() {
+        let board = bit_board! {
+            "xxxx______",
+            "xxxx______"
+        };
+        // two Js fill a 2x4 area, the O fills the rest
+        let queue = [PieceKind::J, PieceKind::J, PieceKind::O];
+        let solutions = PcSolver::default().solutions(&state(board, None, &queue));
+        assert!(!solutions.is_empty());
+        for solution in solutions {
+            let placements = solution
+                .iter()
+                .filter(|mv| matches!(mv, Move::Place(_)))
+                .count();
+            assert_eq!(placements, 3);
+        }
+    }
+
+    #[test]
+    fn test_chance() {
+        let board = bit_board! {
+            "xxxxxx____"
+        };
+        let solver = PcSolver {
+            max_pieces: 1,
+            use_hold: false,
+            ..Default::default()
+        };
+        let chance = solver.chance(&state(board.clone(), None, &[]));
+        assert_eq!(chance.sequences, 7);
+        assert_eq!(chance.solvable, 1);
+        assert!((chance.probability - 1.0 / 7.0).abs() < 1e-9);
+
+        // with hold, either of the next two pieces can be the I
+        let chance = PcSolver {
+            use_hold: true,
+            ..solver
+        }
+        .chance(&state(board, None, &[]));
+        assert_eq!(chance.sequences, 42);
+        assert!((chance.probability - 2.0 / 7.0).abs() < 1e-9);
+    }
+}
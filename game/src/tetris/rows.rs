@@ -0,0 +1,224 @@
+//! Row-major board representation.
+//!
+//! [`BitBoard`] is column-major, which makes heights and drops cheap but rebuilds a row for every
+//! row query. [`RowBoard`] stores one `u16` per row, so line clears and row queries are direct,
+//! at the cost of column queries that scan the rows.
+
+use super::*;
+
+/// Cells of a full row.
+pub const FULL_ROW: u16 = (1 << 10) - 1;
+
+/// A board that stores `N` rows of 10 cells, bit `x` of a row being column `x`.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RowBoard<const N: usize = 64> {
+    pub rows: [u16; N],
+}
+
+impl<const N: usize> Default for RowBoard<N> {
+    fn default() -> Self {
+        Self { rows: [0; N] }
+    }
+}
+
+impl<const N: usize> RowBoard<N> {
+    pub fn get_row(&self, y: i8) -> u16 {
+        self.rows[y as usize]
+    }
+
+    /// Returns the height of the highest occupied cell of any column.
+    pub fn height(&self) -> u32 {
+        self.rows
+            .iter()
+            .rposition(|&row| row != 0)
+            .map_or(0, |y| y as u32 + 1)
+    }
+}
+
+impl<const N: usize> Board for RowBoard<N> {
+    fn occupied(&self, (x, y): (i8, i8)) -> bool {
+        !(0..10).contains(&x) || y < 0 || N <= y as usize || self.rows[y as usize] >> x & 1 == 1
+    }
+
+    fn height_of(&self, x: i8) -> u32 {
+        debug_assert!((0..10).contains(&x));
+        self.rows
+            .iter()
+            .rposition(|&row| row >> x & 1 == 1)
+            .map_or(0, |y| y as u32 + 1)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows.iter().all(|&row| row == 0)
+    }
+
+    fn distance_to_ground(&self, (x, y): (i8, i8)) -> u32 {
+        debug_assert!((0..10).contains(&x));
+        debug_assert!(0 <= y && (y as usize) < N);
+        self.rows[..y as usize]
+            .iter()
+            .rev()
+            .take_while(|&&row| row >> x & 1 == 0)
+            .count() as u32
+    }
+
+    fn add_garbage_line(&mut self, x: i8) {
+        debug_assert!((0..10).contains(&x));
+        self.rows.copy_within(0..N - 1, 1);
+        self.rows[0] = FULL_ROW & !(1 << x);
+    }
+
+    fn add_piece_and_clear(&mut self, piece: PieceState) -> u32 {
+        let cells = piece.pos.cells();
+        for (x, y) in cells {
+            self.rows[y as usize] ^= 1 << x;
+        }
+
+        // Only rows touched by the piece can become full
+        let lowest = cells.iter().map(|&(_, y)| y as usize).min().unwrap();
+        let mut cleared = 0;
+        for y in lowest..N {
+            if self.rows[y] == FULL_ROW {
+                cleared += 1;
+            } else if cleared > 0 {
+                self.rows[y - cleared] = self.rows[y];
+            }
+        }
+        self.rows[N - cleared..].fill(0);
+        cleared as u32
+    }
+}
+
+impl<const N: usize> From<&BitBoard> for RowBoard<N> {
+    fn from(board: &BitBoard) -> Self {
+        debug_assert!(board.cols.iter().all(|&col| col.checked_shr(N as u32).unwrap_or(0) == 0));
+        let mut rows = [0; N];
+        for (x, &col) in board.cols.iter().enumerate() {
+            let mut col = col;
+            while col != 0 {
+                let y = col.trailing_zeros() as usize;
+                if y < N {
+                    rows[y] |= 1 << x;
+                }
+                col &= col - 1;
+            }
+        }
+        Self { rows }
+    }
+}
+
+impl<const N: usize> From<BitBoard> for RowBoard<N> {
+    fn from(board: BitBoard) -> Self {
+        Self::from(&board)
+    }
+}
+
+impl<const N: usize> From<&RowBoard<N>> for BitBoard {
+    fn from(board: &RowBoard<N>) -> Self {
+        let mut cols = [0; 10];
+        for (y, &row) in board.rows.iter().enumerate().take(u64::BITS as usize) {
+            let mut row = row;
+            while row != 0 {
+                let x = row.trailing_zeros() as usize;
+                cols[x] |= 1 << y;
+                row &= row - 1;
+            }
+        }
+        BitBoard { cols }
+    }
+}
+
+impl<const N: usize> From<RowBoard<N>> for BitBoard {
+    fn from(board: RowBoard<N>) -> Self {
+        Self::from(&board)
+    }
+}
+
+impl<const N: usize> From<RowBoard<N>> for ColoredBoard {
+    fn from(board: RowBoard<N>) -> Self {
+        BitBoard::from(&board).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_board;
+
+    #[test]
+    fn test_conversion() {
+        let board = bit_board! {
+            "____xx____",
+            "xxx_xxxxxx",
+            "xxxxxxx_xx"
+        };
+        let rows = RowBoard::<64>::from(&board);
+        assert_eq!(rows.get_row(0), 0b11_0111_1111);
+        assert_eq!(rows.get_row(1), 0b11_1111_0111);
+        assert_eq!(rows.get_row(2), 0b00_0011_0000);
+        assert_eq!(rows.height(), 3);
+        for y in 0..64 {
+            assert_eq!(rows.get_row(y) as u64, board.get_row(y));
+        }
+        assert_eq!(BitBoard::from(&rows), board);
+    }
+
+    #[test]
+    fn test_matches_bit_board() {
+        let mut bits = GameState::<BitBoard>::new();
+        for _ in 0..6 {
+            bits.fulfill_queue();
+        }
+        let mut rows = GameState {
+            board: RowBoard::<64>::from(&bits.board),
+            hold: bits.hold,
+            queue: bits.queue.clone(),
+            bag: bits.bag,
+            ren: bits.ren,
+            b2b: bits.b2b,
+        };
+
+        let mut lines_cleared = 0;
+        for i in 0..200usize {
+            if i % 7 == 3 {
+                bits.board.add_garbage_line((i % 10) as i8);
+                rows.board.add_garbage_line((i % 10) as i8);
+            }
+
+            let (Ok(bit_moves), Ok(row_moves)) = (bits.legal_moves(false), rows.legal_moves(false))
+            else {
+                break;
+            };
+            let mut bit_moves = bit_moves.moves();
+            let mut row_moves = row_moves.moves();
+            bit_moves.sort();
+            row_moves.sort();
+            assert_eq!(bit_moves, row_moves);
+            for x in 0..10 {
+                assert_eq!(bits.board.height_of(x), rows.board.height_of(x));
+            }
+
+            // prefer placements that clear lines, then low ones
+            let Some(&mv) = bit_moves.iter().min_by_key(|mv| {
+                let mut next = bits.board.clone();
+                let Move::Place(piece) = **mv else {
+                    return (0, 0);
+                };
+                let cleared = next.add_piece_and_clear(piece);
+                (-(cleared as i32), piece.pos.y as i32)
+            }) else {
+                break;
+            };
+
+            let result = bits.advance(mv);
+            assert_eq!(rows.advance(mv), result);
+            if result.death {
+                break;
+            }
+            lines_cleared += result.lines_cleared;
+            rows.queue.push_back(bits.fulfill_queue());
+            assert_eq!(BitBoard::from(&rows.board), bits.board);
+        }
+        assert!(lines_cleared > 0);
+    }
+}
@@ -7,7 +7,9 @@ use serde::{Deserialize, Serialize};
 #[serde(tag = "type")]
 pub enum FrontendMessage {
     Rules {
-        randomizer: String,
+        /// Absent when the frontend does not support the randomizer extension.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        randomizer: Option<String>,
     },
     Start(Start),
     Play {
@@ -44,10 +46,16 @@ pub enum BotMessage {
     },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum BotErrorReason {
     UnsupportedRules,
+    /// The line is not a valid frontend message.
+    InvalidMessage,
+    /// The message is not allowed in the current state, such as `suggest` before `start`.
+    UnexpectedMessage,
+    /// The played piece is neither the current piece nor one that can be swapped in by hold.
+    InvalidMove,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -89,7 +97,7 @@ pub struct MoveInfo {
     pub extra: String,
 }
 
-fn collect_enumset<'de, D, T>(de: D) -> Result<EnumSet<T>, D::Error>
+pub(super) fn collect_enumset<'de, D, T>(de: D) -> Result<EnumSet<T>, D::Error>
 where
     D: serde::Deserializer<'de>,
     T: EnumSetType + Deserialize<'de>,
@@ -97,7 +105,7 @@ where
     Ok(Vec::<T>::deserialize(de)?.into_iter().collect())
 }
 
-fn serialize_enumset<S, T>(set: &EnumSet<T>, ser: S) -> Result<S::Ok, S::Error>
+pub(super) fn serialize_enumset<S, T>(set: &EnumSet<T>, ser: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
     T: EnumSetType + Serialize,
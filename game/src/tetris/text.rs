@@ -0,0 +1,297 @@
+//! Plain-text representation of game states.
+//!
+//! ```text
+//! hold: T
+//! queue: SZOI
+//! bag: JL
+//! combo: 0
+//! b2b: false
+//! board:
+//! ....T.....
+//! GGG.TTZZGG
+//! ```
+//!
+//! `hold` and `queue` use `-` when empty. `bag` lists the pieces left in the current bag after the queue.
+//! `combo` follows the TBP convention, which is `ren + 1`.
+//! Board rows are listed from the top, down to the floor. Only rows up to the highest block are printed.
+//! Cells are `.` for empty, the piece letter, or `G` for garbage.
+
+use std::{error::Error, fmt, str::FromStr};
+
+use super::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseStateError {
+    MissingKey(&'static str),
+    UnknownKey(String),
+    InvalidValue(&'static str),
+    /// A board row has the wrong width or an unknown cell. Rows are counted from the top.
+    InvalidRow(usize),
+    TooManyRows,
+}
+
+impl fmt::Display for ParseStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingKey(key) => write!(f, "missing key `{}`", key),
+            Self::UnknownKey(key) => write!(f, "unknown key `{}`", key),
+            Self::InvalidValue(key) => write!(f, "invalid value for `{}`", key),
+            Self::InvalidRow(row) => write!(f, "invalid board row {}", row),
+            Self::TooManyRows => write!(f, "board has more than 64 rows"),
+        }
+    }
+}
+
+impl Error for ParseStateError {}
+
+impl fmt::Display for ColoredBoard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let height = (0..10).map(|x| self.height_of(x)).max().unwrap();
+        for y in (0..height as usize).rev() {
+            for x in 0..10 {
+                write!(f, "{}", cell_to_char(self.cols[x][y]))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ColoredBoard {
+    type Err = ParseStateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+        if rows.len() > 64 {
+            return Err(ParseStateError::TooManyRows);
+        }
+
+        let mut board = ColoredBoard::default();
+        for (i, row) in rows.iter().enumerate() {
+            let y = rows.len() - i - 1;
+            let cells = row
+                .chars()
+                .map(char_to_cell)
+                .collect::<Option<Vec<_>>>()
+                .filter(|cells| cells.len() == 10)
+                .ok_or(ParseStateError::InvalidRow(i))?;
+            for (x, cell) in cells.into_iter().enumerate() {
+                board.cols[x][y] = cell;
+            }
+        }
+        Ok(board)
+    }
+}
+
+impl<B: Board + Into<ColoredBoard>> fmt::Display for GameState<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pieces = |pieces: &mut dyn Iterator<Item = PieceKind>| {
+            let s = pieces.map(|p| p.to_string()).collect::<String>();
+            if s.is_empty() {
+                "-".to_owned()
+            } else {
+                s
+            }
+        };
+        let bag = if self.bag.0.is_empty() {
+            EnumSet::all()
+        } else {
+            self.bag.0
+        };
+
+        writeln!(f, "hold: {}", pieces(&mut self.hold.into_iter()))?;
+        writeln!(f, "queue: {}", pieces(&mut self.queue.iter().copied()))?;
+        writeln!(f, "bag: {}", pieces(&mut bag.iter()))?;
+        writeln!(f, "combo: {}", self.ren + 1)?;
+        writeln!(f, "b2b: {}", self.b2b)?;
+        writeln!(f, "board:")?;
+        write!(f, "{}", Into::<ColoredBoard>::into(self.board.clone()))
+    }
+}
+
+impl<B: Board> FromStr for GameState<B>
+where
+    ColoredBoard: Into<B>,
+{
+    type Err = ParseStateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hold = None;
+        let mut queue = None;
+        let mut bag = None;
+        let mut combo = None;
+        let mut b2b = None;
+
+        let mut lines = s.lines();
+        for line in lines.by_ref() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.trim();
+            match key.trim() {
+                "hold" => {
+                    let pieces =
+                        parse_pieces(value).ok_or(ParseStateError::InvalidValue("hold"))?;
+                    if pieces.len() > 1 {
+                        return Err(ParseStateError::InvalidValue("hold"));
+                    }
+                    hold = Some(pieces.first().copied());
+                }
+                "queue" => {
+                    queue =
+                        Some(parse_pieces(value).ok_or(ParseStateError::InvalidValue("queue"))?);
+                }
+                "bag" => {
+                    let pieces = parse_pieces(value).ok_or(ParseStateError::InvalidValue("bag"))?;
+                    let set = pieces.iter().copied().collect::<EnumSet<_>>();
+                    if set.len() != pieces.len() {
+                        return Err(ParseStateError::InvalidValue("bag"));
+                    }
+                    // a full bag is represented by an empty set
+                    bag = Some(SevenBag(if set == EnumSet::all() {
+                        EnumSet::empty()
+                    } else {
+                        set
+                    }));
+                }
+                "combo" => {
+                    combo = Some(
+                        value
+                            .parse::<u32>()
+                            .map_err(|_| ParseStateError::InvalidValue("combo"))?,
+                    );
+                }
+                "b2b" => {
+                    b2b = Some(
+                        value
+                            .parse::<bool>()
+                            .map_err(|_| ParseStateError::InvalidValue("b2b"))?,
+                    );
+                }
+                "board" => break,
+                key => return Err(ParseStateError::UnknownKey(key.to_owned())),
+            }
+        }
+
+        let board = lines
+            .collect::<Vec<_>>()
+            .join("\n")
+            .parse::<ColoredBoard>()?;
+
+        Ok(GameState {
+            board: board.into(),
+            hold: hold.ok_or(ParseStateError::MissingKey("hold"))?,
+            queue: queue.ok_or(ParseStateError::MissingKey("queue"))?.into(),
+            bag: bag.ok_or(ParseStateError::MissingKey("bag"))?,
+            ren: combo.ok_or(ParseStateError::MissingKey("combo"))? as i32 - 1,
+            b2b: b2b.ok_or(ParseStateError::MissingKey("b2b"))?,
+        })
+    }
+}
+
+fn parse_pieces(s: &str) -> Option<Vec<PieceKind>> {
+    if s == "-" {
+        return Some(vec![]);
+    }
+    s.chars().map(|c| char_to_cell(c)?.piece()).collect()
+}
+
+const fn cell_to_char(cell: CellKind) -> char {
+    match cell {
+        CellKind::None => '.',
+        CellKind::S => 'S',
+        CellKind::Z => 'Z',
+        CellKind::J => 'J',
+        CellKind::L => 'L',
+        CellKind::T => 'T',
+        CellKind::O => 'O',
+        CellKind::I => 'I',
+        CellKind::Gbg => 'G',
+    }
+}
+
+const fn char_to_cell(c: char) -> Option<CellKind> {
+    match c {
+        '.' => Some(CellKind::None),
+        'S' => Some(CellKind::S),
+        'Z' => Some(CellKind::Z),
+        'J' => Some(CellKind::J),
+        'L' => Some(CellKind::L),
+        'T' => Some(CellKind::T),
+        'O' => Some(CellKind::O),
+        'I' => Some(CellKind::I),
+        'G' => Some(CellKind::Gbg),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "\
+hold: T
+queue: SZOI
+bag: JL
+combo: 2
+b2b: true
+board:
+....T.....
+GGG.TTZZGG
+";
+
+    #[test]
+    fn test_parse() {
+        let state = TEXT.parse::<GameState<ColoredBoard>>().unwrap();
+        assert_eq!(state.hold, Some(PieceKind::T));
+        assert_eq!(
+            state.queue,
+            [PieceKind::S, PieceKind::Z, PieceKind::O, PieceKind::I]
+        );
+        assert_eq!(state.bag, SevenBag(PieceKind::J | PieceKind::L));
+        assert_eq!(state.ren, 1);
+        assert!(state.b2b);
+        assert_eq!(state.board.cols[4][1], CellKind::T);
+        assert_eq!(state.board.cols[6][0], CellKind::Z);
+        assert_eq!(state.board.cols[3][0], CellKind::None);
+        assert_eq!(state.to_string(), TEXT);
+    }
+
+    #[test]
+    fn test_round_trip_bit_board() {
+        let mut state = GameState::<BitBoard>::new();
+        state.add_piece(PieceKind::I);
+        state.board.add_garbage_line(3);
+        let text = state.to_string();
+        assert_eq!(text.parse::<GameState<BitBoard>>().unwrap(), state);
+        assert!(text.contains("bag: SZJLTO\n"));
+        assert!(text.ends_with("board:\nGGG.GGGGGG\n"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let parse = |s: &str| s.parse::<GameState<BitBoard>>().unwrap_err();
+        assert_eq!(
+            parse(&TEXT.replace("hold: T\n", "")),
+            ParseStateError::MissingKey("hold")
+        );
+        assert_eq!(
+            parse(&TEXT.replace("combo: 2", "combo: -1")),
+            ParseStateError::InvalidValue("combo")
+        );
+        assert_eq!(
+            parse(&TEXT.replace("GGG.TTZZGG", "GGG.TTZZG")),
+            ParseStateError::InvalidRow(1)
+        );
+        assert_eq!(
+            parse(&TEXT.replace("b2b", "back_to_back")),
+            ParseStateError::UnknownKey("back_to_back".to_owned())
+        );
+    }
+}
@@ -0,0 +1,365 @@
+//! T-spin slot detection.
+//!
+//! Slots are found by generating every T placement with [`MoveGenerator`], so a reported slot is
+//! always reachable from the spawn position. A placement counts as a slot when it is a full T-spin
+//! that clears at least two lines.
+//!
+//! Generating moves is too slow for evaluation, which uses [`scan_t_slot`] to match slots against
+//! the shape of the stack instead.
+
+use std::cmp::Reverse;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TSlotKind {
+    /// T-spin double with the T pointing down.
+    Tsd,
+    /// A T-spin double pointing down into a cross-shaped hole: both upper corners are filled and
+    /// the cell above the T is open, so the T has to be kicked in from that shaft.
+    ImperialCross,
+    /// T-spin triple. The T lies sideways and clears all three of its rows.
+    Tst,
+    /// Sideways T-spin double clearing the lower two rows of the T, the bottom of a TST slot.
+    Stsd,
+    /// Sideways T-spin double clearing the upper two rows of the T, as with fin and neo setups.
+    Fin,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct TSlot {
+    pub kind: TSlotKind,
+    /// The placement that spins into the slot.
+    pub piece: PieceState,
+    /// The rows cleared by the placement, bit `y` being row `y`.
+    pub rows: u64,
+}
+
+impl TSlot {
+    pub fn lines(&self) -> u32 {
+        self.rows.count_ones()
+    }
+
+    /// Places the T into the slot and clears the lines.
+    pub fn apply(&self, board: &mut BitBoard) -> u32 {
+        board.add_piece_and_clear(self.piece)
+    }
+}
+
+/// Returns the T-spin slots of a board, the ones clearing the most lines first, then the lowest.
+pub fn t_slots(board: &BitBoard) -> Vec<TSlot> {
+    let state = GameState {
+        board: board.clone(),
+        queue: VecDeque::from([PieceKind::T]),
+        ..GameState::new()
+    };
+    let Ok(moves) = state.legal_moves(false) else {
+        return vec![];
+    };
+
+    let mut slots = moves
+        .moves()
+        .into_iter()
+        .filter_map(|mv| match mv {
+            Move::Place(piece) if piece.spin == SpinKind::Full => classify(board, piece),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    slots.sort_by_key(|slot| {
+        (
+            Reverse(slot.lines()),
+            slot.piece.pos.y,
+            slot.piece.pos.x,
+            slot.piece.pos.rot,
+        )
+    });
+    slots
+}
+
+/// Returns the best T-spin slot of a board, if any.
+pub fn best_t_slot(board: &BitBoard) -> Option<TSlot> {
+    t_slots(board).into_iter().next()
+}
+
+/// Returns the best T-spin slot of a board like [`best_t_slot`], by matching shapes instead of
+/// generating moves.
+///
+/// A placement pointing down or sideways is taken when it fits, both corners in front of the nub
+/// and one behind are filled, and the T has room above the slot to rotate into it. Reachability
+/// from the spawn position is not checked, so some slots found here need moves the T cannot make.
+pub fn scan_t_slot(board: &BitBoard) -> Option<TSlot> {
+    let open_above = |x: i8, y: i8| board.cols[x as usize] >> (y + 1) == 0;
+    let max_height = (0..10).map(|x| board.height_of(x)).max().unwrap_or(0) as i8;
+
+    let key = |slot: &TSlot| {
+        let pos = slot.piece.pos;
+        (Reverse(slot.lines()), pos.y, pos.x, pos.rot)
+    };
+
+    let mut best = None;
+    for y in 1..=max_height.min(61) {
+        for x in 0..10 {
+            for rot in [Rotation::South, Rotation::East, Rotation::West] {
+                let pos = PiecePosition {
+                    kind: PieceKind::T,
+                    x,
+                    y,
+                    rot,
+                };
+                if board.collides(pos) {
+                    continue;
+                }
+                let (nub_x, nub_y) = rot.rotate_cell((0, 1));
+                // front corners are beside the nub, back corners opposite to it
+                let (side_x, side_y) = (nub_y, nub_x);
+                let corner = |front: i8, side: i8| {
+                    board.occupied((
+                        x + front * nub_x + side * side_x,
+                        y + front * nub_y + side * side_y,
+                    ))
+                };
+                if !(corner(1, 1) && corner(1, -1) && (corner(-1, 1) || corner(-1, -1))) {
+                    continue;
+                }
+                let entry = if nub_x == 0 {
+                    !board.occupied((x, y + 1))
+                        && (!board.occupied((x, y + 2))
+                            || open_above(x - 1, y)
+                            || open_above(x + 1, y))
+                } else {
+                    !board.occupied((x, y + 2))
+                        && (open_above(x, y + 1) || open_above(x + nub_x, y + 1))
+                };
+                if !entry {
+                    continue;
+                }
+
+                let piece = PieceState {
+                    pos,
+                    spin: SpinKind::Full,
+                };
+                let Some(slot) = classify(board, piece) else {
+                    continue;
+                };
+                if best.as_ref().is_none_or(|best| key(&slot) < key(best)) {
+                    best = Some(slot);
+                }
+            }
+        }
+    }
+    best
+}
+
+fn classify(board: &BitBoard, piece: PieceState) -> Option<TSlot> {
+    let mut cols = board.cols;
+    for (x, y) in piece.pos.cells() {
+        cols[x as usize] |= 1 << y;
+    }
+    let rows = cols.iter().fold(u64::MAX, |acc, col| acc & col);
+
+    let (x, y) = (piece.pos.x, piece.pos.y);
+    let (nub_x, _) = piece.pos.rot.rotate_cell((0, 1));
+    let kind = match (nub_x != 0, rows.count_ones()) {
+        (false, 2) => {
+            let upper_corners = board.occupied((x - 1, y + 1)) && board.occupied((x + 1, y + 1));
+            let pointing_down = piece.pos.rot == Rotation::South;
+            let shaft = !board.occupied((x, y + 1));
+            if pointing_down && upper_corners && shaft {
+                TSlotKind::ImperialCross
+            } else {
+                TSlotKind::Tsd
+            }
+        }
+        (true, 3) => TSlotKind::Tst,
+        (true, 2) if rows >> (y - 1) & 0b11 == 0b11 => TSlotKind::Stsd,
+        (true, 2) => TSlotKind::Fin,
+        _ => return None,
+    };
+
+    Some(TSlot { kind, piece, rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_board;
+
+    #[test]
+    fn test_tsd() {
+        let board = bit_board! {
+            "xxx_______",
+            "xx___xxxxx",
+            "xxx_xxxxxx"
+        };
+        let slot = best_t_slot(&board).unwrap();
+        assert_eq!(slot.kind, TSlotKind::Tsd);
+        assert_eq!(slot.rows, 0b11);
+        assert_eq!(slot.piece.pos.rot, Rotation::South);
+        assert_eq!((slot.piece.pos.x, slot.piece.pos.y), (3, 1));
+
+        let mut after = board.clone();
+        assert_eq!(slot.apply(&mut after), 2);
+        assert_eq!(after, bit_board! { "xxx_______" });
+    }
+
+    #[test]
+    fn test_tst() {
+        let board = bit_board! {
+            "xxx_______",
+            "xx________",
+            "xx_xxxxxxx",
+            "xx__xxxxxx",
+            "xx_xxxxxxx"
+        };
+        let slot = best_t_slot(&board).unwrap();
+        assert_eq!(slot.kind, TSlotKind::Tst);
+        assert_eq!(slot.rows, 0b111);
+        assert_eq!(slot.piece.pos.rot, Rotation::East);
+        assert_eq!((slot.piece.pos.x, slot.piece.pos.y), (2, 1));
+    }
+
+    #[test]
+    fn test_stsd() {
+        let board = bit_board! {
+            "xxx_______",
+            "xx________",
+            "xx_xxxxx__",
+            "xx__xxxxxx",
+            "xx_xxxxxxx"
+        };
+        let slot = best_t_slot(&board).unwrap();
+        assert_eq!(slot.kind, TSlotKind::Stsd);
+        assert_eq!(slot.rows, 0b011);
+    }
+
+    #[test]
+    fn test_fin() {
+        let board = bit_board! {
+            "______x___",
+            "__________",
+            "xxxxxx_xxx",
+            "xxxxxx__xx",
+            "_xxxxx_xxx"
+        };
+        let slot = best_t_slot(&board).unwrap();
+        assert_eq!(slot.kind, TSlotKind::Fin);
+        assert_eq!(slot.rows, 0b110);
+        assert_eq!(slot.piece.pos.rot, Rotation::East);
+        assert_eq!((slot.piece.pos.x, slot.piece.pos.y), (6, 1));
+
+        // without the roof, the T cannot be tucked in
+        let board = bit_board! {
+            "xxxxxx_xxx",
+            "xxxxxx__xx",
+            "_xxxxx_xxx"
+        };
+        assert!(t_slots(&board).is_empty());
+    }
+
+    #[test]
+    fn test_imperial_cross() {
+        let board = bit_board! {
+            "___x______",
+            "__________",
+            "__x_x_____",
+            "xx___xxxxx",
+            "xxx_xxxxxx"
+        };
+        let slot = best_t_slot(&board).unwrap();
+        assert_eq!(slot.kind, TSlotKind::ImperialCross);
+        assert_eq!(slot.rows, 0b11);
+        assert_eq!(slot.piece.pos.rot, Rotation::South);
+        assert_eq!((slot.piece.pos.x, slot.piece.pos.y), (3, 1));
+
+        // a single overhang is an ordinary TSD
+        let board = bit_board! {
+            "__x_______",
+            "xx___xxxxx",
+            "xxx_xxxxxx"
+        };
+        assert_eq!(best_t_slot(&board).unwrap().kind, TSlotKind::Tsd);
+
+        // both corners filled without the shaft is not a cross
+        let covered = bit_board! {
+            "__xxx_____",
+            "xx___xxxxx",
+            "xxx_xxxxxx"
+        };
+        let piece = PieceState {
+            pos: PiecePosition {
+                kind: PieceKind::T,
+                x: 3,
+                y: 1,
+                rot: Rotation::South,
+            },
+            spin: SpinKind::Full,
+        };
+        assert_eq!(classify(&covered, piece).unwrap().kind, TSlotKind::Tsd);
+    }
+
+    #[test]
+    fn test_scan() {
+        let boards = [
+            bit_board! {
+                "xxx_______",
+                "xx___xxxxx",
+                "xxx_xxxxxx"
+            },
+            bit_board! {
+                "xxx_______",
+                "xx________",
+                "xx_xxxxxxx",
+                "xx__xxxxxx",
+                "xx_xxxxxxx"
+            },
+            bit_board! {
+                "xxx_______",
+                "xx________",
+                "xx_xxxxx__",
+                "xx__xxxxxx",
+                "xx_xxxxxxx"
+            },
+            bit_board! {
+                "______x___",
+                "__________",
+                "xxxxxx_xxx",
+                "xxxxxx__xx",
+                "_xxxxx_xxx"
+            },
+            bit_board! {
+                "___x______",
+                "__________",
+                "__x_x_____",
+                "xx___xxxxx",
+                "xxx_xxxxxx"
+            },
+            bit_board! {
+                "_______xxx",
+                "xxxxx___xx",
+                "xxxxxx_xxx"
+            },
+            bit_board! {
+                "xxxxx_____",
+                "xx___xxxxx",
+                "xxx_xxxxxx"
+            },
+            BitBoard::default(),
+        ];
+        for board in boards {
+            assert_eq!(scan_t_slot(&board), best_t_slot(&board), "{:?}", board);
+        }
+    }
+
+    #[test]
+    fn test_unreachable() {
+        // the slot is sealed from above
+        let board = bit_board! {
+            "xxxxx_____",
+            "xx___xxxxx",
+            "xxx_xxxxxx"
+        };
+        assert!(t_slots(&board).is_empty());
+
+        assert!(t_slots(&BitBoard::default()).is_empty());
+    }
+}
@@ -0,0 +1,289 @@
+//! Zobrist-style hashing of boards and game states.
+//!
+//! Cell `(x, y)` is keyed by `COLUMN_KEYS[x].rotate_left(y)`, so raising the whole board by a row
+//! only rotates the hash, and a line clear only rehashes the rows above the lowest cleared line.
+//! Every column key has an odd number of set bits, which keeps the 64 keys of a column linearly
+//! independent: two different columns never hash the same.
+
+use std::hash::{Hash, Hasher};
+
+use super::*;
+
+const COLUMN_KEYS: [u64; 10] = keys(0x6869_6b61_7269_0001);
+const HOLD_KEYS: [u64; 7] = keys(0x6869_6b61_7269_0002);
+const BAG_KEYS: [u64; 7] = keys(0x6869_6b61_7269_0003);
+const QUEUE_KEYS: [u64; 7] = keys(0x6869_6b61_7269_0004);
+const B2B_KEY: u64 = keys::<1>(0x6869_6b61_7269_0005)[0];
+const REN_KEY: u64 = keys::<1>(0x6869_6b61_7269_0006)[0];
+
+/// Generates keys with splitmix64, forcing an odd number of set bits.
+const fn keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys = [0; N];
+    let mut state = seed;
+    let mut i = 0;
+    while i < N {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        keys[i] = if z.count_ones() % 2 == 1 { z } else { z ^ 1 };
+        i += 1;
+    }
+    keys
+}
+
+pub trait ZobristHash {
+    fn zobrist(&self) -> u64;
+}
+
+/// Returns the key of a single cell.
+pub fn cell_key(x: i8, y: i8) -> u64 {
+    COLUMN_KEYS[x as usize].rotate_left(y as u32)
+}
+
+/// Hashes the cells of `cols` at or above row `y`.
+fn hash_rows_from(cols: &[u64; 10], y: u32) -> u64 {
+    let mask = u64::MAX.checked_shl(y).unwrap_or(0);
+    cols.iter()
+        .zip(COLUMN_KEYS)
+        .fold(0, |acc, (&col, key)| acc ^ hash_column(col & mask, key))
+}
+
+fn hash_column(mut col: u64, key: u64) -> u64 {
+    let mut hash = 0;
+    while col != 0 {
+        hash ^= key.rotate_left(col.trailing_zeros());
+        col &= col - 1;
+    }
+    hash
+}
+
+impl ZobristHash for BitBoard {
+    /// Computes the hash from scratch.
+    fn zobrist(&self) -> u64 {
+        hash_rows_from(&self.cols, 0)
+    }
+}
+
+/// A [`BitBoard`] that keeps its Zobrist hash up to date.
+///
+/// [`Hash`] only feeds the stored hash, so hash maps keyed by it skip hashing the whole board,
+/// while equality still compares the cells.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct HashedBoard {
+    // compared first, so that unequal boards are usually rejected without comparing the cells
+    hash: u64,
+    board: BitBoard,
+}
+
+impl HashedBoard {
+    pub fn new(board: BitBoard) -> Self {
+        Self {
+            hash: board.zobrist(),
+            board,
+        }
+    }
+
+    pub fn board(&self) -> &BitBoard {
+        &self.board
+    }
+
+    pub fn into_inner(self) -> BitBoard {
+        self.board
+    }
+}
+
+impl From<BitBoard> for HashedBoard {
+    fn from(board: BitBoard) -> Self {
+        Self::new(board)
+    }
+}
+
+impl From<HashedBoard> for BitBoard {
+    fn from(board: HashedBoard) -> Self {
+        board.board
+    }
+}
+
+impl Hash for HashedBoard {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+impl ZobristHash for HashedBoard {
+    fn zobrist(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl Board for HashedBoard {
+    fn occupied(&self, pos: (i8, i8)) -> bool {
+        self.board.occupied(pos)
+    }
+
+    fn height_of(&self, x: i8) -> u32 {
+        self.board.height_of(x)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.board.is_empty()
+    }
+
+    fn distance_to_ground(&self, pos: (i8, i8)) -> u32 {
+        self.board.distance_to_ground(pos)
+    }
+
+    fn add_garbage_line(&mut self, x: i8) {
+        // The top row is pushed out of the board, every other cell moves up by one
+        self.hash ^= hash_rows_from(&self.board.cols, u64::BITS - 1);
+        self.hash = self.hash.rotate_left(1);
+        self.hash ^= COLUMN_KEYS
+            .iter()
+            .enumerate()
+            .filter(|&(col, _)| col != x as usize)
+            .fold(0, |acc, (_, key)| acc ^ key);
+        self.board.add_garbage_line(x);
+    }
+
+    fn add_piece_and_clear(&mut self, piece: PieceState) -> u32 {
+        let mut cols = self.board.cols;
+        for (x, y) in piece.pos.cells() {
+            cols[x as usize] ^= 1 << y;
+            self.hash ^= cell_key(x, y);
+        }
+
+        let lines = cols.iter().fold(u64::MAX, |acc, col| acc & col);
+        if lines == 0 {
+            self.board.cols = cols;
+            return 0;
+        }
+
+        let lowest = lines.trailing_zeros();
+        self.hash ^= hash_rows_from(&cols, lowest);
+        let cleared = self.board.add_piece_and_clear(piece);
+        self.hash ^= hash_rows_from(&self.board.cols, lowest);
+        cleared
+    }
+}
+
+impl<B: Board + ZobristHash> ZobristHash for GameState<B> {
+    fn zobrist(&self) -> u64 {
+        let mut hash = self.board.zobrist();
+        if let Some(hold) = self.hold {
+            hash ^= HOLD_KEYS[hold as usize];
+        }
+        hash ^= self
+            .bag
+            .0
+            .iter()
+            .fold(0, |acc, piece| acc ^ BAG_KEYS[piece as usize]);
+        hash ^= self
+            .queue
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, &piece)| {
+                acc ^ QUEUE_KEYS[piece as usize].rotate_left(i as u32 * 7)
+            });
+        if self.b2b {
+            hash ^= B2B_KEY;
+        }
+        hash ^ REN_KEY.wrapping_mul(self.ren as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_board;
+
+    #[test]
+    fn test_column_keys_independent() {
+        // Distinct contents of a single column must never collide
+        let board = bit_board! {
+            "x_________",
+            "x_________"
+        };
+        let other = bit_board! {
+            "_x________",
+            "_x________"
+        };
+        assert_ne!(board.zobrist(), other.zobrist());
+        assert_ne!(board.zobrist(), 0);
+        assert_eq!(BitBoard::default().zobrist(), 0);
+    }
+
+    #[test]
+    fn test_incremental() {
+        // a line clear moves the rows above it down
+        let mut state = GameState::<HashedBoard>::new();
+        state.board = HashedBoard::new(bit_board! {
+            "x_________",
+            "xxxxxxxx__",
+            "xxxxxxxx__"
+        });
+        state.add_piece(PieceKind::O);
+        let moves = state.legal_moves(false).unwrap().moves();
+        let cleared = moves
+            .into_iter()
+            .filter_map(|mv| {
+                let Move::Place(piece) = mv else {
+                    return None;
+                };
+                let mut board = state.board.clone();
+                (board.add_piece_and_clear(piece) == 2).then_some(board)
+            })
+            .collect::<Vec<_>>();
+        assert!(!cleared.is_empty());
+        let expected = HashedBoard::new(bit_board! { "x_________" });
+        for board in cleared {
+            assert_eq!(board, expected);
+            assert_eq!(board.zobrist(), expected.zobrist());
+        }
+
+        // garbage pushes the top row out of the board
+        let mut board = HashedBoard::new(BitBoard {
+            cols: std::array::from_fn(|x| (x as u64 & 1) << 63 | 1),
+        });
+        board.add_garbage_line(3);
+        assert_eq!(board.zobrist(), board.board().zobrist());
+        assert_eq!(board.board().cols[0], 0b11);
+    }
+
+    #[test]
+    fn test_collision() {
+        // equality compares the cells, so boards whose hashes collide stay apart
+        let board = HashedBoard::new(bit_board! { "x_________" });
+        let forged = HashedBoard {
+            hash: board.hash,
+            board: bit_board! { "_x________" },
+        };
+        assert_ne!(board, forged);
+        assert_eq!(board, HashedBoard::new(board.board().clone()));
+    }
+
+    #[test]
+    fn test_game_state() {
+        let mut state = GameState::<BitBoard>::new();
+        state.add_piece(PieceKind::T);
+        state.add_piece(PieceKind::S);
+        let base = state.zobrist();
+
+        let mut swapped = state.clone();
+        swapped.queue.swap(0, 1);
+        assert_ne!(swapped.zobrist(), base);
+
+        let mut held = state.clone();
+        held.hold = Some(PieceKind::I);
+        assert_ne!(held.zobrist(), base);
+
+        let mut b2b = state.clone();
+        b2b.b2b = true;
+        assert_ne!(b2b.zobrist(), base);
+
+        let mut ren = state.clone();
+        ren.ren = 0;
+        assert_ne!(ren.zobrist(), base);
+    }
+}
@@ -1,41 +1,34 @@
-use std::{
-    collections::HashMap,
-    env::args,
-    process::Stdio,
-    sync::{atomic::AtomicBool, Arc},
-    time::Instant,
-};
+use std::{collections::HashMap, env::args, process::Stdio, sync::Arc, time::Instant};
 
 use game::tetris::*;
-use serde::{ser::SerializeSeq, Serialize};
+use serde::Serialize;
 use smallvec::SmallVec;
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt},
-    process::Command,
-    sync::{
-        mpsc::{Receiver, Sender},
-        Notify,
-    },
-    task::JoinHandle,
-};
+use tbp_io::{Connection, Endpoint, Host, HostConfig};
+use tokio::{process::Command, sync::Notify, task::JoinHandle};
 
 const TBP_LOGGING: bool = false;
 
 #[tokio::main]
 async fn main() {
     eprintln!("gen_cc");
-    let exe_path = args().nth(1).expect("usage: gen_cc <exe_path> <out_dir>");
-    let out_dir = args().nth(2).expect("usage: gen_cc <exe_path> <out_dir>");
+    const USAGE: &str = "usage: gen_cc <exe_path|endpoint> <out_dir> [bot args...]";
+    // the bot is either an executable or an endpoint such as tcp://host:port or ws://host:port,
+    // and the remaining arguments are passed to the executable, such as its search limits
+    let exe_path = args().nth(1).expect(USAGE);
+    let out_dir = args().nth(2).expect(USAGE);
+    let bot_command = std::iter::once(exe_path)
+        .chain(args().skip(3))
+        .collect::<Vec<_>>();
 
     let workers = (0..4)
         .map(|i| {
-            let exe_path = exe_path.to_owned();
+            let bot_command = bot_command.clone();
             let out_dir = out_dir.to_owned();
             tokio::spawn(async move {
                 tokio::time::sleep(std::time::Duration::from_secs(i * 2)).await;
                 loop {
                     eprintln!("worker {} started", i);
-                    gen_cc(&exe_path, &out_dir).await;
+                    gen_cc(&bot_command, &out_dir).await;
                 }
             })
         })
@@ -46,8 +39,7 @@ async fn main() {
     }
 }
 
-async fn gen_cc(exe_path: &str, out_dir: &str) {
-    let exe_path = exe_path.to_owned();
+async fn gen_cc(bot_command: &[String], out_dir: &str) {
     let out_dir = out_dir.to_owned();
     let (out_sender, mut out_receiver) = tokio::sync::mpsc::channel::<Replay>(16);
     let file_writer = tokio::spawn(async move {
@@ -82,7 +74,7 @@ async fn gen_cc(exe_path: &str, out_dir: &str) {
     });
 
     let mut game = Game::new();
-    game.start(&exe_path, out_sender);
+    game.start(bot_command, out_sender);
 
     let mut update_interval = tokio::time::interval(std::time::Duration::from_millis(16));
     let update_worker = tokio::spawn(async move {
@@ -125,25 +117,24 @@ impl Game {
         }
     }
 
-    fn start(&mut self, exe_path: &str, replay_sender: tokio::sync::mpsc::Sender<Replay>) {
+    fn start(&mut self, bot_command: &[String], replay_sender: tokio::sync::mpsc::Sender<Replay>) {
         let replay_sender = Arc::new(replay_sender);
         let mut players = Vec::new();
         for i in 0..2 {
-            players.push(self.spawn_player(exe_path, i as u32, replay_sender.clone()));
+            players.push(self.spawn_player(bot_command, i as u32, replay_sender.clone()));
         }
         self.players = players;
     }
 
     fn spawn_player(
         &mut self,
-        exe_path: &str,
+        bot_command: &[String],
         id: u32,
         replay_sender: Arc<tokio::sync::mpsc::Sender<Replay>>,
     ) -> PlayerHandle {
         let updater = self.updater.clone();
-        let exe_path = exe_path.to_owned();
         let damage_sender = self.damage_sender.clone();
-        let p = PlayerHandle::new(id, &exe_path, updater, damage_sender, replay_sender);
+        let p = PlayerHandle::new(id, bot_command, updater, damage_sender, replay_sender);
         p
     }
 
@@ -218,16 +209,16 @@ struct PlayerHandle {
 impl PlayerHandle {
     fn new(
         id: u32,
-        exe_path: &str,
+        bot_command: &[String],
         updater: UpdateNotifier,
         damage_sender: Arc<std::sync::mpsc::Sender<DamageData>>,
         replay_sender: Arc<tokio::sync::mpsc::Sender<Replay>>,
     ) -> Self {
         let (garbage_sender, garbage_recv) = tokio::sync::mpsc::channel(16);
 
-        let exe_path = exe_path.to_owned();
+        let bot_command = bot_command.to_vec();
         let join_handle = tokio::spawn(async move {
-            let mut p = Player::new(id, &exe_path);
+            let mut p = Player::new(id, &bot_command).await;
             p.run(updater, damage_sender, garbage_recv, replay_sender)
                 .await;
         });
@@ -239,15 +230,13 @@ impl PlayerHandle {
     }
 }
 
-#[derive(Debug)]
 struct Player {
     id: u32,
     state: GameState<BitBoard>,
     damage_buffer: u32,
-    recv: Receiver<tbp::BotMessage>,
-    send: Sender<tbp::FrontendMessage>,
-    process: tokio::process::Child,
-    disconnected: Arc<AtomicBool>,
+    host: Host,
+    /// The bot process, unless connected to a served bot.
+    process: Option<tokio::process::Child>,
 }
 
 impl Drop for Player {
@@ -257,82 +246,73 @@ impl Drop for Player {
 }
 
 impl Player {
-    fn new(id: u32, exe_path: &str) -> Self {
+    /// Spawns the bot executable with its arguments, or connects to it if the command is a TBP
+    /// endpoint.
+    async fn new(id: u32, bot_command: &[String]) -> Self {
+        let (exe_path, bot_args) = bot_command.split_first().expect("bot command");
         let mut state = GameState::new();
         for _ in 0..5 {
             state.fulfill_queue();
         }
 
-        let mut process = Command::new(exe_path)
-            .kill_on_drop(true)
-            .stderr(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stdin(Stdio::piped())
-            .spawn()
-            .expect("spawn failed");
-
-        let out = process.stdout.take().unwrap();
-        let mut reader = tokio::io::BufReader::new(out).lines();
-
-        let mut writer = tokio::io::BufWriter::new(process.stdin.take().unwrap());
-
-        eprintln!("spawn ok");
-
-        let (bot_send, recv) = tokio::sync::mpsc::channel(16);
-
-        let disconnected = Arc::new(AtomicBool::new(false));
-
-        let _discon = disconnected.clone();
-        tokio::spawn(async move {
-            loop {
-                if let Some(s) = reader.next_line().await.expect("read line") {
-                    if TBP_LOGGING {
-                        eprintln!("[RECV/{}] {}", id, s);
-                    }
-                    let msg = serde_json::from_str(&s).expect("deserialize json");
-                    bot_send.send(msg).await.expect("send");
-                } else {
-                    eprintln!("disconnection");
-                    _discon.store(true, std::sync::atomic::Ordering::Relaxed);
-                    break;
-                }
+        let (connection, process) = match exe_path.parse::<Endpoint>() {
+            Ok(endpoint) => {
+                let connection = Connection::connect(&endpoint)
+                    .await
+                    .expect("connect failed");
+                eprintln!("connect ok");
+                (connection, None)
             }
-        });
-
-        let (send, mut bot_recv) = tokio::sync::mpsc::channel(16);
-
-        let _discon = disconnected.clone();
-        tokio::spawn(async move {
-            loop {
-                match bot_recv.recv().await {
-                    Some(msg) => {
-                        let s = serde_json::to_string(&msg).expect("serialize json");
-                        if TBP_LOGGING {
-                            eprintln!("[SEND/{}] {}", id, s);
-                        }
-                        writer.write_all(s.as_bytes()).await.expect("write");
-                        writer.write_all("\n".as_bytes()).await.expect("write");
-                        writer.flush().await.expect("flush");
-                    }
-                    _ => {
-                        eprintln!("disconnection");
-                        _discon.store(true, std::sync::atomic::Ordering::Relaxed);
-                        break;
-                    }
-                }
+            Err(_) => {
+                let mut process = Command::new(exe_path)
+                    .args(bot_args)
+                    .kill_on_drop(true)
+                    .stderr(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .expect("spawn failed");
+                let connection = Connection::lines(
+                    process.stdout.take().unwrap(),
+                    process.stdin.take().unwrap(),
+                );
+                eprintln!("spawn ok");
+                (connection, Some(process))
             }
-        });
+        };
 
-        eprintln!("ready");
+        let config = HostConfig {
+            log: TBP_LOGGING.then(|| id.to_string()),
+            ..Default::default()
+        };
+        let host = Host::connect(connection, config)
+            .await
+            .expect("no bot info");
+        let info = host.info();
+        eprintln!(
+            "name: {}, version: {}, author: {}, features: {:?}",
+            info.name, info.version, info.author, info.features
+        );
 
         Self {
             id,
             state,
-            recv,
-            send,
+            host,
             damage_buffer: 0,
             process,
-            disconnected,
+        }
+    }
+
+    fn start_message(&self) -> tbp::Start {
+        tbp::Start {
+            board: self.state.board.clone().into_colored(CellKind::Gbg),
+            queue: self.state.queue.clone().into_iter().collect(),
+            hold: self.state.hold,
+            combo: (self.state.ren + 1) as u32,
+            back_to_back: self.state.b2b,
+            randomizer: tbp::Randomizer::SevenBag {
+                bag_state: self.state.bag.0.clone(),
+            },
         }
     }
 
@@ -343,62 +323,38 @@ impl Player {
         mut garbage_recv: tokio::sync::mpsc::Receiver<u32>,
         replay_sender: Arc<tokio::sync::mpsc::Sender<Replay>>,
     ) {
-        let msg = self.recv.recv().await;
-        if let Some(tbp::BotMessage::Info {
-            name,
-            version,
-            author,
-            features,
-        }) = msg
-        {
-            eprintln!(
-                "name: {}, version: {}, author: {}, features: {:?}",
-                name, version, author, features
-            );
-        } else {
+        if let Err(err) = self.host.rules(Some("seven_bag".to_owned())).await {
+            eprintln!("rules rejected: {}", err);
             return;
         }
+        eprintln!("ready");
 
-        self.send
-            .send(tbp::FrontendMessage::Rules {
-                randomizer: "seven_bag".to_owned(),
-            })
-            .await
-            .unwrap();
-
-        let msg = self.recv.recv().await;
-        if let Some(tbp::BotMessage::Ready) = msg {
-            eprintln!("ready");
-        } else {
+        // Start and countdown
+        let start = self.start_message();
+        if let Err(err) = self.host.start(start).await {
+            eprintln!("cannot start: {}", err);
             return;
         }
 
-        // Start and countdown
-        self.send
-            .send(tbp::FrontendMessage::Start(tbp::Start {
-                board: self.state.board.clone().into_colored(CellKind::Gbg),
-                queue: self.state.queue.clone().into_iter().collect(),
-                hold: self.state.hold,
-                combo: (self.state.ren + 1) as u32,
-                back_to_back: self.state.b2b,
-                randomizer: tbp::Randomizer::SevenBag {
-                    bag_state: self.state.bag.0.clone(),
-                },
-            }))
-            .await
-            .unwrap();
-
         update_notifier.wait_for_frames(60).await;
 
-        while self.disconnected.load(std::sync::atomic::Ordering::Relaxed) == false {
-            self.run_loop(
-                update_notifier.clone(),
-                damage_sender.clone(),
-                &mut garbage_recv,
-                &replay_sender,
-            )
-            .await
-            .unwrap();
+        loop {
+            let result = self
+                .run_loop(
+                    update_notifier.clone(),
+                    damage_sender.clone(),
+                    &mut garbage_recv,
+                    &replay_sender,
+                )
+                .await;
+            match result {
+                Ok(()) => {}
+                Err(BotStopReason::Disconnection) => {
+                    eprintln!("disconnection");
+                    break;
+                }
+                Err(reason) => panic!("bot stopped: {:?}", reason),
+            }
         }
     }
 
@@ -411,18 +367,9 @@ impl Player {
     ) -> Result<(), BotStopReason> {
         while let Ok(garbage) = garbage_recv.try_recv() {
             self.state.add_garbage(garbage);
-            let msg = tbp::FrontendMessage::Start(tbp::Start {
-                board: self.state.board.clone().into_colored(CellKind::Gbg),
-                queue: self.state.queue.clone().into_iter().collect(),
-                hold: self.state.hold,
-                combo: (self.state.ren + 1) as u32,
-                back_to_back: self.state.b2b,
-                randomizer: tbp::Randomizer::SevenBag {
-                    bag_state: self.state.bag.0.clone(),
-                },
-            });
-            // eprintln!("apply garbage: {:?}, {:?}", garbage, msg);
-            self.send.send(msg).await.unwrap();
+            let start = self.start_message();
+            // eprintln!("apply garbage: {:?}, {:?}", garbage, start);
+            self.host.start(start).await?;
             update_notifier.wait_for_frames(10).await;
         }
 
@@ -432,10 +379,6 @@ impl Player {
             .spawn_next()
             .ok_or(BotStopReason::Death)?;
 
-        self.send
-            .send(tbp::FrontendMessage::Suggest)
-            .await
-            .map_err(|_| BotStopReason::Disconnection)?;
         let timer = Instant::now();
 
         let mut candidates = HashMap::<PieceIdentity, (bool, Move, u16)>::new();
@@ -480,94 +423,79 @@ impl Player {
         }
         // eprintln!("moves found: {:?}", candidates.keys());
 
-        loop {
-            match self.recv.recv().await {
-                Some(tbp::BotMessage::Suggestion { moves, move_info }) => {
-                    for (i, piece) in moves.iter().enumerate() {
-                        if let Some(&(hold_before, mv, cost)) = candidates.get(&(*piece).into()) {
-                            // eprintln!(
-                            //     "pick: #{} {:?} at cost {}, elapsed {}us",
-                            //     i,
-                            //     mv,
-                            //     cost,
-                            //     timer.elapsed().as_micros()
-                            // );
-
-                            let replay = Replay {
-                                player_id: self.id,
-                                frame: 0,
-                                state: replay,
-                                action: (*piece).into(),
-                            };
-                            let replay_send = replay_sender.send(replay);
-
-                            if hold_before {
-                                update_notifier.wait_for_frames(2).await;
-                                self.advance(Move::Hold)
-                                    .await
-                                    .map_err(|_| BotStopReason::Death)?;
-                            }
-                            // then
-                            let pl = match mv {
-                                Move::Place(piece) => {
-                                    update_notifier.wait_for_frames(cost as u64).await;
-                                    self.advance(Move::Place(piece))
-                                        .await
-                                        .map_err(|_| BotStopReason::Death)?
-                                }
-                                _ => unreachable!(),
-                            };
-                            self.send
-                                .send(tbp::FrontendMessage::Play { mv: *piece })
-                                .await
-                                .map_err(|_| BotStopReason::Disconnection)?;
-
-                            // send damage
-
-                            damage_sender
-                                .send(DamageData {
-                                    amount: pl.attack(),
-                                    source: self.id,
-                                    wait: 60,
-                                })
-                                .unwrap();
-                            // placement delay
-                            let delay = if pl.lines_cleared > 0 && !pl.is_pc {
-                                15
-                            } else {
-                                0
-                            };
-
-                            update_notifier.wait_for_frames(delay).await;
-                            replay_send.await.unwrap();
-                            return Ok(());
-                        }
-                    }
-                    eprintln!("move {:?} not found {:?}", moves, move_info);
-                    return Err(BotStopReason::Death);
-                }
-                Some(_) => {
-                    return Err(BotStopReason::IllegalMessage);
+        let tbp_io::Suggestion { moves, move_info } = self.host.suggest().await?;
+        for (i, piece) in moves.iter().enumerate() {
+            if let Some(&(hold_before, mv, cost)) = candidates.get(&(*piece).into()) {
+                // eprintln!(
+                //     "pick: #{} {:?} at cost {}, elapsed {}us",
+                //     i,
+                //     mv,
+                //     cost,
+                //     timer.elapsed().as_micros()
+                // );
+
+                let replay = Replay {
+                    player_id: self.id,
+                    frame: 0,
+                    state: replay,
+                    action: (*piece).into(),
+                };
+                let replay_send = replay_sender.send(replay);
+
+                if hold_before {
+                    update_notifier.wait_for_frames(2).await;
+                    self.advance(Move::Hold)
+                        .await
+                        .map_err(|_| BotStopReason::Death)?;
                 }
-                _ => return Err(BotStopReason::Disconnection),
+                // then
+                let pl = match mv {
+                    Move::Place(piece) => {
+                        update_notifier.wait_for_frames(cost as u64).await;
+                        self.advance(Move::Place(piece))
+                            .await
+                            .map_err(|_| BotStopReason::Death)?
+                    }
+                    _ => unreachable!(),
+                };
+                self.host.play(*piece).await?;
+
+                // send damage
+
+                damage_sender
+                    .send(DamageData {
+                        amount: pl.attack(),
+                        source: self.id,
+                        wait: 60,
+                    })
+                    .unwrap();
+                // placement delay
+                let delay = if pl.lines_cleared > 0 && !pl.is_pc {
+                    15
+                } else {
+                    0
+                };
+
+                update_notifier.wait_for_frames(delay).await;
+                replay_send.await.unwrap();
+                return Ok(());
             }
         }
+        eprintln!("move {:?} not found {:?}", moves, move_info);
+        Err(BotStopReason::Death)
     }
 
-    async fn advance(&mut self, mv: Move) -> Result<PlacementResult, ()> {
+    async fn advance(&mut self, mv: Move) -> Result<PlacementResult, BotStopReason> {
         // eprintln!("advance: {:?}", mv);
         let pl = self.state.advance(mv);
 
         // Add piece
         let last_piece = self.state.fulfill_queue();
-        self.send
-            .send(tbp::FrontendMessage::NewPiece { piece: last_piece })
-            .await
-            .map_err(|_| ())?;
+        self.host.new_piece(last_piece).await?;
 
         // eprintln!("queue: {:?}, hold: {:?}", self.state.queue, self.state.hold);
         if pl.death {
-            Err(())
+            Err(BotStopReason::Death)
         } else {
             Ok(pl)
         }
@@ -579,6 +507,20 @@ enum BotStopReason {
     Death,
     IllegalMessage,
     Disconnection,
+    Timeout,
+}
+
+impl From<tbp_io::Error> for BotStopReason {
+    fn from(err: tbp_io::Error) -> Self {
+        match err {
+            tbp_io::Error::Io(_) | tbp_io::Error::Disconnected => BotStopReason::Disconnection,
+            tbp_io::Error::Timeout { .. } => BotStopReason::Timeout,
+            err => {
+                eprintln!("{}", err);
+                BotStopReason::IllegalMessage
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -637,7 +579,7 @@ struct Replay {
 
 #[derive(Debug, Clone, Serialize)]
 struct ReplayState {
-    #[serde(serialize_with = "serialize_board")]
+    #[serde(with = "game::tetris::codec::bool_grid")]
     board: BitBoard,
     current: PieceKind,
     unhold: PieceKind,
@@ -647,15 +589,3 @@ struct ReplayState {
     b2b: bool,
     bag: SmallVec<[PieceKind; 7]>,
 }
-
-fn serialize_board<S>(board: &BitBoard, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    let mut cols = serializer.serialize_seq(Some(10))?;
-    for x in 0..10 {
-        let cells: Vec<bool> = (0..64).map(|y| board.occupied((x, y as i8))).collect();
-        cols.serialize_element(&cells)?;
-    }
-    cols.end()
-}
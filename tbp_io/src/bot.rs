@@ -0,0 +1,388 @@
+//! The bot side of the protocol.
+//!
+//! [`BotProtocol`] checks that frontend messages arrive in a legal order and turns them into calls
+//! to a [`Bot`], which only has to implement the game logic. [`run_bot`] drives it over a
+//! [`Connection`].
+
+use std::ops::ControlFlow;
+
+use game::tetris::{tbp::*, PieceKind, PieceState};
+
+use crate::{error::frontend_message_type, Connection, Error, ProtocolState, ProtocolViolation};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BotInfo {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub features: Vec<String>,
+}
+
+impl From<BotInfo> for BotMessage {
+    fn from(info: BotInfo) -> Self {
+        BotMessage::Info {
+            name: info.name,
+            version: info.version,
+            author: info.author,
+            features: info.features,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    /// Best move first. Empty when the bot has no move.
+    pub moves: Vec<PieceState>,
+    pub move_info: MoveInfo,
+}
+
+impl From<Suggestion> for BotMessage {
+    fn from(suggestion: Suggestion) -> Self {
+        BotMessage::Suggestion {
+            moves: suggestion.moves,
+            move_info: suggestion.move_info,
+        }
+    }
+}
+
+/// Game logic of a bot. Messages are only passed on when they are legal in the current state.
+pub trait Bot {
+    fn info(&self) -> BotInfo;
+
+    /// Rejecting the rules, usually with [`BotErrorReason::UnsupportedRules`], keeps the bot
+    /// waiting for other rules.
+    fn rules(&mut self, randomizer: Option<&str>) -> Result<(), BotErrorReason>;
+
+    /// Starts a game, replacing the running one if any.
+    fn start(&mut self, start: Start) -> Result<(), BotErrorReason>;
+
+    fn stop(&mut self);
+
+    fn suggest(&mut self) -> Result<Suggestion, BotErrorReason>;
+
+    fn play(&mut self, mv: PieceState) -> Result<(), BotErrorReason>;
+
+    fn new_piece(&mut self, piece: PieceKind) -> Result<(), BotErrorReason>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BotProtocol {
+    state: ProtocolState,
+}
+
+impl Default for BotProtocol {
+    fn default() -> Self {
+        Self {
+            state: ProtocolState::AwaitingRules,
+        }
+    }
+}
+
+impl BotProtocol {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> ProtocolState {
+        self.state
+    }
+
+    /// Handles a frame. Malformed frames are answered with [`BotErrorReason::InvalidMessage`].
+    pub fn handle_line<B: Bot + ?Sized>(
+        &mut self,
+        bot: &mut B,
+        line: &str,
+    ) -> ControlFlow<(), Option<BotMessage>> {
+        if line.trim().is_empty() {
+            return ControlFlow::Continue(None);
+        }
+        match serde_json::from_str::<FrontendMessage>(line) {
+            Ok(message) => self.handle(bot, message),
+            Err(err) => {
+                // such as a board of the wrong size
+                eprintln!("invalid message: {}", err);
+                ControlFlow::Continue(Some(error(BotErrorReason::InvalidMessage)))
+            }
+        }
+    }
+
+    /// Handles a message, returning the reply if any. Breaks when the frontend quits.
+    ///
+    /// Messages that are not allowed in the current state are answered with
+    /// [`BotErrorReason::UnexpectedMessage`], and unknown messages are ignored.
+    pub fn handle<B: Bot + ?Sized>(
+        &mut self,
+        bot: &mut B,
+        message: FrontendMessage,
+    ) -> ControlFlow<(), Option<BotMessage>> {
+        use ProtocolState::*;
+
+        let reply = match (self.state, message) {
+            (_, FrontendMessage::Rules { randomizer }) => {
+                self.stop(bot);
+                match bot.rules(randomizer.as_deref()) {
+                    Ok(()) => {
+                        self.state = Ready;
+                        Some(BotMessage::Ready)
+                    }
+                    Err(reason) => {
+                        self.state = AwaitingRules;
+                        Some(error(reason))
+                    }
+                }
+            }
+            (Ready | Running, FrontendMessage::Start(start)) => {
+                self.stop(bot);
+                match bot.start(start) {
+                    Ok(()) => {
+                        self.state = Running;
+                        None
+                    }
+                    Err(reason) => Some(error(reason)),
+                }
+            }
+            // stopping twice is harmless
+            (_, FrontendMessage::Stop) => {
+                self.stop(bot);
+                None
+            }
+            (Running, FrontendMessage::Suggest) => Some(match bot.suggest() {
+                Ok(suggestion) => suggestion.into(),
+                Err(reason) => error(reason),
+            }),
+            (Running, FrontendMessage::Play { mv }) => bot.play(mv).err().map(error),
+            (Running, FrontendMessage::NewPiece { piece }) => bot.new_piece(piece).err().map(error),
+            (_, FrontendMessage::Quit) => {
+                self.stop(bot);
+                return ControlFlow::Break(());
+            }
+            // unknown messages must be ignored as per the specification
+            (_, FrontendMessage::Unknown) => None,
+            (state, message) => {
+                let violation = ProtocolViolation {
+                    state,
+                    message: frontend_message_type(&message),
+                };
+                eprintln!("{}", violation);
+                Some(error(BotErrorReason::UnexpectedMessage))
+            }
+        };
+        ControlFlow::Continue(reply)
+    }
+
+    /// Stops the running game, as when the frontend disconnects without quitting.
+    pub fn stop<B: Bot + ?Sized>(&mut self, bot: &mut B) {
+        if self.state == ProtocolState::Running {
+            bot.stop();
+            self.state = ProtocolState::Ready;
+        }
+    }
+}
+
+/// Plays the protocol over the connection until the frontend quits or disconnects.
+pub async fn run_bot<B: Bot + ?Sized>(
+    mut connection: Connection,
+    bot: &mut B,
+) -> Result<(), Error> {
+    let mut protocol = BotProtocol::new();
+    connection.send(&BotMessage::from(bot.info())).await?;
+
+    let result = loop {
+        let line = match connection.recv_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break Ok(()),
+            Err(err) => break Err(err.into()),
+        };
+        match protocol.handle_line(bot, &line) {
+            ControlFlow::Continue(Some(reply)) => {
+                if let Err(err) = connection.send(&reply).await {
+                    break Err(err.into());
+                }
+            }
+            ControlFlow::Continue(None) => {}
+            ControlFlow::Break(()) => break Ok(()),
+        }
+    };
+    // the game must not keep running for a frontend that is gone
+    protocol.stop(bot);
+    result
+}
+
+fn error(reason: BotErrorReason) -> BotMessage {
+    BotMessage::Error { reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::tetris::ColoredBoard;
+
+    /// Records the calls it receives.
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<&'static str>,
+    }
+
+    impl Bot for Recorder {
+        fn info(&self) -> BotInfo {
+            BotInfo {
+                name: "recorder".to_owned(),
+                version: "0".to_owned(),
+                author: "".to_owned(),
+                features: vec![],
+            }
+        }
+
+        fn rules(&mut self, randomizer: Option<&str>) -> Result<(), BotErrorReason> {
+            self.calls.push("rules");
+            match randomizer {
+                None | Some("seven_bag") => Ok(()),
+                Some(_) => Err(BotErrorReason::UnsupportedRules),
+            }
+        }
+
+        fn start(&mut self, _: Start) -> Result<(), BotErrorReason> {
+            self.calls.push("start");
+            Ok(())
+        }
+
+        fn stop(&mut self) {
+            self.calls.push("stop");
+        }
+
+        fn suggest(&mut self) -> Result<Suggestion, BotErrorReason> {
+            self.calls.push("suggest");
+            Ok(Suggestion {
+                moves: vec![],
+                move_info: MoveInfo {
+                    nodes: 0,
+                    nps: 0.0,
+                    extra: "".to_owned(),
+                },
+            })
+        }
+
+        fn play(&mut self, _: PieceState) -> Result<(), BotErrorReason> {
+            self.calls.push("play");
+            Ok(())
+        }
+
+        fn new_piece(&mut self, _: PieceKind) -> Result<(), BotErrorReason> {
+            self.calls.push("new_piece");
+            Ok(())
+        }
+    }
+
+    fn start() -> FrontendMessage {
+        FrontendMessage::Start(Start {
+            board: ColoredBoard::default(),
+            queue: vec![PieceKind::T],
+            hold: None,
+            combo: 0,
+            back_to_back: false,
+            randomizer: Randomizer::Unknown,
+        })
+    }
+
+    fn reply(
+        protocol: &mut BotProtocol,
+        bot: &mut Recorder,
+        message: FrontendMessage,
+    ) -> Option<BotMessage> {
+        match protocol.handle(bot, message) {
+            ControlFlow::Continue(reply) => reply,
+            ControlFlow::Break(()) => panic!("unexpected quit"),
+        }
+    }
+
+    fn is_error(reply: Option<BotMessage>, expected: BotErrorReason) -> bool {
+        matches!(reply, Some(BotMessage::Error { reason }) if reason == expected)
+    }
+
+    #[test]
+    fn test_message_order() {
+        let mut protocol = BotProtocol::new();
+        let mut bot = Recorder::default();
+        let unexpected = BotErrorReason::UnexpectedMessage;
+
+        assert!(is_error(
+            reply(&mut protocol, &mut bot, FrontendMessage::Suggest),
+            unexpected
+        ));
+        assert!(is_error(
+            reply(&mut protocol, &mut bot, start()),
+            unexpected
+        ));
+        assert!(bot.calls.is_empty());
+
+        let classic = FrontendMessage::Rules {
+            randomizer: Some("classic".to_owned()),
+        };
+        assert!(is_error(
+            reply(&mut protocol, &mut bot, classic),
+            BotErrorReason::UnsupportedRules
+        ));
+        assert_eq!(protocol.state(), ProtocolState::AwaitingRules);
+        let rules = FrontendMessage::Rules { randomizer: None };
+        assert!(matches!(
+            reply(&mut protocol, &mut bot, rules),
+            Some(BotMessage::Ready)
+        ));
+
+        assert!(reply(&mut protocol, &mut bot, start()).is_none());
+        // a start while running restarts the game
+        assert!(reply(&mut protocol, &mut bot, start()).is_none());
+        assert!(matches!(
+            reply(&mut protocol, &mut bot, FrontendMessage::Suggest),
+            Some(BotMessage::Suggestion { .. })
+        ));
+        assert!(reply(&mut protocol, &mut bot, FrontendMessage::Stop).is_none());
+        assert!(reply(&mut protocol, &mut bot, FrontendMessage::Stop).is_none());
+        assert!(is_error(
+            reply(&mut protocol, &mut bot, FrontendMessage::Suggest),
+            unexpected
+        ));
+
+        assert!(protocol.handle(&mut bot, FrontendMessage::Quit).is_break());
+        assert_eq!(
+            bot.calls,
+            ["rules", "rules", "start", "stop", "start", "suggest", "stop"]
+        );
+    }
+
+    #[test]
+    fn test_malformed_lines() {
+        let mut protocol = BotProtocol::new();
+        let mut bot = Recorder::default();
+
+        let reply = protocol.handle_line(&mut bot, "not json");
+        assert!(matches!(
+            reply,
+            ControlFlow::Continue(Some(BotMessage::Error {
+                reason: BotErrorReason::InvalidMessage
+            }))
+        ));
+        let reply = protocol.handle_line(&mut bot, r#"{"type":"future_extension"}"#);
+        assert!(matches!(reply, ControlFlow::Continue(None)));
+        assert!(matches!(
+            protocol.handle_line(&mut bot, ""),
+            ControlFlow::Continue(None)
+        ));
+        let reply = protocol.handle_line(&mut bot, r#"{"type":"rules"}"#);
+        assert!(matches!(
+            reply,
+            ControlFlow::Continue(Some(BotMessage::Ready))
+        ));
+
+        let start = r#"{"type":"start","board":[],"queue":["T"],"hold":null,"combo":0,"back_to_back":false}"#;
+        let reply = protocol.handle_line(&mut bot, start);
+        assert!(matches!(
+            reply,
+            ControlFlow::Continue(Some(BotMessage::Error {
+                reason: BotErrorReason::InvalidMessage
+            }))
+        ));
+        assert_eq!(protocol.state(), ProtocolState::Ready);
+        assert_eq!(bot.calls, ["rules"]);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fmt, io, time::Duration};
+
+use game::tetris::tbp::{BotErrorReason, BotMessage, FrontendMessage};
+
+/// Where a connection is in the protocol, as seen by both sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolState {
+    /// Waiting for `rules`, or for rules that the bot supports.
+    AwaitingRules,
+    /// The rules are accepted, and no game is running.
+    Ready,
+    /// Between `start` and `stop`.
+    Running,
+}
+
+impl fmt::Display for ProtocolState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ProtocolState::AwaitingRules => "awaiting rules",
+            ProtocolState::Ready => "ready",
+            ProtocolState::Running => "running",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A message that is not allowed in the current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolViolation {
+    pub state: ProtocolState,
+    /// The `type` of the offending message.
+    pub message: &'static str,
+}
+
+impl fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unexpected {} while {}", self.message, self.state)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// A frame that is not a valid message. The connection can still be used.
+    Decode(serde_json::Error),
+    /// The peer closed the connection.
+    Disconnected,
+    /// The bot did not answer in time.
+    Timeout {
+        waiting_for: &'static str,
+        after: Duration,
+    },
+    Protocol(ProtocolViolation),
+    /// The bot answered with an error.
+    Bot(BotErrorReason),
+    /// An error of the bot came while waiting for a suggestion, so the later replies can no longer
+    /// be matched to requests. Suggestions are refused until the bot is connected again.
+    Desynced,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "i/o error: {}", err),
+            Error::Decode(err) => write!(f, "invalid message: {}", err),
+            Error::Disconnected => write!(f, "disconnected"),
+            Error::Timeout { waiting_for, after } => {
+                write!(f, "no {} after {:?}", waiting_for, after)
+            }
+            Error::Protocol(violation) => write!(f, "protocol violation: {}", violation),
+            Error::Bot(reason) => write!(f, "bot error: {:?}", reason),
+            Error::Desynced => write!(f, "replies of the bot are out of sync"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ProtocolViolation> for Error {
+    fn from(violation: ProtocolViolation) -> Self {
+        Error::Protocol(violation)
+    }
+}
+
+/// Returns the `type` of a frontend message.
+pub(crate) fn frontend_message_type(message: &FrontendMessage) -> &'static str {
+    match message {
+        FrontendMessage::Rules { .. } => "rules",
+        FrontendMessage::Start(_) => "start",
+        FrontendMessage::Play { .. } => "play",
+        FrontendMessage::NewPiece { .. } => "new_piece",
+        FrontendMessage::Suggest => "suggest",
+        FrontendMessage::Stop => "stop",
+        FrontendMessage::Quit => "quit",
+        FrontendMessage::Unknown => "unknown",
+    }
+}
+
+/// Returns the `type` of a bot message.
+pub(crate) fn bot_message_type(message: &BotMessage) -> &'static str {
+    match message {
+        BotMessage::Info { .. } => "info",
+        BotMessage::Ready => "ready",
+        BotMessage::Error { .. } => "error",
+        BotMessage::Suggestion { .. } => "suggestion",
+    }
+}
@@ -0,0 +1,451 @@
+//! The frontend side of the protocol.
+//!
+//! [`Host`] only lets the frontend send messages that are legal in the current state, and waits
+//! for the replies that the protocol requires. Bots may also answer other messages with an error;
+//! such an error is returned by the next call that waits for a reply.
+
+use std::time::Duration;
+
+use game::tetris::{tbp::*, PieceKind, PieceState};
+
+use crate::{
+    bot::{BotInfo, Suggestion},
+    error::bot_message_type,
+    transport::{FrameReader, FrameWriter},
+    Connection, Error, ProtocolState, ProtocolViolation,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostConfig {
+    pub info_timeout: Duration,
+    pub rules_timeout: Duration,
+    pub suggestion_timeout: Duration,
+    /// Prints every frame to stderr with this label.
+    pub log: Option<String>,
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        Self {
+            info_timeout: Duration::from_secs(10),
+            rules_timeout: Duration::from_secs(10),
+            suggestion_timeout: Duration::from_secs(5),
+            log: None,
+        }
+    }
+}
+
+pub struct Host {
+    reader: FrameReader,
+    writer: FrameWriter,
+    state: ProtocolState,
+    info: BotInfo,
+    config: HostConfig,
+    /// A suggestion that timed out and may still arrive.
+    late_suggestion: bool,
+    /// See [`Error::Desynced`].
+    desynced: bool,
+}
+
+impl Host {
+    /// Waits for the bot to introduce itself.
+    pub async fn connect(connection: Connection, config: HostConfig) -> Result<Self, Error> {
+        let (mut reader, writer) = connection.split();
+        let message = recv(&mut reader, &config, config.info_timeout, "info").await?;
+        let BotMessage::Info {
+            name,
+            version,
+            author,
+            features,
+        } = message
+        else {
+            return Err(unexpected(ProtocolState::AwaitingRules, &message));
+        };
+
+        Ok(Self {
+            reader,
+            writer,
+            state: ProtocolState::AwaitingRules,
+            info: BotInfo {
+                name,
+                version,
+                author,
+                features,
+            },
+            config,
+            late_suggestion: false,
+            desynced: false,
+        })
+    }
+
+    pub fn info(&self) -> &BotInfo {
+        &self.info
+    }
+
+    pub fn state(&self) -> ProtocolState {
+        self.state
+    }
+
+    /// Sends the rules and waits for the bot to accept them.
+    /// A rejection is returned as [`Error::Bot`], and other rules can be tried.
+    pub async fn rules(&mut self, randomizer: Option<String>) -> Result<(), Error> {
+        self.send(&FrontendMessage::Rules { randomizer }).await?;
+        self.state = ProtocolState::AwaitingRules;
+
+        let message = self.recv(self.config.rules_timeout, "ready").await?;
+        match message {
+            BotMessage::Ready => {
+                self.state = ProtocolState::Ready;
+                Ok(())
+            }
+            BotMessage::Error { reason } => Err(Error::Bot(reason)),
+            _ => Err(unexpected(self.state, &message)),
+        }
+    }
+
+    /// Starts a game, replacing the running one if any.
+    pub async fn start(&mut self, start: Start) -> Result<(), Error> {
+        self.expect_state(&[ProtocolState::Ready, ProtocolState::Running], "start")?;
+        self.send(&FrontendMessage::Start(start)).await?;
+        self.state = ProtocolState::Running;
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<(), Error> {
+        self.expect_state(&[ProtocolState::Running], "stop")?;
+        self.send(&FrontendMessage::Stop).await?;
+        self.state = ProtocolState::Ready;
+        Ok(())
+    }
+
+    /// Asks for a suggestion and waits for it.
+    ///
+    /// A suggestion that timed out is read and dropped first, so that replies stay in order.
+    ///
+    /// An [`Error::Bot`] may also be the answer to an earlier `start`, `play` or `new_piece`, in
+    /// which case the suggestion may still be on its way. As it cannot be told apart from the
+    /// next one, later calls fail with [`Error::Desynced`].
+    pub async fn suggest(&mut self) -> Result<Suggestion, Error> {
+        self.expect_state(&[ProtocolState::Running], "suggest")?;
+        if self.desynced {
+            return Err(Error::Desynced);
+        }
+        if self.late_suggestion {
+            self.recv_suggestion().await?;
+        }
+        self.send(&FrontendMessage::Suggest).await?;
+        self.late_suggestion = true;
+        self.recv_suggestion().await
+    }
+
+    /// Waits for the suggestion that is owed.
+    async fn recv_suggestion(&mut self) -> Result<Suggestion, Error> {
+        let message = self
+            .recv(self.config.suggestion_timeout, "suggestion")
+            .await?;
+        match message {
+            BotMessage::Suggestion { moves, move_info } => {
+                self.late_suggestion = false;
+                Ok(Suggestion { moves, move_info })
+            }
+            BotMessage::Error { reason } => {
+                self.desynced = true;
+                Err(Error::Bot(reason))
+            }
+            _ => Err(unexpected(self.state, &message)),
+        }
+    }
+
+    pub async fn play(&mut self, mv: PieceState) -> Result<(), Error> {
+        self.expect_state(&[ProtocolState::Running], "play")?;
+        self.send(&FrontendMessage::Play { mv }).await
+    }
+
+    pub async fn new_piece(&mut self, piece: PieceKind) -> Result<(), Error> {
+        self.expect_state(&[ProtocolState::Running], "new_piece")?;
+        self.send(&FrontendMessage::NewPiece { piece }).await
+    }
+
+    /// Tells the bot to exit and closes the connection.
+    pub async fn quit(mut self) -> Result<(), Error> {
+        self.send(&FrontendMessage::Quit).await?;
+        self.writer.close().await?;
+        Ok(())
+    }
+
+    /// Misuse by the frontend is reported as a protocol violation, and nothing is sent.
+    fn expect_state(
+        &self,
+        allowed: &[ProtocolState],
+        message: &'static str,
+    ) -> Result<(), ProtocolViolation> {
+        if allowed.contains(&self.state) {
+            Ok(())
+        } else {
+            Err(ProtocolViolation {
+                state: self.state,
+                message,
+            })
+        }
+    }
+
+    async fn send(&mut self, message: &FrontendMessage) -> Result<(), Error> {
+        let line = serde_json::to_string(message).map_err(Error::Decode)?;
+        if let Some(label) = &self.config.log {
+            eprintln!("[SEND/{}] {}", label, line);
+        }
+        self.writer.send_line(&line).await?;
+        Ok(())
+    }
+
+    async fn recv(
+        &mut self,
+        timeout: Duration,
+        waiting_for: &'static str,
+    ) -> Result<BotMessage, Error> {
+        recv(&mut self.reader, &self.config, timeout, waiting_for).await
+    }
+}
+
+async fn recv(
+    reader: &mut FrameReader,
+    config: &HostConfig,
+    timeout: Duration,
+    waiting_for: &'static str,
+) -> Result<BotMessage, Error> {
+    let line = tokio::time::timeout(timeout, reader.recv_line())
+        .await
+        .map_err(|_| Error::Timeout {
+            waiting_for,
+            after: timeout,
+        })??
+        .ok_or(Error::Disconnected)?;
+    if let Some(label) = &config.log {
+        eprintln!("[RECV/{}] {}", label, line);
+    }
+    serde_json::from_str(&line).map_err(Error::Decode)
+}
+
+fn unexpected(state: ProtocolState, message: &BotMessage) -> Error {
+    Error::Protocol(ProtocolViolation {
+        state,
+        message: bot_message_type(message),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::{run_bot, Bot};
+    use game::tetris::{ColoredBoard, PiecePosition, Rotation, SpinKind};
+    use tokio::io::duplex;
+
+    /// Suggests a T at the bottom left, and rejects the second played move.
+    #[derive(Default)]
+    struct Stub {
+        played: usize,
+        pieces: usize,
+        suggested: usize,
+        /// Delay of the first suggestion.
+        delay: Option<Duration>,
+    }
+
+    impl Bot for Stub {
+        fn info(&self) -> BotInfo {
+            BotInfo {
+                name: "stub".to_owned(),
+                version: "0".to_owned(),
+                author: "".to_owned(),
+                features: vec![],
+            }
+        }
+
+        fn rules(&mut self, randomizer: Option<&str>) -> Result<(), BotErrorReason> {
+            match randomizer {
+                Some("seven_bag") => Ok(()),
+                _ => Err(BotErrorReason::UnsupportedRules),
+            }
+        }
+
+        fn start(&mut self, _: Start) -> Result<(), BotErrorReason> {
+            Ok(())
+        }
+
+        fn stop(&mut self) {}
+
+        fn suggest(&mut self) -> Result<Suggestion, BotErrorReason> {
+            if let Some(delay) = self.delay.take() {
+                std::thread::sleep(delay);
+            }
+            self.suggested += 1;
+            Ok(Suggestion {
+                moves: vec![t_piece()],
+                move_info: MoveInfo {
+                    nodes: self.suggested as u64,
+                    nps: 1.0,
+                    extra: "".to_owned(),
+                },
+            })
+        }
+
+        fn play(&mut self, _: PieceState) -> Result<(), BotErrorReason> {
+            self.played += 1;
+            if self.played == 2 {
+                return Err(BotErrorReason::InvalidMove);
+            }
+            Ok(())
+        }
+
+        fn new_piece(&mut self, _: PieceKind) -> Result<(), BotErrorReason> {
+            self.pieces += 1;
+            Ok(())
+        }
+    }
+
+    fn t_piece() -> PieceState {
+        PieceState {
+            pos: PiecePosition {
+                kind: PieceKind::T,
+                x: 1,
+                y: 0,
+                rot: Rotation::North,
+            },
+            spin: SpinKind::None,
+        }
+    }
+
+    fn start() -> Start {
+        Start {
+            board: ColoredBoard::default(),
+            queue: vec![PieceKind::T],
+            hold: None,
+            combo: 0,
+            back_to_back: false,
+            randomizer: Randomizer::Unknown,
+        }
+    }
+
+    /// Connects a host to a [`Stub`] bot through an in-memory pipe.
+    fn pipe() -> (Connection, Connection) {
+        let (host_end, bot_end) = duplex(4096);
+        let (host_read, host_write) = tokio::io::split(host_end);
+        let (bot_read, bot_write) = tokio::io::split(bot_end);
+        (
+            Connection::lines(host_read, host_write),
+            Connection::lines(bot_read, bot_write),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_session() {
+        let (host_end, bot_end) = pipe();
+        let bot = tokio::spawn(async move {
+            let mut stub = Stub::default();
+            // the last suggestion may find the host gone
+            let _ = run_bot(bot_end, &mut stub).await;
+            stub
+        });
+
+        let mut host = Host::connect(host_end, HostConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(host.info().name, "stub");
+
+        // misuse is caught before anything is sent
+        assert!(matches!(
+            host.suggest().await,
+            Err(Error::Protocol(ProtocolViolation {
+                state: ProtocolState::AwaitingRules,
+                message: "suggest"
+            }))
+        ));
+
+        assert!(matches!(
+            host.rules(Some("classic".to_owned())).await,
+            Err(Error::Bot(BotErrorReason::UnsupportedRules))
+        ));
+        host.rules(Some("seven_bag".to_owned())).await.unwrap();
+        host.start(start()).await.unwrap();
+
+        let suggestion = host.suggest().await.unwrap();
+        assert_eq!(suggestion.moves, [t_piece()]);
+        host.play(t_piece()).await.unwrap();
+        host.new_piece(PieceKind::I).await.unwrap();
+        host.stop().await.unwrap();
+        assert_eq!(host.state(), ProtocolState::Ready);
+
+        // the error for the second play arrives with the next reply
+        host.start(start()).await.unwrap();
+        host.play(t_piece()).await.unwrap();
+        assert!(matches!(
+            host.suggest().await,
+            Err(Error::Bot(BotErrorReason::InvalidMove))
+        ));
+        // the suggestion behind the error would be taken for the next one
+        assert!(matches!(host.suggest().await, Err(Error::Desynced)));
+        host.quit().await.unwrap();
+
+        let stub = bot.await.unwrap();
+        assert_eq!((stub.played, stub.pieces, stub.suggested), (2, 1, 2));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_late_suggestion() {
+        let (host_end, bot_end) = pipe();
+        let bot = tokio::spawn(async move {
+            let mut stub = Stub {
+                delay: Some(Duration::from_millis(200)),
+                ..Default::default()
+            };
+            let _ = run_bot(bot_end, &mut stub).await;
+            stub
+        });
+
+        let config = HostConfig {
+            suggestion_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mut host = Host::connect(host_end, config).await.unwrap();
+        host.rules(Some("seven_bag".to_owned())).await.unwrap();
+        host.start(start()).await.unwrap();
+        assert!(matches!(
+            host.suggest().await,
+            Err(Error::Timeout {
+                waiting_for: "suggestion",
+                ..
+            })
+        ));
+
+        // the late suggestion is dropped, and the next one answers
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let suggestion = host.suggest().await.unwrap();
+        assert_eq!(suggestion.move_info.nodes, 2);
+        host.quit().await.unwrap();
+
+        assert_eq!(bot.await.unwrap().suggested, 2);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_disconnection() {
+        let (host_end, bot_end) = pipe();
+        let config = HostConfig {
+            info_timeout: Duration::from_millis(10),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Host::connect(host_end, config).await,
+            Err(Error::Timeout {
+                waiting_for: "info",
+                ..
+            })
+        ));
+
+        drop(bot_end);
+        let (host_end, bot_end) = pipe();
+        drop(bot_end);
+        assert!(matches!(
+            Host::connect(host_end, HostConfig::default()).await,
+            Err(Error::Disconnected)
+        ));
+    }
+}
@@ -0,0 +1,15 @@
+//! Asynchronous Tetris Bot Protocol for the messages of [`game::tetris::tbp`].
+//!
+//! [`transport`] frames messages over stdio, child processes, TCP and WebSocket. On top of it,
+//! [`host`] and [`bot`] implement the two sides of the protocol, so that frontends and bots do not
+//! have to track the order of messages themselves.
+
+pub mod bot;
+mod error;
+pub mod host;
+pub mod transport;
+
+pub use bot::{run_bot, Bot, BotInfo, BotProtocol, Suggestion};
+pub use error::{Error, ProtocolState, ProtocolViolation};
+pub use host::{Host, HostConfig};
+pub use transport::{Connection, Endpoint, Incoming, Listener};
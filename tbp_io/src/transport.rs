@@ -0,0 +1,380 @@
+//! Framed TBP connections.
+//!
+//! Every transport carries one JSON message per frame: a line for stdio, child processes and TCP,
+//! and a text message for WebSocket, which browser frontends use.
+
+use std::{fmt, io, str::FromStr};
+
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines},
+    net::{TcpListener, TcpStream},
+};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+use crate::Error;
+
+/// Where a bot is served.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Line-delimited JSON over TCP, written as `tcp://host:port`.
+    Tcp(String),
+    /// JSON text messages over WebSocket, written as `ws://host:port/path`.
+    WebSocket(String),
+}
+
+impl Endpoint {
+    /// Returns the `host:port` part.
+    pub fn address(&self) -> &str {
+        match self {
+            Endpoint::Tcp(address) => address,
+            Endpoint::WebSocket(url) => {
+                let rest = url.strip_prefix("ws://").unwrap_or(url);
+                rest.split('/').next().unwrap_or(rest)
+            }
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = EndpointError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix("tcp://") {
+            if !address.is_empty() {
+                return Ok(Endpoint::Tcp(address.to_owned()));
+            }
+        } else if s.len() > "ws://".len() && s.starts_with("ws://") {
+            return Ok(Endpoint::WebSocket(s.to_owned()));
+        }
+        Err(EndpointError(s.to_owned()))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "tcp://{}", address),
+            Endpoint::WebSocket(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointError(String);
+
+impl fmt::Display for EndpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid endpoint {:?}, expected tcp://host:port or ws://host:port",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for EndpointError {}
+
+type BoxedRead = Box<dyn AsyncRead + Send + Unpin>;
+type BoxedWrite = Box<dyn AsyncWrite + Send + Unpin>;
+
+enum ReaderInner {
+    Lines(Lines<BufReader<BoxedRead>>),
+    WebSocket(SplitStream<WebSocketStream<TcpStream>>),
+}
+
+enum WriterInner {
+    Lines(BoxedWrite),
+    WebSocket(SplitSink<WebSocketStream<TcpStream>, Message>),
+}
+
+/// The receiving half of a connection.
+pub struct FrameReader(ReaderInner);
+
+/// The sending half of a connection.
+pub struct FrameWriter(WriterInner);
+
+/// A bidirectional connection carrying one message per frame.
+pub struct Connection {
+    reader: FrameReader,
+    writer: FrameWriter,
+}
+
+impl FrameReader {
+    /// Returns the next frame, or `None` once the peer has closed the connection.
+    pub async fn recv_line(&mut self) -> io::Result<Option<String>> {
+        match &mut self.0 {
+            ReaderInner::Lines(lines) => lines.next_line().await,
+            ReaderInner::WebSocket(stream) => loop {
+                let Some(message) = stream.next().await else {
+                    return Ok(None);
+                };
+                match message.map_err(io::Error::other)? {
+                    Message::Text(text) => return Ok(Some(text)),
+                    Message::Binary(data) => {
+                        return String::from_utf8(data)
+                            .map(Some)
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+                    }
+                    Message::Close(_) => return Ok(None),
+                    // pings are answered by the stream itself
+                    Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+                }
+            },
+        }
+    }
+
+    /// Returns the next message, or `None` once the peer has closed the connection.
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, Error> {
+        match self.recv_line().await? {
+            Some(line) => serde_json::from_str(&line).map(Some).map_err(Error::Decode),
+            None => Ok(None),
+        }
+    }
+}
+
+impl FrameWriter {
+    pub async fn send_line(&mut self, line: &str) -> io::Result<()> {
+        debug_assert!(!line.contains('\n'));
+        match &mut self.0 {
+            WriterInner::Lines(writer) => {
+                writer.write_all(line.as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await
+            }
+            WriterInner::WebSocket(sink) => sink
+                .send(Message::Text(line.to_owned()))
+                .await
+                .map_err(io::Error::other),
+        }
+    }
+
+    pub async fn send<T: Serialize>(&mut self, message: &T) -> io::Result<()> {
+        let line = serde_json::to_string(message)?;
+        self.send_line(&line).await
+    }
+
+    /// Closes the connection. A WebSocket peer receives a close frame.
+    pub async fn close(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            WriterInner::Lines(writer) => writer.shutdown().await,
+            WriterInner::WebSocket(sink) => sink.close().await.map_err(io::Error::other),
+        }
+    }
+}
+
+impl Connection {
+    /// Creates a connection exchanging lines, such as the pipes of a child process.
+    pub fn lines(
+        reader: impl AsyncRead + Send + Unpin + 'static,
+        writer: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Self {
+        let reader: BoxedRead = Box::new(reader);
+        Self {
+            reader: FrameReader(ReaderInner::Lines(BufReader::new(reader).lines())),
+            writer: FrameWriter(WriterInner::Lines(Box::new(writer))),
+        }
+    }
+
+    pub fn stdio() -> Self {
+        Self::lines(tokio::io::stdin(), tokio::io::stdout())
+    }
+
+    pub fn tcp(stream: TcpStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self::lines(reader, writer)
+    }
+
+    pub fn websocket(stream: WebSocketStream<TcpStream>) -> Self {
+        let (sink, stream) = stream.split();
+        Self {
+            reader: FrameReader(ReaderInner::WebSocket(stream)),
+            writer: FrameWriter(WriterInner::WebSocket(sink)),
+        }
+    }
+
+    /// Connects to a bot served at `endpoint`.
+    pub async fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+        let stream = TcpStream::connect(endpoint.address()).await?;
+        stream.set_nodelay(true)?;
+        match endpoint {
+            Endpoint::Tcp(_) => Ok(Self::tcp(stream)),
+            Endpoint::WebSocket(url) => {
+                let (stream, _) = tokio_tungstenite::client_async(url.as_str(), stream)
+                    .await
+                    .map_err(io::Error::other)?;
+                Ok(Self::websocket(stream))
+            }
+        }
+    }
+
+    /// Splits the connection, so that messages can be sent while waiting for one.
+    pub fn split(self) -> (FrameReader, FrameWriter) {
+        (self.reader, self.writer)
+    }
+
+    pub async fn recv_line(&mut self) -> io::Result<Option<String>> {
+        self.reader.recv_line().await
+    }
+
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, Error> {
+        self.reader.recv().await
+    }
+
+    pub async fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.writer.send_line(line).await
+    }
+
+    pub async fn send<T: Serialize>(&mut self, message: &T) -> io::Result<()> {
+        self.writer.send(message).await
+    }
+
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.writer.close().await
+    }
+}
+
+/// Accepts connections of frontends to a bot.
+pub struct Listener {
+    listener: TcpListener,
+    websocket: bool,
+}
+
+impl Listener {
+    pub async fn bind(endpoint: &Endpoint) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(endpoint.address()).await?,
+            websocket: matches!(endpoint, Endpoint::WebSocket(_)),
+        })
+    }
+
+    /// Returns the bound endpoint, with the actual port when bound to port 0.
+    pub fn local_endpoint(&self) -> io::Result<Endpoint> {
+        let address = self.listener.local_addr()?.to_string();
+        Ok(if self.websocket {
+            Endpoint::WebSocket(format!("ws://{}", address))
+        } else {
+            Endpoint::Tcp(address)
+        })
+    }
+
+    /// Accepts a frontend without completing the WebSocket handshake, which the frontend may
+    /// never finish. Run [`Incoming::handshake`] apart from the accept loop.
+    pub async fn accept(&self) -> io::Result<Incoming> {
+        let (stream, _) = self.listener.accept().await?;
+        stream.set_nodelay(true)?;
+        Ok(Incoming {
+            stream,
+            websocket: self.websocket,
+        })
+    }
+}
+
+/// A frontend accepted by a [`Listener`], before the handshake.
+pub struct Incoming {
+    stream: TcpStream,
+    websocket: bool,
+}
+
+impl Incoming {
+    /// Completes the WebSocket handshake if there is one.
+    pub async fn handshake(self) -> io::Result<Connection> {
+        if self.websocket {
+            let stream = tokio_tungstenite::accept_async(self.stream)
+                .await
+                .map_err(io::Error::other)?;
+            Ok(Connection::websocket(stream))
+        } else {
+            Ok(Connection::tcp(self.stream))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::tetris::tbp::{BotMessage, FrontendMessage};
+
+    #[test]
+    fn test_endpoint() {
+        let tcp = "tcp://127.0.0.1:9000".parse::<Endpoint>().unwrap();
+        assert_eq!(tcp, Endpoint::Tcp("127.0.0.1:9000".to_owned()));
+        assert_eq!(tcp.to_string(), "tcp://127.0.0.1:9000");
+
+        let ws = "ws://localhost:9000/tbp".parse::<Endpoint>().unwrap();
+        assert_eq!(ws.address(), "localhost:9000");
+        assert_eq!(ws.to_string(), "ws://localhost:9000/tbp");
+
+        assert!("127.0.0.1:9000".parse::<Endpoint>().is_err());
+        assert!("tcp://".parse::<Endpoint>().is_err());
+    }
+
+    async fn roundtrip(endpoint: Endpoint) {
+        let listener = Listener::bind(&endpoint).await.unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+
+        let bot = tokio::spawn(async move {
+            let mut connection = listener.accept().await.unwrap().handshake().await.unwrap();
+            connection.send(&BotMessage::Ready).await.unwrap();
+            assert!(matches!(
+                connection.recv().await.unwrap(),
+                Some(FrontendMessage::Suggest)
+            ));
+            assert!(matches!(
+                connection.recv::<FrontendMessage>().await,
+                Err(Error::Decode(_))
+            ));
+            assert_eq!(connection.recv_line().await.unwrap().as_deref(), Some("{}"));
+            assert!(connection.recv_line().await.unwrap().is_none());
+        });
+
+        let mut frontend = Connection::connect(&endpoint).await.unwrap();
+        assert!(matches!(
+            frontend.recv().await.unwrap(),
+            Some(BotMessage::Ready)
+        ));
+        frontend.send(&FrontendMessage::Suggest).await.unwrap();
+        frontend.send_line("not json").await.unwrap();
+        frontend.send_line("{}").await.unwrap();
+        frontend.close().await.unwrap();
+        bot.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tcp() {
+        roundtrip(Endpoint::Tcp("127.0.0.1:0".to_owned())).await;
+    }
+
+    #[tokio::test]
+    async fn test_websocket() {
+        roundtrip(Endpoint::WebSocket("ws://127.0.0.1:0".to_owned())).await;
+    }
+
+    #[tokio::test]
+    async fn test_stalled_handshake() {
+        let listener = Listener::bind(&Endpoint::WebSocket("ws://127.0.0.1:0".to_owned()))
+            .await
+            .unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+
+        // connects but never sends the handshake
+        let _stalled = TcpStream::connect(endpoint.address()).await.unwrap();
+        let stalled = listener.accept().await.unwrap();
+        let stalled = tokio::spawn(stalled.handshake());
+
+        let frontend = tokio::spawn(async move { Connection::connect(&endpoint).await });
+        let mut bot = listener.accept().await.unwrap().handshake().await.unwrap();
+        let mut frontend = frontend.await.unwrap().unwrap();
+        bot.send(&BotMessage::Ready).await.unwrap();
+        assert!(matches!(
+            frontend.recv().await.unwrap(),
+            Some(BotMessage::Ready)
+        ));
+        assert!(!stalled.is_finished());
+        stalled.abort();
+    }
+}